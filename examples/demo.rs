@@ -23,7 +23,7 @@ fn main() -> pdb::Result<()> {
     println!("Listing all visible windows:\n");
     let windows = controller.list_windows()?;
     
-    println!("{:<20} {:<50} {}", "HWND", "Title", "Class");
+    println!("{:<20} {:<50} Class", "HWND", "Title");
     println!("{}", "-".repeat(90));
     
     for window in &windows {
@@ -0,0 +1,69 @@
+//! Pre-shared key authentication
+//!
+//! A server configured with keys answers `Hello` with a random challenge
+//! instead of its capabilities. The client proves it holds one of the keys
+//! by returning the HMAC-SHA256 of the challenge under that key; only then
+//! does the server send its capabilities and accept commands. The key itself
+//! never crosses the wire, and a fresh challenge per connection stops
+//! recorded proofs from being replayed.
+
+use crate::error::{PdbError, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Length of a challenge in bytes
+pub const CHALLENGE_LEN: usize = 32;
+
+/// Prefix of the signed message, so proofs cannot be reused elsewhere
+const CONTEXT: &[u8] = b"pdb-auth-v1";
+
+/// Fixed HMAC key for comparing presented keys
+const BEARER_CONTEXT: &[u8] = b"pdb-bearer-v1";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Generate a random challenge
+pub fn challenge() -> Result<Vec<u8>> {
+    let mut challenge = vec![0u8; CHALLENGE_LEN];
+    getrandom::getrandom(&mut challenge)
+        .map_err(|e| PdbError::AuthenticationFailed(format!("no randomness: {}", e)))?;
+    Ok(challenge)
+}
+
+/// Proof that the sender holds `key`, for a given challenge
+pub fn sign(key: &[u8], challenge: &[u8]) -> Vec<u8> {
+    mac(key, challenge).finalize().into_bytes().to_vec()
+}
+
+/// Check a proof against every accepted key, in constant time per key
+pub fn verify(keys: &[Vec<u8>], challenge: &[u8], proof: &[u8]) -> bool {
+    find_key(keys, challenge, proof).is_some()
+}
+
+/// Index of the key a proof was made with, if it is one of `keys`
+pub fn find_key(keys: &[Vec<u8>], challenge: &[u8], proof: &[u8]) -> Option<usize> {
+    keys.iter()
+        .position(|key| mac(key, challenge).verify_slice(proof).is_ok())
+}
+
+/// Check a key presented as-is (e.g. an HTTP bearer token) against every
+/// accepted key, without leaking how much of it matched
+pub fn verify_key(keys: &[Vec<u8>], presented: &[u8]) -> bool {
+    find_presented_key(keys, presented).is_some()
+}
+
+/// Index of a key presented as-is in `keys`, if it is one of them
+pub fn find_presented_key(keys: &[Vec<u8>], presented: &[u8]) -> Option<usize> {
+    // Compare digests, so the comparison takes the same time for any input
+    let digest = sign(BEARER_CONTEXT, presented);
+    keys.iter()
+        .position(|key| mac(BEARER_CONTEXT, key).verify_slice(&digest).is_ok())
+}
+
+fn mac(key: &[u8], challenge: &[u8]) -> HmacSha256 {
+    // HMAC accepts keys of any length
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC key of any length");
+    mac.update(CONTEXT);
+    mac.update(challenge);
+    mac
+}
@@ -0,0 +1,377 @@
+//! In-memory mock backend
+//!
+//! Serves a scripted set of fake windows and records every input call into an
+//! event log, so automation code and the server can be tested without a real
+//! desktop.
+//!
+//! ```rust
+//! use pdb::backend::mock::{MockBackend, MockEvent, MockWindow};
+//! use pdb::{Rect, WindowController};
+//! use std::sync::Arc;
+//!
+//! let backend = Arc::new(MockBackend::new());
+//! backend.add_window(MockWindow::new(0x100, "Notepad", "Edit", Rect::new(10, 10, 110, 60)));
+//!
+//! let controller = WindowController::with_backend(backend.clone());
+//! let device = controller.device(controller.find_window("Notepad")?);
+//! device.click(5, 5)?;
+//!
+//! assert_eq!(backend.events(), vec![MockEvent::Click { hwnd: 0x100, x: 5, y: 5 }]);
+//! # Ok::<(), pdb::PdbError>(())
+//! ```
+
+use super::{Backend, CaptureBackend, InputBackend, WindowBackend};
+use crate::error::{PdbError, Result};
+use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
+use std::collections::VecDeque;
+use std::sync::{Mutex, MutexGuard};
+
+/// Input event recorded by the mock backend
+///
+/// Coordinates are relative to the client area of `hwnd`, the window that had
+/// focus when the input was sent (0 if none).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockEvent {
+    /// Mouse click
+    Click { hwnd: usize, x: i32, y: i32 },
+    /// Mouse swipe
+    Swipe {
+        hwnd: usize,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        duration_ms: u32,
+    },
+    /// Text input
+    InputText { hwnd: usize, text: String },
+    /// Key press
+    KeyEvent { hwnd: usize, key: KeyCode },
+}
+
+/// A scripted fake window
+#[derive(Debug, Clone)]
+pub struct MockWindow {
+    info: WindowInfo,
+    minimized: bool,
+    /// Frames served by `capture_window`; the last one is repeated forever
+    frames: VecDeque<Screenshot>,
+}
+
+impl MockWindow {
+    /// Create a visible window filled with opaque black
+    pub fn new(hwnd: usize, title: &str, class_name: &str, rect: Rect) -> Self {
+        let window = Self {
+            info: WindowInfo {
+                hwnd,
+                title: title.to_string(),
+                class_name: class_name.to_string(),
+                rect,
+                visible: true,
+            },
+            minimized: false,
+            frames: VecDeque::new(),
+        };
+        window.with_color([0, 0, 0, 255])
+    }
+
+    /// Fill the framebuffer with a solid RGBA color
+    pub fn with_color(self, rgba: [u8; 4]) -> Self {
+        let width = self.info.rect.width().max(0) as u32;
+        let height = self.info.rect.height().max(0) as u32;
+        self.with_frame(solid_frame(width, height, rgba))
+    }
+
+    /// Replace the framebuffer
+    pub fn with_frame(mut self, frame: Screenshot) -> Self {
+        self.frames.clear();
+        self.frames.push_back(frame);
+        self
+    }
+
+    /// Start the window minimized
+    pub fn minimized(mut self) -> Self {
+        self.minimized = true;
+        self
+    }
+
+    /// Window info
+    pub fn info(&self) -> &WindowInfo {
+        &self.info
+    }
+}
+
+/// Create a screenshot filled with a single RGBA color
+pub fn solid_frame(width: u32, height: u32, rgba: [u8; 4]) -> Screenshot {
+    Screenshot {
+        width,
+        height,
+        data: rgba.repeat(width as usize * height as usize),
+    }
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    windows: Vec<MockWindow>,
+    focused: usize,
+    cursor: (i32, i32),
+    events: Vec<MockEvent>,
+    /// Explicit screen size; defaults to the bounding box of all windows
+    screen_size: Option<(i32, i32)>,
+    /// Whether swipes take their full duration
+    realtime: bool,
+}
+
+impl MockState {
+    fn window(&self, hwnd: usize) -> Result<&MockWindow> {
+        self.windows
+            .iter()
+            .find(|w| w.info.hwnd == hwnd)
+            .ok_or_else(|| PdbError::WindowNotFound(format!("0x{:X}", hwnd)))
+    }
+
+    fn window_mut(&mut self, hwnd: usize) -> Result<&mut MockWindow> {
+        self.windows
+            .iter_mut()
+            .find(|w| w.info.hwnd == hwnd)
+            .ok_or_else(|| PdbError::WindowNotFound(format!("0x{:X}", hwnd)))
+    }
+
+    /// Convert screen coordinates to the focused window's client coordinates
+    fn to_focused_client(&self, x: i32, y: i32) -> (i32, i32) {
+        match self.window(self.focused) {
+            Ok(w) => (x - w.info.rect.left, y - w.info.rect.top),
+            Err(_) => (x, y),
+        }
+    }
+}
+
+/// In-memory backend with scripted windows and a recorded event log
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    state: Mutex<MockState>,
+}
+
+impl MockBackend {
+    /// Create an empty mock desktop
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a mock desktop with the given windows
+    pub fn with_windows(windows: impl IntoIterator<Item = MockWindow>) -> Self {
+        let backend = Self::new();
+        for window in windows {
+            backend.add_window(window);
+        }
+        backend
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Add a window, replacing any existing window with the same hwnd
+    pub fn add_window(&self, window: MockWindow) {
+        let mut state = self.state();
+        state.windows.retain(|w| w.info.hwnd != window.info.hwnd);
+        state.windows.push(window);
+    }
+
+    /// Remove a window (e.g. to simulate it being closed)
+    pub fn remove_window(&self, hwnd: usize) -> Option<MockWindow> {
+        let mut state = self.state();
+        let index = state.windows.iter().position(|w| w.info.hwnd == hwnd)?;
+        Some(state.windows.remove(index))
+    }
+
+    /// Replace the framebuffer served for a window
+    pub fn set_frame(&self, hwnd: usize, frame: Screenshot) -> Result<()> {
+        let mut state = self.state();
+        let window = state.window_mut(hwnd)?;
+        window.frames.clear();
+        window.frames.push_back(frame);
+        Ok(())
+    }
+
+    /// Queue a frame to be served after the current ones
+    ///
+    /// Each capture consumes one queued frame; the last frame is repeated.
+    pub fn push_frame(&self, hwnd: usize, frame: Screenshot) -> Result<()> {
+        self.state().window_mut(hwnd)?.frames.push_back(frame);
+        Ok(())
+    }
+
+    /// Set the reported screen size
+    pub fn set_screen_size(&self, width: i32, height: i32) {
+        self.state().screen_size = Some((width, height));
+    }
+
+    /// Make swipes take their full duration, like real input
+    pub fn set_realtime(&self, realtime: bool) {
+        self.state().realtime = realtime;
+    }
+
+    /// Currently focused window (0 if none)
+    pub fn focused(&self) -> usize {
+        self.state().focused
+    }
+
+    /// Snapshot of the recorded events
+    pub fn events(&self) -> Vec<MockEvent> {
+        self.state().events.clone()
+    }
+
+    /// Take and clear the recorded events
+    pub fn take_events(&self) -> Vec<MockEvent> {
+        std::mem::take(&mut self.state().events)
+    }
+
+    /// Clear the recorded events
+    pub fn clear_events(&self) {
+        self.state().events.clear();
+    }
+}
+
+impl WindowBackend for MockBackend {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        Ok(self
+            .state()
+            .windows
+            .iter()
+            .filter(|w| w.info.visible && !w.info.title.is_empty())
+            .map(|w| w.info.clone())
+            .collect())
+    }
+
+    fn window_info(&self, hwnd: usize) -> Result<WindowInfo> {
+        Ok(self.state().window(hwnd)?.info.clone())
+    }
+
+    fn is_minimized(&self, hwnd: usize) -> bool {
+        self.state().window(hwnd).map(|w| w.minimized).unwrap_or(false)
+    }
+
+    fn restore(&self, hwnd: usize) -> Result<()> {
+        self.state().window_mut(hwnd)?.minimized = false;
+        Ok(())
+    }
+
+    fn minimize(&self, hwnd: usize) -> Result<()> {
+        self.state().window_mut(hwnd)?.minimized = true;
+        Ok(())
+    }
+
+    fn focus(&self, hwnd: usize) -> Result<()> {
+        let mut state = self.state();
+        state.window(hwnd)?;
+        state.focused = hwnd;
+        Ok(())
+    }
+
+    fn client_size(&self, hwnd: usize) -> Result<(i32, i32)> {
+        let state = self.state();
+        let rect = state.window(hwnd)?.info.rect;
+        Ok((rect.width(), rect.height()))
+    }
+
+    fn window_rect(&self, hwnd: usize) -> Result<Rect> {
+        Ok(self.state().window(hwnd)?.info.rect)
+    }
+
+    fn client_to_screen(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        let state = self.state();
+        let rect = state.window(hwnd)?.info.rect;
+        Ok((x + rect.left, y + rect.top))
+    }
+
+    fn screen_to_client(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        let state = self.state();
+        let rect = state.window(hwnd)?.info.rect;
+        Ok((x - rect.left, y - rect.top))
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32)> {
+        let state = self.state();
+        Ok(state.screen_size.unwrap_or_else(|| {
+            state.windows.iter().fold((0, 0), |(w, h), window| {
+                (w.max(window.info.rect.right), h.max(window.info.rect.bottom))
+            })
+        }))
+    }
+}
+
+impl InputBackend for MockBackend {
+    fn mouse_click(&self, x: i32, y: i32) -> Result<()> {
+        let mut state = self.state();
+        let hwnd = state.focused;
+        state.cursor = (x, y);
+        let (x, y) = state.to_focused_client(x, y);
+        state.events.push(MockEvent::Click { hwnd, x, y });
+        Ok(())
+    }
+
+    fn mouse_swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Result<()> {
+        if self.state().realtime {
+            std::thread::sleep(std::time::Duration::from_millis(duration_ms as u64));
+        }
+        let mut state = self.state();
+        let hwnd = state.focused;
+        state.cursor = (x2, y2);
+        let (x1, y1) = state.to_focused_client(x1, y1);
+        let (x2, y2) = state.to_focused_client(x2, y2);
+        state.events.push(MockEvent::Swipe {
+            hwnd,
+            x1,
+            y1,
+            x2,
+            y2,
+            duration_ms,
+        });
+        Ok(())
+    }
+
+    fn key_event(&self, key: KeyCode) -> Result<()> {
+        let mut state = self.state();
+        let hwnd = state.focused;
+        state.events.push(MockEvent::KeyEvent { hwnd, key });
+        Ok(())
+    }
+
+    fn input_text(&self, text: &str) -> Result<()> {
+        let mut state = self.state();
+        let hwnd = state.focused;
+        state.events.push(MockEvent::InputText {
+            hwnd,
+            text: text.to_string(),
+        });
+        Ok(())
+    }
+
+    fn cursor_pos(&self) -> Result<(i32, i32)> {
+        Ok(self.state().cursor)
+    }
+}
+
+impl CaptureBackend for MockBackend {
+    fn capture_window(&self, hwnd: usize) -> Result<Screenshot> {
+        let mut state = self.state();
+        let window = state.window_mut(hwnd)?;
+        let frame = if window.frames.len() > 1 {
+            window.frames.pop_front()
+        } else {
+            window.frames.front().cloned()
+        };
+        frame.ok_or_else(|| PdbError::CaptureError("No frame configured".into()))
+    }
+
+    fn capture_window_client(&self, hwnd: usize) -> Result<Screenshot> {
+        self.capture_window(hwnd)
+    }
+}
+
+impl Backend for MockBackend {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+}
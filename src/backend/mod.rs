@@ -0,0 +1,261 @@
+//! Platform backends
+//!
+//! `WindowController` and `Device` are thin wrappers over these traits.
+//! Each platform provides one implementation; the Win32 backend is compiled
+//! only on Windows and the X11 backend only on Unix with the `x11` feature,
+//! so the protocol, client and server build everywhere.
+
+use crate::error::{PdbError, Result};
+use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
+use std::fmt::Debug;
+use std::sync::Arc;
+
+pub mod mock;
+#[cfg(windows)]
+pub mod win32;
+#[cfg(feature = "vnc")]
+pub mod vnc;
+#[cfg(all(unix, feature = "x11"))]
+pub mod x11;
+
+/// Window enumeration and management
+pub trait WindowBackend: Send + Sync {
+    /// List all visible top-level windows
+    fn list_windows(&self) -> Result<Vec<WindowInfo>>;
+
+    /// Get window info by handle
+    fn window_info(&self, hwnd: usize) -> Result<WindowInfo>;
+
+    /// Check if window is minimized
+    fn is_minimized(&self, hwnd: usize) -> bool;
+
+    /// Restore a minimized window without activating it
+    fn restore(&self, hwnd: usize) -> Result<()>;
+
+    /// Minimize window
+    fn minimize(&self, hwnd: usize) -> Result<()>;
+
+    /// Bring window to foreground
+    fn focus(&self, hwnd: usize) -> Result<()>;
+
+    /// Get client area size
+    fn client_size(&self, hwnd: usize) -> Result<(i32, i32)>;
+
+    /// Get window rectangle in screen coordinates
+    fn window_rect(&self, hwnd: usize) -> Result<Rect>;
+
+    /// Convert client coordinates to screen coordinates
+    fn client_to_screen(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)>;
+
+    /// Convert screen coordinates to client coordinates
+    fn screen_to_client(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)>;
+
+    /// Get the size of the (primary) screen
+    fn screen_size(&self) -> Result<(i32, i32)>;
+}
+
+/// Input injection (all coordinates are screen coordinates)
+pub trait InputBackend: Send + Sync {
+    /// Send mouse click
+    fn mouse_click(&self, x: i32, y: i32) -> Result<()>;
+
+    /// Send mouse swipe from (x1, y1) to (x2, y2) over duration_ms milliseconds
+    fn mouse_swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Result<()>;
+
+    /// Send key press and release
+    fn key_event(&self, key: KeyCode) -> Result<()>;
+
+    /// Type unicode text
+    fn input_text(&self, text: &str) -> Result<()>;
+
+    /// Get current cursor position
+    fn cursor_pos(&self) -> Result<(i32, i32)>;
+}
+
+/// Screen capture
+pub trait CaptureBackend: Send + Sync {
+    /// Capture the whole window
+    fn capture_window(&self, hwnd: usize) -> Result<Screenshot>;
+
+    /// Capture the window client area
+    fn capture_window_client(&self, hwnd: usize) -> Result<Screenshot>;
+}
+
+/// A complete platform backend
+pub trait Backend: WindowBackend + InputBackend + CaptureBackend + Debug {
+    /// Backend name (e.g. "win32")
+    fn name(&self) -> &'static str;
+}
+
+/// Get the default backend for the current platform
+pub fn default_backend() -> Arc<dyn Backend> {
+    #[cfg(windows)]
+    {
+        Arc::new(win32::Win32Backend::new())
+    }
+    #[cfg(all(unix, feature = "x11"))]
+    {
+        if std::env::var_os("DISPLAY").is_some() {
+            match x11::X11Backend::connect(None) {
+                Ok(backend) => return Arc::new(backend),
+                Err(e) => log::warn!("X11 backend unavailable: {}", e),
+            }
+        }
+        Arc::new(UnsupportedBackend)
+    }
+    #[cfg(not(any(windows, all(unix, feature = "x11"))))]
+    {
+        Arc::new(UnsupportedBackend)
+    }
+}
+
+/// Intermediate pointer positions for a swipe, with the delay between them
+///
+/// Uses the same step count and quadratic ease-out as the Win32 backend so
+/// swipes feel the same on every platform.
+#[cfg_attr(not(any(feature = "vnc", all(unix, feature = "x11"))), allow(dead_code))]
+pub(crate) fn swipe_path(
+    x1: i32,
+    y1: i32,
+    x2: i32,
+    y2: i32,
+    duration_ms: u32,
+) -> (Vec<(i32, i32)>, std::time::Duration) {
+    let steps = 50u32.max(duration_ms / 10);
+    let step_delay = std::time::Duration::from_millis((duration_ms / steps).max(5) as u64);
+
+    let points = (1..=steps)
+        .map(|i| {
+            let t = i as f64 / steps as f64;
+            let eased = 1.0 - (1.0 - t) * (1.0 - t);
+            (
+                x1 + ((x2 - x1) as f64 * eased) as i32,
+                y1 + ((y2 - y1) as f64 * eased) as i32,
+            )
+        })
+        .collect();
+
+    (points, step_delay)
+}
+
+/// Keysym for a character (Latin-1 directly, everything else as unicode)
+#[cfg_attr(not(any(feature = "vnc", all(unix, feature = "x11"))), allow(dead_code))]
+pub(crate) fn char_keysym(ch: char) -> u32 {
+    match ch {
+        '\n' | '\r' => KeyCode::Enter.keysym(),
+        '\t' => KeyCode::Tab.keysym(),
+        '\u{8}' => KeyCode::Backspace.keysym(),
+        ' '..='~' | '\u{A0}'..='\u{FF}' => ch as u32,
+        _ => 0x0100_0000 | ch as u32,
+    }
+}
+
+/// Character typed by a keysym (inverse of `char_keysym` for printable keys)
+#[cfg_attr(not(feature = "vnc"), allow(dead_code))]
+pub(crate) fn keysym_char(keysym: u32) -> Option<char> {
+    match keysym {
+        0x20..=0x7E | 0xA0..=0xFF => char::from_u32(keysym),
+        0x0100_0000..=0x0110_FFFF => char::from_u32(keysym - 0x0100_0000),
+        _ => None,
+    }
+}
+
+/// Placeholder backend for platforms without native support
+///
+/// Every operation fails with `PdbError::Unsupported`, but the crate (and the
+/// remote `Client`) still builds and runs.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct UnsupportedBackend;
+
+impl UnsupportedBackend {
+    fn unsupported<T>(&self) -> Result<T> {
+        Err(PdbError::Unsupported(format!(
+            "no native backend for {}",
+            std::env::consts::OS
+        )))
+    }
+}
+
+impl WindowBackend for UnsupportedBackend {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        self.unsupported()
+    }
+
+    fn window_info(&self, _hwnd: usize) -> Result<WindowInfo> {
+        self.unsupported()
+    }
+
+    fn is_minimized(&self, _hwnd: usize) -> bool {
+        false
+    }
+
+    fn restore(&self, _hwnd: usize) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn minimize(&self, _hwnd: usize) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn focus(&self, _hwnd: usize) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn client_size(&self, _hwnd: usize) -> Result<(i32, i32)> {
+        self.unsupported()
+    }
+
+    fn window_rect(&self, _hwnd: usize) -> Result<Rect> {
+        self.unsupported()
+    }
+
+    fn client_to_screen(&self, _hwnd: usize, _x: i32, _y: i32) -> Result<(i32, i32)> {
+        self.unsupported()
+    }
+
+    fn screen_to_client(&self, _hwnd: usize, _x: i32, _y: i32) -> Result<(i32, i32)> {
+        self.unsupported()
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32)> {
+        self.unsupported()
+    }
+}
+
+impl InputBackend for UnsupportedBackend {
+    fn mouse_click(&self, _x: i32, _y: i32) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn mouse_swipe(&self, _x1: i32, _y1: i32, _x2: i32, _y2: i32, _duration_ms: u32) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn key_event(&self, _key: KeyCode) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn input_text(&self, _text: &str) -> Result<()> {
+        self.unsupported()
+    }
+
+    fn cursor_pos(&self) -> Result<(i32, i32)> {
+        self.unsupported()
+    }
+}
+
+impl CaptureBackend for UnsupportedBackend {
+    fn capture_window(&self, _hwnd: usize) -> Result<Screenshot> {
+        self.unsupported()
+    }
+
+    fn capture_window_client(&self, _hwnd: usize) -> Result<Screenshot> {
+        self.unsupported()
+    }
+}
+
+impl Backend for UnsupportedBackend {
+    fn name(&self) -> &'static str {
+        "unsupported"
+    }
+}
@@ -0,0 +1,593 @@
+//! VNC (RFB 3.8) client backend
+//!
+//! Drives a remote framebuffer as a single window. Input is sent as RFB
+//! PointerEvent/KeyEvent messages; a background thread applies incoming
+//! FramebufferUpdates (Raw, CopyRect, ZRLE and DesktopSize) to a local copy
+//! of the framebuffer which `capture_window` returns.
+
+use super::{char_keysym, swipe_path, Backend, CaptureBackend, InputBackend, WindowBackend};
+use crate::error::{PdbError, Result};
+use crate::rfb::{
+    self, zrle::ZrleDecoder, PixelFormat, BUTTON_LEFT, CLIENT_SET_ENCODINGS,
+    CLIENT_SET_PIXEL_FORMAT, ENCODING_COPY_RECT, ENCODING_DESKTOP_SIZE, ENCODING_RAW,
+    ENCODING_ZRLE, SECURITY_NONE, SECURITY_VNC_AUTH, SERVER_BELL, SERVER_CUT_TEXT,
+    SERVER_FRAMEBUFFER_UPDATE, SERVER_SET_COLOUR_MAP_ENTRIES,
+};
+use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Handle of the single window representing the remote framebuffer
+pub const VNC_HWND: usize = 1;
+
+/// Upper bound for server strings (desktop name, failure reasons, cut text)
+const MAX_STRING: usize = 1 << 20;
+
+/// How long to wait for the first full frame
+const FULL_UPDATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time for connecting and for each read of the handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Local copy of the remote framebuffer
+#[derive(Debug, Default)]
+struct FrameState {
+    width: u16,
+    height: u16,
+    /// RGBA pixels
+    pixels: Vec<u8>,
+    /// Number of FramebufferUpdates applied
+    updates: u64,
+    /// Error that stopped the reader thread
+    error: Option<String>,
+}
+
+impl FrameState {
+    fn resize(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+        self.pixels = vec![0; width as usize * height as usize * 4];
+    }
+
+    fn check_rect(&self, x: u16, y: u16, w: u16, h: u16) -> Result<()> {
+        if x as u32 + w as u32 > self.width as u32 || y as u32 + h as u32 > self.height as u32 {
+            return Err(PdbError::ProtocolError(format!(
+                "Rectangle {}x{}+{}+{} outside {}x{} framebuffer",
+                w, h, x, y, self.width, self.height
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copy RGBA pixels of a w x h rectangle into the framebuffer
+    fn blit(&mut self, x: u16, y: u16, w: u16, h: u16, pixels: &[u8]) -> Result<()> {
+        self.check_rect(x, y, w, h)?;
+        let stride = self.width as usize * 4;
+        let row_len = w as usize * 4;
+        for row in 0..h as usize {
+            let dst = (y as usize + row) * stride + x as usize * 4;
+            let src = row * row_len;
+            self.pixels[dst..dst + row_len].copy_from_slice(&pixels[src..src + row_len]);
+        }
+        Ok(())
+    }
+
+    /// Copy a rectangle from (src_x, src_y) to (x, y)
+    fn copy_rect(&mut self, x: u16, y: u16, w: u16, h: u16, src_x: u16, src_y: u16) -> Result<()> {
+        self.check_rect(src_x, src_y, w, h)?;
+        let stride = self.width as usize * 4;
+        let row_len = w as usize * 4;
+        let mut copy = Vec::with_capacity(row_len * h as usize);
+        for row in 0..h as usize {
+            let src = (src_y as usize + row) * stride + src_x as usize * 4;
+            copy.extend_from_slice(&self.pixels[src..src + row_len]);
+        }
+        self.blit(x, y, w, h, &copy)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    frame: Mutex<FrameState>,
+    updated: Condvar,
+}
+
+impl Shared {
+    fn frame(&self) -> MutexGuard<'_, FrameState> {
+        self.frame.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Pointer state sent to the server
+#[derive(Debug, Default, Clone, Copy)]
+struct Pointer {
+    x: i32,
+    y: i32,
+}
+
+/// Backend driving a VNC server
+#[derive(Debug)]
+pub struct VncBackend {
+    stream: Mutex<TcpStream>,
+    shared: Arc<Shared>,
+    pointer: Mutex<Pointer>,
+    name: String,
+    update_timeout: Duration,
+}
+
+impl VncBackend {
+    /// Connect to a VNC server, e.g. `"127.0.0.1:5900"`
+    ///
+    /// `password` is used when the server requires VNC authentication.
+    pub fn connect(addr: &str, password: Option<&str>) -> Result<Self> {
+        Self::connect_with_timeout(addr, password, HANDSHAKE_TIMEOUT)
+    }
+
+    /// Connect, giving up when the server does not accept or stops
+    /// answering the handshake for `timeout`
+    pub fn connect_with_timeout(
+        addr: &str,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let mut stream = open(addr, timeout)
+            .map_err(|e| PdbError::ConnectionError(format!("{}: {}", addr, e)))?;
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(timeout))?;
+
+        let minor = negotiate_version(&mut stream)?;
+        negotiate_security(&mut stream, minor, password)?;
+
+        // ClientInit: allow other clients to stay connected
+        stream.write_all(&[1])?;
+
+        // ServerInit
+        let width = rfb::read_u16(&mut stream)?;
+        let height = rfb::read_u16(&mut stream)?;
+        let _server_format = PixelFormat::read_from(&mut stream)?;
+        let name_len = rfb::read_u32(&mut stream)? as usize;
+        let name = rfb::read_bytes(&mut stream, name_len, MAX_STRING)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+
+        let mut set_format = vec![CLIENT_SET_PIXEL_FORMAT, 0, 0, 0];
+        set_format.extend_from_slice(&PixelFormat::rgbx().to_bytes());
+        stream.write_all(&set_format)?;
+
+        let encodings = [
+            ENCODING_ZRLE,
+            ENCODING_COPY_RECT,
+            ENCODING_RAW,
+            ENCODING_DESKTOP_SIZE,
+        ];
+        let mut set_encodings = vec![CLIENT_SET_ENCODINGS, 0];
+        set_encodings.extend_from_slice(&(encodings.len() as u16).to_be_bytes());
+        for encoding in encodings {
+            set_encodings.extend_from_slice(&encoding.to_be_bytes());
+        }
+        stream.write_all(&set_encodings)?;
+
+        let shared = Arc::new(Shared::default());
+        shared.frame().resize(width, height);
+
+        // Updates may be minutes apart; the reader waits for them
+        stream.set_read_timeout(None)?;
+        let reader = stream.try_clone()?;
+        let reader_shared = shared.clone();
+        thread::spawn(move || read_loop(reader, reader_shared));
+
+        Ok(Self {
+            stream: Mutex::new(stream),
+            shared,
+            pointer: Mutex::new(Pointer::default()),
+            name,
+            update_timeout: Duration::from_millis(200),
+        })
+    }
+
+    /// Set how long a screenshot waits for an incremental update
+    ///
+    /// Servers only answer incremental requests once something changed, so
+    /// after this timeout the current framebuffer is returned as-is.
+    pub fn with_update_timeout(mut self, timeout: Duration) -> Self {
+        self.update_timeout = timeout;
+        self
+    }
+
+    /// Desktop name announced by the server
+    pub fn desktop_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Current framebuffer size
+    pub fn size(&self) -> (u16, u16) {
+        let frame = self.shared.frame();
+        (frame.width, frame.height)
+    }
+
+    fn check_hwnd(&self, hwnd: usize) -> Result<()> {
+        if hwnd != VNC_HWND {
+            return Err(PdbError::WindowNotFound(format!("0x{:X}", hwnd)));
+        }
+        Ok(())
+    }
+
+    fn send(&self, f: impl FnOnce(&mut TcpStream) -> Result<()>) -> Result<()> {
+        if let Some(error) = &self.shared.frame().error {
+            return Err(PdbError::ConnectionError(error.clone()));
+        }
+        let mut stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut stream)?;
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn pointer_event(&self, buttons: u8, x: i32, y: i32) -> Result<()> {
+        let (width, height) = self.size();
+        let x = x.clamp(0, width.saturating_sub(1) as i32);
+        let y = y.clamp(0, height.saturating_sub(1) as i32);
+        *self.pointer.lock().unwrap_or_else(|e| e.into_inner()) = Pointer { x, y };
+        self.send(|s| rfb::write_pointer_event(s, buttons, x as u16, y as u16))
+    }
+
+    fn tap_keysym(&self, keysym: u32) -> Result<()> {
+        self.send(|s| {
+            rfb::write_key_event(s, keysym, true)?;
+            rfb::write_key_event(s, keysym, false)
+        })
+    }
+}
+
+/// Connect to the first address of `addr` that accepts within `timeout`
+fn open(addr: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, "no address");
+    for socket in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&socket, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Exchange protocol versions, returning the agreed minor version
+fn negotiate_version(stream: &mut TcpStream) -> Result<u32> {
+    let mut version = [0u8; 12];
+    stream.read_exact(&mut version)?;
+    let (major, minor) = rfb::parse_version(&version)?;
+    if major != 3 {
+        return Err(PdbError::ProtocolError(format!(
+            "Unsupported RFB version {}.{}",
+            major, minor
+        )));
+    }
+
+    let minor = match minor {
+        8.. => 8,
+        7 => 7,
+        _ => 3,
+    };
+    stream.write_all(format!("RFB 003.{:03}\n", minor).as_bytes())?;
+    Ok(minor)
+}
+
+/// Pick a security type and authenticate
+fn negotiate_security(stream: &mut TcpStream, minor: u32, password: Option<&str>) -> Result<()> {
+    let security = if minor >= 7 {
+        let count = rfb::read_u8(stream)? as usize;
+        if count == 0 {
+            return Err(PdbError::ConnectionError(read_reason(stream)?));
+        }
+        let types = rfb::read_bytes(stream, count, 255)?;
+        let chosen = if password.is_some() && types.contains(&SECURITY_VNC_AUTH) {
+            SECURITY_VNC_AUTH
+        } else if types.contains(&SECURITY_NONE) {
+            SECURITY_NONE
+        } else if types.contains(&SECURITY_VNC_AUTH) {
+            SECURITY_VNC_AUTH
+        } else {
+            return Err(PdbError::ConnectionError(format!(
+                "No supported VNC security type (server offers {:?})",
+                types
+            )));
+        };
+        stream.write_all(&[chosen])?;
+        chosen
+    } else {
+        match rfb::read_u32(stream)? {
+            0 => return Err(PdbError::ConnectionError(read_reason(stream)?)),
+            t if t == SECURITY_NONE as u32 || t == SECURITY_VNC_AUTH as u32 => t as u8,
+            t => {
+                return Err(PdbError::ConnectionError(format!(
+                    "Unsupported VNC security type {}",
+                    t
+                )))
+            }
+        }
+    };
+
+    if security == SECURITY_VNC_AUTH {
+        let password = password
+            .ok_or_else(|| PdbError::ConnectionError("VNC server requires a password".into()))?;
+        let mut challenge = [0u8; 16];
+        stream.read_exact(&mut challenge)?;
+        stream.write_all(&rfb::vnc_auth_response(&challenge, password))?;
+    }
+
+    // RFB 3.8 always sends a SecurityResult; older versions skip it for None
+    if (security == SECURITY_VNC_AUTH || minor >= 8) && rfb::read_u32(stream)? != 0 {
+        let reason = if minor >= 8 {
+            read_reason(stream)?
+        } else {
+            "authentication failed".to_string()
+        };
+        return Err(PdbError::ConnectionError(format!(
+            "VNC authentication failed: {}",
+            reason
+        )));
+    }
+    Ok(())
+}
+
+fn read_reason(r: &mut impl Read) -> Result<String> {
+    let len = rfb::read_u32(r)? as usize;
+    let reason = rfb::read_bytes(r, len, MAX_STRING)?;
+    Ok(String::from_utf8_lossy(&reason).into_owned())
+}
+
+/// Reader thread: apply server messages until the connection fails
+fn read_loop(mut stream: TcpStream, shared: Arc<Shared>) {
+    let mut decoder = ZrleDecoder::new();
+    let error = loop {
+        if let Err(e) = read_message(&mut stream, &shared, &mut decoder) {
+            break e;
+        }
+    };
+
+    log::debug!("VNC reader stopped: {}", error);
+    shared.frame().error = Some(error.to_string());
+    shared.updated.notify_all();
+}
+
+fn read_message(stream: &mut TcpStream, shared: &Shared, decoder: &mut ZrleDecoder) -> Result<()> {
+    match rfb::read_u8(stream)? {
+        SERVER_FRAMEBUFFER_UPDATE => {
+            rfb::skip(stream, 1)?;
+            let rects = rfb::read_u16(stream)?;
+            for _ in 0..rects {
+                read_rect(stream, shared, decoder)?;
+            }
+            shared.frame().updates += 1;
+            shared.updated.notify_all();
+        }
+        SERVER_SET_COLOUR_MAP_ENTRIES => {
+            rfb::skip(stream, 3)?;
+            let colours = rfb::read_u16(stream)?;
+            rfb::skip(stream, colours as u64 * 6)?;
+        }
+        SERVER_BELL => {}
+        SERVER_CUT_TEXT => {
+            rfb::skip(stream, 3)?;
+            let len = rfb::read_u32(stream)?;
+            rfb::skip(stream, len as u64)?;
+        }
+        other => {
+            return Err(PdbError::ProtocolError(format!(
+                "Unknown RFB server message {}",
+                other
+            )))
+        }
+    }
+    Ok(())
+}
+
+fn read_rect(stream: &mut TcpStream, shared: &Shared, decoder: &mut ZrleDecoder) -> Result<()> {
+    let x = rfb::read_u16(stream)?;
+    let y = rfb::read_u16(stream)?;
+    let w = rfb::read_u16(stream)?;
+    let h = rfb::read_u16(stream)?;
+    let encoding = rfb::read_i32(stream)?;
+
+    match encoding {
+        ENCODING_RAW => {
+            // Checked before reading, so a bogus size cannot allocate gigabytes
+            shared.frame().check_rect(x, y, w, h)?;
+            let len = w as usize * h as usize * 4;
+            let mut pixels = rfb::read_bytes(stream, len, len)?;
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel[3] = 255;
+            }
+            shared.frame().blit(x, y, w, h, &pixels)
+        }
+        ENCODING_COPY_RECT => {
+            let src_x = rfb::read_u16(stream)?;
+            let src_y = rfb::read_u16(stream)?;
+            shared.frame().copy_rect(x, y, w, h, src_x, src_y)
+        }
+        ENCODING_ZRLE => {
+            shared.frame().check_rect(x, y, w, h)?;
+            let pixels = decoder.decode(stream, w as usize, h as usize)?;
+            shared.frame().blit(x, y, w, h, &pixels)
+        }
+        ENCODING_DESKTOP_SIZE => {
+            shared.frame().resize(w, h);
+            Ok(())
+        }
+        other => Err(PdbError::ProtocolError(format!(
+            "Unsupported RFB encoding {}",
+            other
+        ))),
+    }
+}
+
+impl WindowBackend for VncBackend {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        Ok(vec![self.window_info(VNC_HWND)?])
+    }
+
+    fn window_info(&self, hwnd: usize) -> Result<WindowInfo> {
+        self.check_hwnd(hwnd)?;
+        let (width, height) = self.size();
+        Ok(WindowInfo {
+            hwnd,
+            title: self.name.clone(),
+            class_name: "VNC".to_string(),
+            rect: Rect::new(0, 0, width as i32, height as i32),
+            visible: true,
+        })
+    }
+
+    fn is_minimized(&self, _hwnd: usize) -> bool {
+        false
+    }
+
+    fn restore(&self, hwnd: usize) -> Result<()> {
+        self.check_hwnd(hwnd)
+    }
+
+    fn minimize(&self, hwnd: usize) -> Result<()> {
+        self.check_hwnd(hwnd)
+    }
+
+    fn focus(&self, hwnd: usize) -> Result<()> {
+        self.check_hwnd(hwnd)
+    }
+
+    fn client_size(&self, hwnd: usize) -> Result<(i32, i32)> {
+        self.check_hwnd(hwnd)?;
+        let (width, height) = self.size();
+        Ok((width as i32, height as i32))
+    }
+
+    fn window_rect(&self, hwnd: usize) -> Result<Rect> {
+        self.window_info(hwnd).map(|info| info.rect)
+    }
+
+    fn client_to_screen(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        self.check_hwnd(hwnd)?;
+        Ok((x, y))
+    }
+
+    fn screen_to_client(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        self.check_hwnd(hwnd)?;
+        Ok((x, y))
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32)> {
+        let (width, height) = self.size();
+        Ok((width as i32, height as i32))
+    }
+}
+
+impl InputBackend for VncBackend {
+    fn mouse_click(&self, x: i32, y: i32) -> Result<()> {
+        self.pointer_event(0, x, y)?;
+        self.pointer_event(BUTTON_LEFT, x, y)?;
+        self.pointer_event(0, x, y)
+    }
+
+    fn mouse_swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Result<()> {
+        let (points, step_delay) = swipe_path(x1, y1, x2, y2, duration_ms);
+
+        self.pointer_event(0, x1, y1)?;
+        thread::sleep(Duration::from_millis(30));
+        self.pointer_event(BUTTON_LEFT, x1, y1)?;
+        thread::sleep(Duration::from_millis(50));
+
+        for (x, y) in points {
+            self.pointer_event(BUTTON_LEFT, x, y)?;
+            thread::sleep(step_delay);
+        }
+
+        thread::sleep(Duration::from_millis(30));
+        self.pointer_event(0, x2, y2)
+    }
+
+    fn key_event(&self, key: KeyCode) -> Result<()> {
+        self.tap_keysym(key.keysym())
+    }
+
+    fn input_text(&self, text: &str) -> Result<()> {
+        for ch in text.chars() {
+            self.tap_keysym(char_keysym(ch))?;
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    fn cursor_pos(&self) -> Result<(i32, i32)> {
+        let pointer = *self.pointer.lock().unwrap_or_else(|e| e.into_inner());
+        Ok((pointer.x, pointer.y))
+    }
+}
+
+impl CaptureBackend for VncBackend {
+    fn capture_window(&self, hwnd: usize) -> Result<Screenshot> {
+        self.check_hwnd(hwnd)?;
+
+        let (seen, width, height) = {
+            let frame = self.shared.frame();
+            (frame.updates, frame.width, frame.height)
+        };
+        let incremental = seen > 0;
+        self.send(|s| rfb::write_update_request(s, incremental, width, height))?;
+
+        let timeout = if incremental {
+            self.update_timeout
+        } else {
+            FULL_UPDATE_TIMEOUT
+        };
+        let deadline = Instant::now() + timeout;
+        let mut frame = self.shared.frame();
+        while frame.updates == seen && frame.error.is_none() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            frame = self
+                .shared
+                .updated
+                .wait_timeout(frame, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+
+        if let Some(error) = &frame.error {
+            return Err(PdbError::CaptureError(format!(
+                "VNC connection lost: {}",
+                error
+            )));
+        }
+        if frame.updates == 0 {
+            return Err(PdbError::CaptureError(
+                "Timed out waiting for VNC framebuffer".into(),
+            ));
+        }
+
+        Ok(Screenshot {
+            width: frame.width as u32,
+            height: frame.height as u32,
+            data: frame.pixels.clone(),
+        })
+    }
+
+    fn capture_window_client(&self, hwnd: usize) -> Result<Screenshot> {
+        self.capture_window(hwnd)
+    }
+}
+
+impl Backend for VncBackend {
+    fn name(&self) -> &'static str {
+        "vnc"
+    }
+}
+
+impl Drop for VncBackend {
+    fn drop(&mut self) {
+        // Unblocks the reader thread
+        let stream = self.stream.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+    }
+}
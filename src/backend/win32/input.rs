@@ -1,4 +1,4 @@
-//! Input simulation module
+//! Win32 input simulation using SendInput
 
 use crate::error::{PdbError, Result};
 use crate::types::KeyCode;
@@ -0,0 +1,207 @@
+//! Win32 backend
+
+pub mod capture;
+pub mod input;
+
+use super::{Backend, CaptureBackend, InputBackend, WindowBackend};
+use crate::error::Result;
+use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
+use std::ffi::OsString;
+use std::os::windows::ffi::OsStringExt;
+use windows::Win32::Foundation::{BOOL, HWND, LPARAM, POINT, RECT};
+use windows::Win32::Graphics::Gdi::{ClientToScreen, ScreenToClient};
+use windows::Win32::UI::WindowsAndMessaging::{
+    EnumWindows, GetClassNameW, GetClientRect, GetSystemMetrics, GetWindowRect,
+    GetWindowTextLengthW, GetWindowTextW, IsIconic, IsWindowVisible, SetForegroundWindow,
+    ShowWindow, SM_CXSCREEN, SM_CYSCREEN, SW_MINIMIZE, SW_SHOWNOACTIVATE,
+};
+
+/// Backend using the Win32 API
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Win32Backend;
+
+impl Win32Backend {
+    /// Create a new Win32 backend
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+fn to_hwnd(hwnd: usize) -> HWND {
+    HWND(hwnd as *mut _)
+}
+
+impl WindowBackend for Win32Backend {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        let mut windows: Vec<WindowInfo> = Vec::new();
+
+        unsafe {
+            let _ = EnumWindows(
+                Some(enum_windows_callback),
+                LPARAM(&mut windows as *mut Vec<WindowInfo> as isize),
+            );
+        }
+
+        Ok(windows)
+    }
+
+    fn window_info(&self, hwnd: usize) -> Result<WindowInfo> {
+        get_window_info(to_hwnd(hwnd))
+    }
+
+    fn is_minimized(&self, hwnd: usize) -> bool {
+        unsafe { IsIconic(to_hwnd(hwnd)).as_bool() }
+    }
+
+    fn restore(&self, hwnd: usize) -> Result<()> {
+        unsafe {
+            let _ = ShowWindow(to_hwnd(hwnd), SW_SHOWNOACTIVATE);
+        }
+        Ok(())
+    }
+
+    fn minimize(&self, hwnd: usize) -> Result<()> {
+        unsafe {
+            let _ = ShowWindow(to_hwnd(hwnd), SW_MINIMIZE);
+        }
+        Ok(())
+    }
+
+    fn focus(&self, hwnd: usize) -> Result<()> {
+        unsafe {
+            let _ = SetForegroundWindow(to_hwnd(hwnd));
+        }
+        Ok(())
+    }
+
+    fn client_size(&self, hwnd: usize) -> Result<(i32, i32)> {
+        unsafe {
+            let mut rect = RECT::default();
+            let _ = GetClientRect(to_hwnd(hwnd), &mut rect);
+            Ok((rect.right - rect.left, rect.bottom - rect.top))
+        }
+    }
+
+    fn window_rect(&self, hwnd: usize) -> Result<Rect> {
+        unsafe {
+            let mut rect = RECT::default();
+            let _ = GetWindowRect(to_hwnd(hwnd), &mut rect);
+            Ok(Rect::new(rect.left, rect.top, rect.right, rect.bottom))
+        }
+    }
+
+    fn client_to_screen(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        unsafe {
+            let mut point = POINT { x, y };
+            let _ = ClientToScreen(to_hwnd(hwnd), &mut point);
+            Ok((point.x, point.y))
+        }
+    }
+
+    fn screen_to_client(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        unsafe {
+            let mut point = POINT { x, y };
+            let _ = ScreenToClient(to_hwnd(hwnd), &mut point);
+            Ok((point.x, point.y))
+        }
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32)> {
+        unsafe { Ok((GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN))) }
+    }
+}
+
+impl InputBackend for Win32Backend {
+    fn mouse_click(&self, x: i32, y: i32) -> Result<()> {
+        input::mouse_click(x, y)
+    }
+
+    fn mouse_swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Result<()> {
+        input::mouse_swipe(x1, y1, x2, y2, duration_ms)
+    }
+
+    fn key_event(&self, key: KeyCode) -> Result<()> {
+        input::key_event(key)
+    }
+
+    fn input_text(&self, text: &str) -> Result<()> {
+        input::input_text(text)
+    }
+
+    fn cursor_pos(&self) -> Result<(i32, i32)> {
+        input::get_cursor_pos()
+    }
+}
+
+impl CaptureBackend for Win32Backend {
+    fn capture_window(&self, hwnd: usize) -> Result<Screenshot> {
+        capture::capture_window(to_hwnd(hwnd))
+    }
+
+    fn capture_window_client(&self, hwnd: usize) -> Result<Screenshot> {
+        capture::capture_window_client(to_hwnd(hwnd))
+    }
+}
+
+impl Backend for Win32Backend {
+    fn name(&self) -> &'static str {
+        "win32"
+    }
+}
+
+/// Callback for EnumWindows
+unsafe extern "system" fn enum_windows_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let windows = &mut *(lparam.0 as *mut Vec<WindowInfo>);
+
+    // Only include visible windows
+    if IsWindowVisible(hwnd).as_bool() {
+        if let Ok(info) = get_window_info(hwnd) {
+            // Filter out empty titles
+            if !info.title.is_empty() {
+                windows.push(info);
+            }
+        }
+    }
+
+    BOOL(1) // Continue enumeration
+}
+
+/// Get window information
+fn get_window_info(hwnd: HWND) -> Result<WindowInfo> {
+    unsafe {
+        // Get window title
+        let title_len = GetWindowTextLengthW(hwnd);
+        let title = if title_len > 0 {
+            let mut buffer: Vec<u16> = vec![0; (title_len + 1) as usize];
+            GetWindowTextW(hwnd, &mut buffer);
+            OsString::from_wide(&buffer[..title_len as usize])
+                .to_string_lossy()
+                .to_string()
+        } else {
+            String::new()
+        };
+
+        // Get class name
+        let mut class_buffer: Vec<u16> = vec![0; 256];
+        let class_len = GetClassNameW(hwnd, &mut class_buffer);
+        let class_name = if class_len > 0 {
+            OsString::from_wide(&class_buffer[..class_len as usize])
+                .to_string_lossy()
+                .to_string()
+        } else {
+            String::new()
+        };
+
+        // Get window rect
+        let mut rect = RECT::default();
+        let _ = GetWindowRect(hwnd, &mut rect);
+
+        Ok(WindowInfo {
+            hwnd: hwnd.0 as usize,
+            title,
+            class_name,
+            rect: Rect::new(rect.left, rect.top, rect.right, rect.bottom),
+            visible: IsWindowVisible(hwnd).as_bool(),
+        })
+    }
+}
@@ -0,0 +1,630 @@
+//! X11 backend
+//!
+//! Windows are listed from `_NET_CLIENT_LIST` (or the root window's children
+//! when no EWMH window manager is running, e.g. under a bare Xvfb), input is
+//! injected with XTest and screenshots are read with `GetImage`.
+//!
+//! Screenshots are copied through the X connection rather than shared
+//! memory (XShm), and need a TrueColor visual of 16, 24 or 32 bits per
+//! pixel; 8-bit palette screens fail with `CaptureError`.
+
+use super::{char_keysym, swipe_path, Backend, CaptureBackend, InputBackend, WindowBackend};
+use crate::error::{PdbError, Result};
+use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::ReplyError;
+use x11rb::protocol::xproto::{
+    Atom, AtomEnum, ClientMessageEvent, ConfigureWindowAux, ConnectionExt as _, EventMask,
+    GetImageReply, ImageFormat, ImageOrder, InputFocus, Keycode, MapState, StackMode, VisualClass,
+    Window, BUTTON_PRESS_EVENT, BUTTON_RELEASE_EVENT, KEY_PRESS_EVENT, KEY_RELEASE_EVENT,
+    MOTION_NOTIFY_EVENT,
+};
+use x11rb::protocol::xtest::{self, ConnectionExt as _};
+use x11rb::rust_connection::RustConnection;
+use x11rb::CURRENT_TIME;
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_CLIENT_LIST,
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_NAME,
+        _NET_WM_STATE,
+        _NET_WM_STATE_HIDDEN,
+        WM_STATE,
+        WM_CHANGE_STATE,
+        UTF8_STRING,
+    }
+}
+
+/// ICCCM `IconicState`
+const ICONIC_STATE: u32 = 3;
+
+/// Left mouse button
+const BUTTON_LEFT: u8 = 1;
+
+/// Keysym of the left shift key
+const XK_SHIFT_L: u32 = 0xFFE1;
+
+/// Backend talking to an X server
+pub struct X11Backend {
+    conn: RustConnection,
+    root: Window,
+    atoms: Atoms,
+}
+
+impl fmt::Debug for X11Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("X11Backend")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl X11Backend {
+    /// Connect to an X server
+    ///
+    /// `display` uses the usual syntax (e.g. `":0"`); `None` reads `$DISPLAY`.
+    pub fn connect(display: Option<&str>) -> Result<Self> {
+        let (conn, screen_num) = x11rb::connect(display)?;
+        let root = conn.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&conn)?.reply()?;
+
+        if conn
+            .extension_information(xtest::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            return Err(PdbError::X11Error("XTest extension not available".into()));
+        }
+
+        Ok(Self { conn, root, atoms })
+    }
+
+    /// Top-level client windows
+    fn client_windows(&self) -> Result<Vec<Window>> {
+        let reply = self
+            .conn
+            .get_property(
+                false,
+                self.root,
+                self.atoms._NET_CLIENT_LIST,
+                AtomEnum::WINDOW,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        if let Some(list) = reply.value32() {
+            let list: Vec<Window> = list.collect();
+            if !list.is_empty() {
+                return Ok(list);
+            }
+        }
+
+        // No EWMH window manager: fall back to children of the root window
+        Ok(self.conn.query_tree(self.root)?.reply()?.children)
+    }
+
+    /// Read an 8-bit text property
+    fn text_property(&self, window: Window, property: Atom, type_: Atom) -> Result<Option<String>> {
+        let reply = self
+            .conn
+            .get_property(false, window, property, type_, 0, 1024)?
+            .reply()?;
+        if reply.format != 8 || reply.value.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()))
+    }
+
+    /// Window title from `_NET_WM_NAME`, falling back to `WM_NAME`
+    fn title(&self, window: Window) -> Result<String> {
+        if let Some(title) =
+            self.text_property(window, self.atoms._NET_WM_NAME, self.atoms.UTF8_STRING)?
+        {
+            return Ok(title);
+        }
+        Ok(self
+            .text_property(window, AtomEnum::WM_NAME.into(), AtomEnum::ANY.into())?
+            .unwrap_or_default())
+    }
+
+    /// Window class from `WM_CLASS` (`instance\0class\0`)
+    fn class_name(&self, window: Window) -> Result<String> {
+        let value = self
+            .text_property(window, AtomEnum::WM_CLASS.into(), AtomEnum::STRING.into())?
+            .unwrap_or_default();
+        let mut parts = value.split('\0').filter(|s| !s.is_empty());
+        let instance = parts.next().unwrap_or_default();
+        Ok(parts.next().unwrap_or(instance).to_string())
+    }
+
+    /// Check `WM_STATE` and `_NET_WM_STATE` for an iconified window
+    fn iconic(&self, window: Window) -> Result<bool> {
+        let wm_state = self
+            .conn
+            .get_property(
+                false,
+                window,
+                self.atoms.WM_STATE,
+                self.atoms.WM_STATE,
+                0,
+                2,
+            )?
+            .reply()?;
+        if let Some(mut state) = wm_state.value32() {
+            if state.next() == Some(ICONIC_STATE) {
+                return Ok(true);
+            }
+        }
+
+        let net_state = self
+            .conn
+            .get_property(
+                false,
+                window,
+                self.atoms._NET_WM_STATE,
+                AtomEnum::ATOM,
+                0,
+                1024,
+            )?
+            .reply()?;
+        Ok(net_state
+            .value32()
+            .is_some_and(|mut atoms| atoms.any(|a| a == self.atoms._NET_WM_STATE_HIDDEN)))
+    }
+
+    /// Window rectangle in root coordinates
+    fn geometry(&self, window: Window) -> Result<Rect> {
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+        let origin = self
+            .conn
+            .translate_coordinates(window, self.root, 0, 0)?
+            .reply()?;
+        let left = origin.dst_x as i32;
+        let top = origin.dst_y as i32;
+        Ok(Rect::new(
+            left,
+            top,
+            left + geometry.width as i32,
+            top + geometry.height as i32,
+        ))
+    }
+
+    /// Send an EWMH/ICCCM client message to the root window
+    fn send_root_message(&self, window: Window, type_: Atom, data: [u32; 5]) -> Result<()> {
+        let event = ClientMessageEvent::new(32, window, type_, data);
+        self.conn
+            .send_event(
+                false,
+                self.root,
+                EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                event,
+            )?
+            .check()?;
+        Ok(())
+    }
+
+    /// Inject a fake event with XTest
+    fn fake_input(&self, type_: u8, detail: u8, x: i32, y: i32) -> Result<()> {
+        self.conn.xtest_fake_input(
+            type_,
+            detail,
+            CURRENT_TIME,
+            self.root,
+            coordinate(x)?,
+            coordinate(y)?,
+            0,
+        )?;
+        Ok(())
+    }
+
+    /// Wait until the server has processed every request sent so far
+    fn sync(&self) -> Result<()> {
+        self.conn.get_input_focus()?.reply()?;
+        Ok(())
+    }
+
+    /// Current keycode -> keysym table
+    fn keyboard_map(&self) -> Result<KeyboardMap> {
+        let setup = self.conn.setup();
+        let min_keycode = setup.min_keycode;
+        let count = setup.max_keycode - min_keycode + 1;
+        let reply = self
+            .conn
+            .get_keyboard_mapping(min_keycode, count)?
+            .reply()?;
+        Ok(KeyboardMap {
+            min_keycode,
+            per_keycode: reply.keysyms_per_keycode as usize,
+            keysyms: reply.keysyms,
+        })
+    }
+
+    /// Press and release a keycode, optionally holding shift
+    fn tap_keycode(&self, keycode: Keycode, shift: Option<Keycode>) -> Result<()> {
+        if let Some(shift) = shift {
+            self.fake_input(KEY_PRESS_EVENT, shift, 0, 0)?;
+        }
+        self.fake_input(KEY_PRESS_EVENT, keycode, 0, 0)?;
+        self.fake_input(KEY_RELEASE_EVENT, keycode, 0, 0)?;
+        if let Some(shift) = shift {
+            self.fake_input(KEY_RELEASE_EVENT, shift, 0, 0)?;
+        }
+        self.sync()
+    }
+
+    /// Type a single keysym
+    ///
+    /// Keysyms missing from the keymap are temporarily bound to an unused
+    /// keycode, which is how unicode text is typed.
+    fn type_keysym(&self, keysym: u32) -> Result<()> {
+        let map = self.keyboard_map()?;
+
+        if let Some((keycode, shifted)) = map.find(keysym) {
+            let shift = if shifted {
+                map.find(XK_SHIFT_L).map(|(k, _)| k)
+            } else {
+                None
+            };
+            return self.tap_keycode(keycode, shift);
+        }
+
+        let spare = map.spare().ok_or_else(|| {
+            PdbError::InputError(format!("No free keycode for keysym 0x{:X}", keysym))
+        })?;
+        let per_keycode = map.per_keycode as u8;
+        self.conn
+            .change_keyboard_mapping(1, spare, per_keycode, &vec![keysym; map.per_keycode])?
+            .check()?;
+        // Give clients a moment to process the MappingNotify
+        thread::sleep(Duration::from_millis(20));
+        let result = self.tap_keycode(spare, None);
+        self.conn
+            .change_keyboard_mapping(1, spare, per_keycode, &vec![0; map.per_keycode])?
+            .check()?;
+        result
+    }
+
+    /// Read the pixels of a drawable area, with the area's size
+    fn get_image(
+        &self,
+        drawable: Window,
+        x: i16,
+        y: i16,
+        width: u16,
+        height: u16,
+    ) -> std::result::Result<(GetImageReply, u16, u16), ReplyError> {
+        let image = self
+            .conn
+            .get_image(ImageFormat::Z_PIXMAP, drawable, x, y, width, height, !0)?
+            .reply()?;
+        Ok((image, width, height))
+    }
+
+    fn capture(&self, window: Window) -> Result<Screenshot> {
+        let geometry = self.conn.get_geometry(window)?.reply()?;
+
+        let raw = match self.get_image(window, 0, 0, geometry.width, geometry.height) {
+            Ok(raw) => raw,
+            // Window partially off-screen or not viewable: read its area from the root
+            Err(ReplyError::X11Error(_)) => {
+                let rect = self.geometry(window)?;
+                let screen = self.conn.get_geometry(self.root)?.reply()?;
+                let left = rect.left.max(0);
+                let top = rect.top.max(0);
+                let right = rect.right.min(screen.width as i32);
+                let bottom = rect.bottom.min(screen.height as i32);
+                if right <= left || bottom <= top {
+                    return Err(PdbError::CaptureError("Window is off-screen".into()));
+                }
+                self.get_image(
+                    self.root,
+                    left as i16,
+                    top as i16,
+                    (right - left) as u16,
+                    (bottom - top) as u16,
+                )?
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let (image, width, height) = raw;
+        self.to_rgba(image, width, height)
+    }
+
+    /// Convert a TrueColor ZPixmap to RGBA using the masks of its visual
+    fn to_rgba(&self, image: GetImageReply, width: u16, height: u16) -> Result<Screenshot> {
+        let setup = self.conn.setup();
+        let format = setup.pixmap_formats.iter().find(|f| f.depth == image.depth);
+        let visual = setup
+            .roots
+            .iter()
+            .flat_map(|screen| &screen.allowed_depths)
+            .flat_map(|depth| &depth.visuals)
+            .find(|visual| visual.visual_id == image.visual);
+        let (format, visual) = match (format, visual) {
+            (Some(format), Some(visual))
+                if matches!(format.bits_per_pixel, 16 | 24 | 32)
+                    && visual.class == VisualClass::TRUE_COLOR =>
+            {
+                (format, visual)
+            }
+            _ => {
+                return Err(PdbError::CaptureError(format!(
+                    "Unsupported pixel format: depth {} with {} bits per pixel",
+                    image.depth,
+                    format.map_or(0, |f| f.bits_per_pixel)
+                )))
+            }
+        };
+
+        // Rows are padded to the scanline unit
+        let bytes = format.bits_per_pixel as usize / 8;
+        let pad = format.scanline_pad as usize / 8;
+        let stride = (width as usize * bytes).div_ceil(pad) * pad;
+        if image.data.len() < stride * height as usize {
+            return Err(PdbError::CaptureError(format!(
+                "Short image: {} bytes for {}x{}",
+                image.data.len(),
+                width,
+                height
+            )));
+        }
+
+        let lsb_first = setup.image_byte_order == ImageOrder::LSB_FIRST;
+        let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+        for row in image.data.chunks_exact(stride).take(height as usize) {
+            for pixel in row[..width as usize * bytes].chunks_exact(bytes) {
+                let value = if lsb_first {
+                    pixel.iter().rev().fold(0, |v, &b| v << 8 | b as u32)
+                } else {
+                    pixel.iter().fold(0, |v, &b| v << 8 | b as u32)
+                };
+                data.extend_from_slice(&[
+                    channel(value, visual.red_mask),
+                    channel(value, visual.green_mask),
+                    channel(value, visual.blue_mask),
+                    255,
+                ]);
+            }
+        }
+
+        Ok(Screenshot {
+            width: width as u32,
+            height: height as u32,
+            data,
+        })
+    }
+}
+
+/// Scale the bits of `pixel` under `mask` to 0..=255
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let shift = mask.trailing_zeros();
+    let max = (mask >> shift) as u64;
+    let value = ((pixel & mask) >> shift) as u64;
+    (value * 255 / max) as u8
+}
+
+/// Fit a coordinate into the 16 bits of the X protocol
+fn coordinate(value: i32) -> Result<i16> {
+    i16::try_from(value)
+        .map_err(|_| PdbError::InputError(format!("Coordinate {} is outside the X11 range", value)))
+}
+
+/// Snapshot of the server keymap
+struct KeyboardMap {
+    min_keycode: Keycode,
+    per_keycode: usize,
+    keysyms: Vec<u32>,
+}
+
+impl KeyboardMap {
+    /// Find the keycode producing a keysym, and whether shift is needed
+    fn find(&self, keysym: u32) -> Option<(Keycode, bool)> {
+        self.keysyms
+            .chunks(self.per_keycode.max(1))
+            .enumerate()
+            .find_map(|(i, syms)| {
+                let keycode = self.min_keycode + i as u8;
+                if syms.first() == Some(&keysym) {
+                    Some((keycode, false))
+                } else if syms.get(1) == Some(&keysym) {
+                    Some((keycode, true))
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Highest keycode without any keysym bound
+    fn spare(&self) -> Option<Keycode> {
+        self.keysyms
+            .chunks(self.per_keycode.max(1))
+            .enumerate()
+            .rev()
+            .find(|(_, syms)| syms.iter().all(|&s| s == 0))
+            .map(|(i, _)| self.min_keycode + i as u8)
+    }
+}
+
+/// Treat X protocol errors (e.g. a destroyed window) as non-fatal
+fn ignore_x11_error(result: std::result::Result<(), ReplyError>) -> Result<()> {
+    match result {
+        Ok(()) | Err(ReplyError::X11Error(_)) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+impl WindowBackend for X11Backend {
+    fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        let mut windows = Vec::new();
+        for window in self.client_windows()? {
+            if let Ok(info) = self.window_info(window as usize) {
+                if info.visible && !info.title.is_empty() {
+                    windows.push(info);
+                }
+            }
+        }
+        Ok(windows)
+    }
+
+    fn window_info(&self, hwnd: usize) -> Result<WindowInfo> {
+        let window = hwnd as Window;
+        let attributes = match self.conn.get_window_attributes(window)?.reply() {
+            Ok(attributes) => attributes,
+            Err(ReplyError::X11Error(_)) => {
+                return Err(PdbError::WindowNotFound(format!("0x{:X}", hwnd)))
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(WindowInfo {
+            hwnd,
+            title: self.title(window)?,
+            class_name: self.class_name(window)?,
+            rect: self.geometry(window)?,
+            visible: attributes.map_state == MapState::VIEWABLE || self.iconic(window)?,
+        })
+    }
+
+    fn is_minimized(&self, hwnd: usize) -> bool {
+        self.iconic(hwnd as Window).unwrap_or(false)
+    }
+
+    fn restore(&self, hwnd: usize) -> Result<()> {
+        ignore_x11_error(self.conn.map_window(hwnd as Window)?.check())
+    }
+
+    fn minimize(&self, hwnd: usize) -> Result<()> {
+        self.send_root_message(
+            hwnd as Window,
+            self.atoms.WM_CHANGE_STATE,
+            [ICONIC_STATE, 0, 0, 0, 0],
+        )
+    }
+
+    fn focus(&self, hwnd: usize) -> Result<()> {
+        let window = hwnd as Window;
+        // Ask the window manager first (source indication 2 = pager), then
+        // raise and focus directly for setups without one
+        self.send_root_message(
+            window,
+            self.atoms._NET_ACTIVE_WINDOW,
+            [2, CURRENT_TIME, 0, 0, 0],
+        )?;
+        ignore_x11_error(
+            self.conn
+                .configure_window(
+                    window,
+                    &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+                )?
+                .check(),
+        )?;
+        ignore_x11_error(
+            self.conn
+                .set_input_focus(InputFocus::PARENT, window, CURRENT_TIME)?
+                .check(),
+        )
+    }
+
+    fn client_size(&self, hwnd: usize) -> Result<(i32, i32)> {
+        let geometry = self.conn.get_geometry(hwnd as Window)?.reply()?;
+        Ok((geometry.width as i32, geometry.height as i32))
+    }
+
+    fn window_rect(&self, hwnd: usize) -> Result<Rect> {
+        self.geometry(hwnd as Window)
+    }
+
+    fn client_to_screen(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        let reply = self
+            .conn
+            .translate_coordinates(hwnd as Window, self.root, coordinate(x)?, coordinate(y)?)?
+            .reply()?;
+        Ok((reply.dst_x as i32, reply.dst_y as i32))
+    }
+
+    fn screen_to_client(&self, hwnd: usize, x: i32, y: i32) -> Result<(i32, i32)> {
+        let reply = self
+            .conn
+            .translate_coordinates(self.root, hwnd as Window, coordinate(x)?, coordinate(y)?)?
+            .reply()?;
+        Ok((reply.dst_x as i32, reply.dst_y as i32))
+    }
+
+    fn screen_size(&self) -> Result<(i32, i32)> {
+        let geometry = self.conn.get_geometry(self.root)?.reply()?;
+        Ok((geometry.width as i32, geometry.height as i32))
+    }
+}
+
+impl InputBackend for X11Backend {
+    fn mouse_click(&self, x: i32, y: i32) -> Result<()> {
+        self.fake_input(MOTION_NOTIFY_EVENT, 0, x, y)?;
+        self.fake_input(BUTTON_PRESS_EVENT, BUTTON_LEFT, x, y)?;
+        self.fake_input(BUTTON_RELEASE_EVENT, BUTTON_LEFT, x, y)?;
+        self.sync()
+    }
+
+    fn mouse_swipe(&self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Result<()> {
+        let (points, step_delay) = swipe_path(x1, y1, x2, y2, duration_ms);
+
+        self.fake_input(MOTION_NOTIFY_EVENT, 0, x1, y1)?;
+        self.sync()?;
+        thread::sleep(Duration::from_millis(30));
+
+        self.fake_input(BUTTON_PRESS_EVENT, BUTTON_LEFT, x1, y1)?;
+        self.sync()?;
+        thread::sleep(Duration::from_millis(50));
+
+        for (x, y) in points {
+            self.fake_input(MOTION_NOTIFY_EVENT, 0, x, y)?;
+            self.conn.flush()?;
+            thread::sleep(step_delay);
+        }
+
+        thread::sleep(Duration::from_millis(30));
+        self.fake_input(BUTTON_RELEASE_EVENT, BUTTON_LEFT, x2, y2)?;
+        self.sync()
+    }
+
+    fn key_event(&self, key: KeyCode) -> Result<()> {
+        self.type_keysym(key.keysym())
+    }
+
+    fn input_text(&self, text: &str) -> Result<()> {
+        for ch in text.chars() {
+            self.type_keysym(char_keysym(ch))?;
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+
+    fn cursor_pos(&self) -> Result<(i32, i32)> {
+        let reply = self.conn.query_pointer(self.root)?.reply()?;
+        Ok((reply.root_x as i32, reply.root_y as i32))
+    }
+}
+
+impl CaptureBackend for X11Backend {
+    /// X11 has no portable notion of the decorated frame, so this captures
+    /// the client window just like `capture_window_client`
+    fn capture_window(&self, hwnd: usize) -> Result<Screenshot> {
+        self.capture(hwnd as Window)
+    }
+
+    fn capture_window_client(&self, hwnd: usize) -> Result<Screenshot> {
+        self.capture(hwnd as Window)
+    }
+}
+
+impl Backend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+}
@@ -1,6 +1,8 @@
 //! PDB Client binary - command line tool for local and remote operations
 
-use pdb::{Client, Device, KeyCode, WindowController};
+use pdb::protocol::Encoding;
+use pdb::rpc::RpcServer;
+use pdb::{Client, ClientOptions, Device, KeyCode, ScreenshotFormat, WindowController};
 use std::env;
 
 /// Check if running in local mode
@@ -8,10 +10,70 @@ fn is_local_mode(args: &[String]) -> bool {
     args.iter().any(|a| a == "--local" || a == "-l")
 }
 
-/// Filter out --local flag from args
+/// Check if JSON wire encoding was requested (for debugging)
+fn is_json_mode(args: &[String]) -> bool {
+    args.iter().any(|a| a == "--json")
+}
+
+/// Remove `<name> <value>` from args and return the value
+fn take_option(args: &mut Vec<String>, name: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == name)?;
+    if i + 1 >= args.len() {
+        return None;
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Some(value)
+}
+
+/// Remove `--key <key>` from args and return the key
+///
+/// Falls back to the `PDB_KEY` environment variable, which keeps the key out
+/// of the process list.
+fn take_key(args: &mut Vec<String>) -> Option<String> {
+    take_option(args, "--key").or_else(|| env::var("PDB_KEY").ok().filter(|key| !key.is_empty()))
+}
+
+/// Remove the `--tls-*` options from args and build the TLS settings
+#[cfg(feature = "tls")]
+fn take_tls(args: &mut Vec<String>) -> pdb::Result<Option<pdb::TlsClientConfig>> {
+    let ca = take_option(args, "--tls-ca");
+    let pin = take_option(args, "--tls-pin");
+    let cert = take_option(args, "--tls-cert");
+    let key = take_option(args, "--tls-key");
+    let name = take_option(args, "--tls-name");
+
+    let mut tls = match (ca, pin) {
+        (Some(ca), None) => pdb::TlsClientConfig::with_ca_pem(&std::fs::read(ca)?)?,
+        (None, Some(pin)) => pdb::TlsClientConfig::pinned(&pin)?,
+        (None, None) => return Ok(None),
+        (Some(_), Some(_)) => {
+            return Err(pdb::PdbError::TlsError(
+                "--tls-ca and --tls-pin are mutually exclusive".into(),
+            ))
+        }
+    };
+    match (cert, key) {
+        (Some(cert), Some(key)) => {
+            tls = tls.with_client_cert_pem(&std::fs::read(cert)?, &std::fs::read(key)?)?;
+        }
+        (None, None) => {}
+        _ => {
+            return Err(pdb::PdbError::TlsError(
+                "--tls-cert and --tls-key go together".into(),
+            ))
+        }
+    }
+    if let Some(name) = name {
+        tls = tls.with_server_name(&name);
+    }
+    Ok(Some(tls))
+}
+
+/// Filter out --local and --json flags from args
 fn filter_args(args: &[String]) -> Vec<String> {
     args.iter()
-        .filter(|a| *a != "--local" && *a != "-l")
+        .filter(|a| *a != "--local" && *a != "-l" && *a != "--json")
         .cloned()
         .collect()
 }
@@ -20,8 +82,19 @@ fn filter_args(args: &[String]) -> Vec<String> {
 async fn main() -> pdb::Result<()> {
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
     let local_mode = is_local_mode(&args);
+    let mut options = ClientOptions::default();
+    if is_json_mode(&args) {
+        options = options.with_encoding(Encoding::Json);
+    }
+    if let Some(key) = take_key(&mut args) {
+        options = options.with_key(key);
+    }
+    #[cfg(feature = "tls")]
+    if let Some(tls) = take_tls(&mut args)? {
+        options = options.with_tls(tls);
+    }
     let args = filter_args(&args);
 
     if args.len() < 2 {
@@ -34,7 +107,7 @@ async fn main() -> pdb::Result<()> {
     if local_mode {
         run_local_command(command, &args).await
     } else {
-        run_remote_command(command, &args).await
+        run_remote_command(command, &args, &options).await
     }
 }
 
@@ -47,7 +120,7 @@ async fn run_local_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let windows = controller.list_windows()?;
             
             println!("List of Windows (Local):");
-            println!("{:<20} {:<60} {}", "HWND", "Title", "Class");
+            println!("{:<20} {:<60} Class", "HWND", "Title");
             println!("{}", "-".repeat(100));
             for window in windows {
                 println!("{:<20} {:<60} {}", 
@@ -144,6 +217,18 @@ async fn run_local_command(command: &str, args: &[String]) -> pdb::Result<()> {
             println!("Screenshot saved to: {}", output_path);
         }
         
+        "rpc" => {
+            // Responses go to stdout, logs to stderr
+            let rpc = RpcServer::local(controller);
+            rpc.serve(tokio::io::stdin(), tokio::io::stdout()).await?;
+        }
+        
+        #[cfg(feature = "mcp")]
+        "mcp" => {
+            let mcp = pdb::mcp::McpServer::local(controller);
+            mcp.serve(tokio::io::stdin(), tokio::io::stdout()).await?;
+        }
+        
         "coord" | "mouse" => {
             if args.len() < 3 {
                 println!("Usage: pdb-client --local coord <hwnd>");
@@ -163,19 +248,16 @@ async fn run_local_command(command: &str, args: &[String]) -> pdb::Result<()> {
             
             let mut last_inside = false;
             loop {
-                match device.get_cursor_pos() {
-                    Ok((x, y)) => {
-                        let inside = x >= 0 && y >= 0 && x < width && y < height;
-                        if inside {
-                            print!("\r{:>8}  {:>8}  {:>10}", x, y, "IN WINDOW");
-                            last_inside = true;
-                        } else if last_inside {
-                            print!("\r{:>8}  {:>8}  {:>10}", "-", "-", "OUTSIDE  ");
-                            last_inside = false;
-                        }
-                        std::io::Write::flush(&mut std::io::stdout()).ok();
+                if let Ok((x, y)) = device.get_cursor_pos() {
+                    let inside = x >= 0 && y >= 0 && x < width && y < height;
+                    if inside {
+                        print!("\r{:>8}  {:>8}  {:>10}", x, y, "IN WINDOW");
+                        last_inside = true;
+                    } else if last_inside {
+                        print!("\r{:>8}  {:>8}  {:>10}", "-", "-", "OUTSIDE  ");
+                        last_inside = false;
                     }
-                    Err(_) => {}
+                    std::io::Write::flush(&mut std::io::stdout()).ok();
                 }
                 std::thread::sleep(std::time::Duration::from_millis(50));
             }
@@ -190,15 +272,19 @@ async fn run_local_command(command: &str, args: &[String]) -> pdb::Result<()> {
 }
 
 /// Run command in remote mode (requires server)
-async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
+async fn run_remote_command(
+    command: &str,
+    args: &[String],
+    options: &ClientOptions,
+) -> pdb::Result<()> {
     match command {
         "devices" | "list" => {
             let addr = get_addr(args, 2);
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let windows = client.list_windows().await?;
             
             println!("List of Windows (Remote: {}):", addr);
-            println!("{:<20} {:<60} {}", "HWND", "Title", "Class");
+            println!("{:<20} {:<60} Class", "HWND", "Title");
             println!("{}", "-".repeat(100));
             for window in windows {
                 println!("{:<20} {:<60} {}", 
@@ -216,7 +302,7 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             }
             let title = &args[2];
             let addr = get_addr(args, 3);
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window(title).await?;
             println!("Connected to: {} (HWND: 0x{:X})", device.info().title, device.hwnd());
         }
@@ -231,7 +317,7 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let y: i32 = args[4].parse().expect("Invalid y coordinate");
             let addr = get_addr(args, 5);
             
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window_by_hwnd(hwnd).await?;
             device.click(x, y).await?;
             println!("Clicked at ({}, {})", x, y);
@@ -250,7 +336,7 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let duration_ms: u32 = args.get(7).and_then(|s| s.parse().ok()).unwrap_or(500);
             let addr = get_addr(args, 8);
             
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window_by_hwnd(hwnd).await?;
             device.swipe(x1, y1, x2, y2, duration_ms).await?;
             println!("Swiped from ({}, {}) to ({}, {})", x1, y1, x2, y2);
@@ -265,7 +351,7 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let text = &args[3];
             let addr = get_addr(args, 4);
             
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window_by_hwnd(hwnd).await?;
             device.input_text(text).await?;
             println!("Input text: {}", text);
@@ -280,7 +366,7 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let key = parse_keycode(&args[3])?;
             let addr = get_addr(args, 4);
             
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window_by_hwnd(hwnd).await?;
             device.key_event(key).await?;
             println!("Sent key event: {:?}", key);
@@ -295,16 +381,17 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             let output_path = &args[3];
             let addr = get_addr(args, 4);
             
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             let device = client.connect_window_by_hwnd(hwnd).await?;
-            let screenshot = device.screenshot().await?;
+            // Lossless and much smaller than raw pixels on the wire
+            let screenshot = device.screenshot_with(ScreenshotFormat::Png, None).await?;
             screenshot.save(output_path)?;
             println!("Screenshot saved to: {}", output_path);
         }
         
         "ping" => {
             let addr = get_addr(args, 2);
-            let client = Client::connect(&addr).await?;
+            let client = Client::connect_with(&addr, options.clone()).await?;
             if client.ping().await? {
                 println!("Server is alive");
             } else {
@@ -312,6 +399,56 @@ async fn run_remote_command(command: &str, args: &[String]) -> pdb::Result<()> {
             }
         }
         
+        "sessions" => {
+            let addr = get_addr(args, 2);
+            let client = Client::connect_with(&addr, options.clone()).await?;
+            let sessions = client.list_sessions().await?;
+            
+            println!("Sessions (Remote: {}):", addr);
+            println!("{:<8} {:<10} {:<30} Devices", "ID", "Endpoint", "Peer");
+            println!("{}", "-".repeat(70));
+            for session in sessions {
+                let devices: Vec<String> =
+                    session.devices.iter().map(|hwnd| format!("0x{:X}", hwnd)).collect();
+                println!("{:<8} {:<10} {:<30} {}", 
+                    session.id,
+                    session.endpoint,
+                    session.peer,
+                    devices.join(", ")
+                );
+            }
+        }
+        
+        "rpc" => {
+            let addr = get_addr(args, 2);
+            let client = Client::connect_with(&addr, options.clone()).await?;
+            let rpc = RpcServer::remote(client);
+            rpc.serve(tokio::io::stdin(), tokio::io::stdout()).await?;
+        }
+        
+        #[cfg(feature = "mcp")]
+        "mcp" => {
+            let addr = get_addr(args, 2);
+            let client = Client::connect_with(&addr, options.clone()).await?;
+            let mcp = pdb::mcp::McpServer::remote(client);
+            mcp.serve(tokio::io::stdin(), tokio::io::stdout()).await?;
+        }
+        
+        "info" => {
+            let addr = get_addr(args, 2);
+            let client = Client::connect_with(&addr, options.clone()).await?;
+            let caps = client.capabilities();
+            println!("Server:    {} ({})", caps.agent, addr);
+            println!("Protocol:  {}", caps.version);
+            println!("Backend:   {} on {}", caps.backend, caps.os);
+            match caps.screen_size {
+                Some((width, height)) => println!("Screen:    {}x{}", width, height),
+                None => println!("Screen:    unknown"),
+            }
+            println!("Encodings: {}", caps.encodings.join(", "));
+            println!("Commands:  {}", caps.commands.join(", "));
+        }
+        
         _ => {
             print_usage();
         }
@@ -328,6 +465,13 @@ fn print_usage() {
     println!("Modes:");
     println!("  --local, -l                             Run in local mode (no server required)");
     println!("  (default)                               Connect to remote server");
+    println!("  --json                                  Use the JSON wire encoding (debugging)");
+    println!("  --key <key>                             Key for servers that require one");
+    println!("                                          (default: $PDB_KEY)");
+    println!("  --tls-ca <pem>                          Use TLS, trusting servers signed by this CA");
+    println!("  --tls-pin <sha256>                      Use TLS, trusting only this server certificate");
+    println!("  --tls-cert <pem> --tls-key <pem>        Client certificate for mutual TLS");
+    println!("  --tls-name <name>                       Name expected in the server certificate");
     println!();
     println!("Commands:");
     println!("  devices|list [server_addr]              List all windows");
@@ -340,6 +484,10 @@ fn print_usage() {
     println!("  screenshot <hwnd> <path> [server_addr]  Take screenshot");
     println!("  coord|mouse <hwnd>                      Track mouse position (local only)");
     println!("  ping [server_addr]                      Ping server (remote only)");
+    println!("  info [server_addr]                      Show server capabilities (remote only)");
+    println!("  sessions [server_addr]                  List the server's sessions (remote only)");
+    println!("  rpc [server_addr]                       Answer JSON-RPC 2.0 requests on stdin/stdout");
+    println!("  mcp [server_addr]                       Serve windows as MCP tools on stdin/stdout");
     println!();
     println!("Examples:");
     println!("  pdb-client --local devices              List windows locally");
@@ -347,6 +495,7 @@ fn print_usage() {
     println!("  pdb-client --local coord 0x12345        Track mouse in window");
     println!("  pdb-client devices                      List windows via server");
     println!("  pdb-client devices 192.168.1.100:5037   List windows on remote machine");
+    println!("  pdb-client devices unix:/tmp/pdb.sock   List windows via a local Unix socket");
     println!();
     println!("Default server address: 127.0.0.1:5037");
     println!();
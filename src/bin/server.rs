@@ -1,22 +1,273 @@
 //! PDB Server binary - runs the remote control server
 
+use pdb::policy::PolicyFile;
 use pdb::Server;
-use log::info;
+use log::{info, warn};
+
+/// Default address for the RFB (VNC) endpoint
+#[cfg(feature = "vnc")]
+const DEFAULT_VNC_ADDR: &str = "127.0.0.1:5900";
+
+/// Command line options
+#[derive(Debug, Default)]
+struct Options {
+    addr: Option<String>,
+    /// Title of the window to serve over RFB
+    vnc_window: Option<String>,
+    vnc_addr: Option<String>,
+    vnc_password: Option<String>,
+    /// Keys clients must authenticate with
+    keys: Vec<String>,
+    /// TLS certificate chain and private key (PEM files)
+    tls_cert: Option<String>,
+    tls_key: Option<String>,
+    /// CA for client certificates (PEM file)
+    tls_client_ca: Option<String>,
+    /// WebSocket listen address
+    ws_addr: Option<String>,
+    /// Origins allowed to open WebSockets
+    ws_origins: Vec<String>,
+    /// HTTP REST gateway listen address
+    http_addr: Option<String>,
+    /// Largest frame accepted from clients, in bytes
+    max_frame: Option<u32>,
+    /// Only let clients observe windows
+    read_only: bool,
+    /// Policy file (JSON)
+    policy: Option<String>,
+}
+
+fn parse_args() -> Options {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vnc" => options.vnc_window = args.next(),
+            "--vnc-addr" => options.vnc_addr = args.next(),
+            "--vnc-password" => options.vnc_password = args.next(),
+            "--key" => options.keys.extend(args.next()),
+            "--tls-cert" => options.tls_cert = args.next(),
+            "--tls-key" => options.tls_key = args.next(),
+            "--tls-client-ca" => options.tls_client_ca = args.next(),
+            "--ws" => options.ws_addr = args.next(),
+            "--ws-origin" => options.ws_origins.extend(args.next()),
+            "--http" => options.http_addr = args.next(),
+            "--read-only" => options.read_only = true,
+            "--policy" => options.policy = args.next(),
+            "--max-frame" => match args.next().map(|bytes| bytes.parse()) {
+                Some(Ok(bytes)) => options.max_frame = Some(bytes),
+                Some(Err(e)) => {
+                    eprintln!("Invalid --max-frame: {}", e);
+                    std::process::exit(1);
+                }
+                None => {}
+            },
+            "--key-file" => match args.next().map(std::fs::read_to_string) {
+                Some(Ok(key)) => options.keys.push(key.trim_end().to_string()),
+                Some(Err(e)) => {
+                    eprintln!("Cannot read key file: {}", e);
+                    std::process::exit(1);
+                }
+                None => {}
+            },
+            "-h" | "--help" => {
+                print_usage();
+                std::process::exit(0);
+            }
+            _ => options.addr = Some(arg),
+        }
+    }
+    if let Ok(key) = std::env::var("PDB_KEY") {
+        if !key.is_empty() {
+            options.keys.push(key);
+        }
+    }
+    options
+}
+
+fn print_usage() {
+    println!("Usage: pdb-server [addr] [--key <key>] [--key-file <path>] [--vnc <window_title>] [--vnc-addr <addr>] [--vnc-password <password>]");
+    println!();
+    println!("  addr              PDB listen address (default 0.0.0.0:{})", pdb::DEFAULT_PORT);
+    println!("                    or unix:/path/to.sock for a local-only Unix socket");
+    println!("  --key <key>       Require clients to authenticate with this key (repeatable;");
+    println!("                    also read from $PDB_KEY)");
+    println!("  --key-file <path> Read a key from a file");
+    println!("  --ws <addr>       Also accept WebSocket clients (browsers) on this address");
+    println!("  --ws-origin <origin>");
+    println!("                    Accept WebSockets from pages of this origin (repeatable;");
+    println!("                    without it only non-browser clients may connect)");
+    println!("  --http <addr>     Also answer HTTP REST requests on this address");
+    println!("  --read-only       Only let clients take screenshots and list windows");
+    println!("  --policy <file>   Restrict commands and windows as described in this JSON file,");
+    println!("                    which may also grant keys their own policy");
+    println!("  --max-frame <bytes>");
+    println!("                    Largest message accepted from clients (default 16 MiB)");
+    println!("  --tls-cert <pem> --tls-key <pem>");
+    println!("                    Only accept TLS connections, with this certificate");
+    println!("  --tls-client-ca <pem>");
+    println!("                    Require client certificates signed by this CA");
+    println!("  --vnc <title>     Also serve this window to VNC viewers (view-only if the");
+    println!("                    policy denies input)");
+    println!("  --vnc-addr <addr> VNC listen address (default 127.0.0.1:5900, this machine only)");
+    println!("  --vnc-password <password>");
+    println!("                    Require VNC authentication with this password (needed");
+    println!("                    when clients must authenticate with a key)");
+}
+
+/// Serve a window over VNC; `has_keys` tells whether pdb clients must
+/// authenticate, in which case viewers must too
+#[cfg(feature = "vnc")]
+fn start_vnc(title: &str, options: &Options, server: &Server, has_keys: bool) -> pdb::Result<()> {
+    // Viewers are held to the server's policy and leases
+    let mut rfb = server.rfb_server_for_title(title)?;
+    let addr = options
+        .vnc_addr
+        .clone()
+        .unwrap_or_else(|| DEFAULT_VNC_ADDR.to_string());
+    match &options.vnc_password {
+        Some(password) => rfb = rfb.with_password(password),
+        // Keys do not apply to viewers, so the password stands in for them
+        None if has_keys => {
+            return Err(pdb::PdbError::AuthenticationFailed(
+                "--vnc needs --vnc-password when clients must authenticate with a key".to_string(),
+            ))
+        }
+        None => warn!("No VNC password: anyone who can reach {} can watch and control \"{}\"", addr, title),
+    }
+    let listener = std::net::TcpListener::bind(&addr)?;
+    info!("Serving \"{}\" over VNC on {}", rfb.device().info().title, addr);
+    std::thread::spawn(move || rfb.serve(listener));
+    Ok(())
+}
+
+#[cfg(not(feature = "vnc"))]
+fn start_vnc(_title: &str, _options: &Options, _server: &Server, _has_keys: bool) -> pdb::Result<()> {
+    Err(pdb::PdbError::Unsupported(
+        "pdb-server was built without the vnc feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "tls")]
+fn tls_config(options: &Options) -> pdb::Result<Option<pdb::TlsServerConfig>> {
+    let (cert, key) = match (&options.tls_cert, &options.tls_key) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if options.tls_client_ca.is_none() => return Ok(None),
+        _ => {
+            return Err(pdb::PdbError::TlsError(
+                "--tls-cert and --tls-key are both required for TLS".to_string(),
+            ))
+        }
+    };
+    let mut tls = pdb::TlsServerConfig::from_pem_files(cert, key)?;
+    if let Some(ca) = &options.tls_client_ca {
+        tls = tls.with_client_ca_pem(&std::fs::read(ca)?)?;
+    }
+    info!("TLS certificate fingerprint: {}", tls.fingerprint());
+    Ok(Some(tls))
+}
+
+#[cfg(not(feature = "tls"))]
+fn tls_config(options: &Options) -> pdb::Result<Option<()>> {
+    match (&options.tls_cert, &options.tls_key, &options.tls_client_ca) {
+        (None, None, None) => Ok(None),
+        _ => Err(pdb::PdbError::Unsupported(
+            "pdb-server was built without the tls feature".to_string(),
+        )),
+    }
+}
+
+#[cfg(feature = "websocket")]
+async fn start_websocket(server: &Server, addr: Option<&str>) -> pdb::Result<()> {
+    match addr {
+        Some(addr) => server.start_websocket(addr).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "websocket"))]
+async fn start_websocket(_server: &Server, addr: Option<&str>) -> pdb::Result<()> {
+    match addr {
+        Some(_) => Err(pdb::PdbError::Unsupported(
+            "pdb-server was built without the websocket feature".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "http")]
+async fn start_http(server: &Server, addr: Option<&str>) -> pdb::Result<()> {
+    match addr {
+        Some(addr) => server.start_http(addr).await,
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(feature = "http"))]
+async fn start_http(_server: &Server, addr: Option<&str>) -> pdb::Result<()> {
+    match addr {
+        Some(_) => Err(pdb::PdbError::Unsupported(
+            "pdb-server was built without the http feature".to_string(),
+        )),
+        None => Ok(()),
+    }
+}
 
 #[tokio::main]
 async fn main() -> pdb::Result<()> {
     // Initialize logger
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-    let args: Vec<String> = std::env::args().collect();
-    
-    let addr = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        format!("0.0.0.0:{}", pdb::DEFAULT_PORT)
-    };
+    let options = parse_args();
+
+    let addr = options
+        .addr
+        .clone()
+        .unwrap_or_else(|| format!("0.0.0.0:{}", pdb::DEFAULT_PORT));
 
     info!("Starting PDB Server on {}", addr);
-    let server = Server::new(&addr);
-    server.start().await
+    let mut server = Server::new(&addr);
+    let mut policy = match &options.policy {
+        Some(path) => PolicyFile::load(path)?,
+        None => PolicyFile::default(),
+    };
+    // Keys of the policy file keep their own policy
+    policy.policy.read_only |= options.read_only;
+    let has_keys = !options.keys.is_empty() || !policy.keys.is_empty();
+    // A Unix socket is only reachable by this user
+    if !has_keys && !addr.starts_with(pdb::transport::UNIX_PREFIX) {
+        warn!("No key configured: anyone who can reach {} can control this machine", addr);
+    }
+    for key in &options.keys {
+        server = server.with_key(key.as_bytes());
+    }
+    for key in &policy.keys {
+        server = server.with_key_policy(key.key()?, key.policy.clone());
+    }
+    server = server.with_policy(policy.policy);
+    if let Some(bytes) = options.max_frame {
+        server = server.with_max_frame(bytes);
+    }
+    #[cfg(feature = "tls")]
+    if let Some(tls) = tls_config(&options)? {
+        server = server.with_tls(tls);
+    }
+    #[cfg(not(feature = "tls"))]
+    tls_config(&options)?;
+
+    #[cfg(feature = "websocket")]
+    for origin in &options.ws_origins {
+        server = server.with_websocket_origin(origin);
+    }
+
+    if let Some(title) = &options.vnc_window {
+        start_vnc(title, &options, &server, has_keys)?;
+    }
+
+    tokio::try_join!(
+        server.start(),
+        start_websocket(&server, options.ws_addr.as_deref()),
+        start_http(&server, options.http_addr.as_deref()),
+    )?;
+    Ok(())
 }
@@ -1,25 +1,344 @@
 //! Client implementation for remote connections
 
+use crate::auth;
+use crate::delta::DeltaDecoder;
 use crate::error::{PdbError, Result};
-use crate::protocol::{Command, MessageHeader, Response, DEFAULT_PORT};
-use crate::types::{KeyCode, Screenshot, WindowInfo};
+use crate::framing::{self, FrameLimits};
+use crate::protocol::{
+    BatchStep, Capabilities, Command, Encoding, Hello, Response, DEFAULT_PORT,
+    HANDSHAKE_VERSION, MAX_LEASE_MS, REQUEST_ID_VERSION,
+};
+#[cfg(feature = "tls")]
+use crate::tls::TlsClientConfig;
+use crate::session::SessionInfo;
+use crate::transport::{self, Address, BoxedTransport, Transport};
+use crate::types::{KeyCode, Screenshot, ScreenshotFormat, WindowInfo};
+use log::debug;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::task::JoinHandle;
+use tokio_stream::Stream;
+
+/// Options for `Client::connect_with`
+#[derive(Clone)]
+pub struct ClientOptions {
+    /// Wire encodings to offer, most preferred first
+    pub encodings: Vec<Encoding>,
+    /// Key to answer the server's authentication challenge with
+    pub key: Option<Vec<u8>>,
+    /// Bounds for frames read from the server
+    pub limits: FrameLimits,
+    /// TLS settings, if the server uses TLS
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsClientConfig>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            encodings: Encoding::ALL.to_vec(),
+            key: None,
+            limits: FrameLimits::client(),
+            #[cfg(feature = "tls")]
+            tls: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ClientOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut options = f.debug_struct("ClientOptions");
+        options
+            .field("encodings", &self.encodings)
+            .field("key", &self.key.as_ref().map(|_| "<redacted>"))
+            .field("limits", &self.limits);
+        #[cfg(feature = "tls")]
+        options.field("tls", &self.tls);
+        options.finish()
+    }
+}
+
+impl ClientOptions {
+    /// Only offer one wire encoding (e.g. `Encoding::Json` for debugging)
+    pub fn with_encoding(mut self, encoding: Encoding) -> Self {
+        self.encodings = vec![encoding];
+        self
+    }
+
+    /// Authenticate with a key shared with the server
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+
+    /// Largest frame accepted from the server, in bytes
+    /// (default `framing::DEFAULT_CLIENT_MAX_FRAME`)
+    pub fn with_max_frame(mut self, bytes: u32) -> Self {
+        self.limits.max_frame = bytes;
+        self
+    }
+
+    /// Time a frame may take to arrive once it started (default
+    /// `framing::DEFAULT_READ_TIMEOUT`; `None` waits forever)
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.limits.read_timeout = timeout;
+        self
+    }
+
+    /// Connect over TLS
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsClientConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+}
+
+/// Last frame received per device, for delta screenshots
+type Decoders = Arc<std::sync::Mutex<HashMap<usize, DeltaDecoder>>>;
+
+/// Responses buffered between the connection and a subscription's decoder
+const STREAM_DEPTH: usize = 16;
+
+/// Where responses to a request go
+enum Route {
+    /// The single response
+    Reply(oneshot::Sender<Result<Response>>),
+    /// Every response until the route is removed (subscriptions)
+    Stream(mpsc::Sender<Response>),
+}
+
+/// Requests waiting for responses, by id (`None` once the connection closed)
+type Pending = Arc<std::sync::Mutex<Option<BTreeMap<u32, Route>>>>;
+
+/// One server connection, shared by a `Client` and its devices
+///
+/// Requests are tagged with increasing ids and a background task routes
+/// responses back by id, so any number of requests can be in flight at
+/// once. Servers before `REQUEST_ID_VERSION` answer strictly in order, so
+/// their responses go to the oldest pending request.
+struct Connection {
+    writer: Mutex<WriteHalf<BoxedTransport>>,
+    pending: Pending,
+    next_id: AtomicU32,
+    /// Protocol version agreed with the server
+    version: u32,
+    /// Wire encoding agreed with the server
+    encoding: Encoding,
+    /// Whether the server runs `Command::Batch`
+    batch: bool,
+    reader: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Start TLS if configured, handshake and start routing responses
+    ///
+    /// `host` is the name the server was reached by, for TLS verification.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    async fn open(
+        mut stream: BoxedTransport,
+        host: Option<&str>,
+        options: &ClientOptions,
+    ) -> Result<(Self, Capabilities)> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &options.tls {
+            stream = Box::new(tls.connect(host, stream).await?);
+        }
+        let capabilities = handshake(&mut stream, options).await?;
+        let encoding = Encoding::from_name(&capabilities.encoding).unwrap_or_default();
+        let (reader, writer) = tokio::io::split(stream);
+        let pending = Pending::new(std::sync::Mutex::new(Some(BTreeMap::new())));
+        let reader = tokio::spawn(route_responses(
+            reader,
+            encoding,
+            options.limits,
+            pending.clone(),
+        ));
+        let connection = Self {
+            writer: Mutex::new(writer),
+            pending,
+            next_id: AtomicU32::new(1),
+            version: capabilities.version,
+            encoding,
+            batch: capabilities.supports("Batch"),
+            reader,
+        };
+        Ok((connection, capabilities))
+    }
+
+    /// Write a request whose responses go to `route`, returning its id
+    async fn send(&self, command: &Command, route: Route) -> Result<u32> {
+        let body = self.encoding.encode(command)?;
+        let mut writer = self.writer.lock().await;
+        // Taken under the write lock, so requests go out in id order
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        match self.pending.lock().unwrap().as_mut() {
+            Some(routes) => routes.insert(id, route),
+            None => return Err(PdbError::ConnectionError("connection closed".into())),
+        };
+        let written = match framing::encode_frame(self.version, id, &body) {
+            Ok(frame) => framing::write_frame(&mut *writer, &frame).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            self.forget(id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    /// Send a command and wait for its response
+    async fn request(&self, command: &Command) -> Result<Response> {
+        let (sender, receiver) = oneshot::channel();
+        self.send(command, Route::Reply(sender)).await?;
+        receiver
+            .await
+            .map_err(|_| PdbError::ConnectionError("connection closed".into()))?
+    }
+
+    /// Stop routing responses for a request
+    fn forget(&self, id: u32) {
+        if let Some(routes) = self.pending.lock().unwrap().as_mut() {
+            routes.remove(&id);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Read responses and hand each to the request it answers
+async fn route_responses(
+    mut reader: ReadHalf<BoxedTransport>,
+    encoding: Encoding,
+    limits: FrameLimits,
+    pending: Pending,
+) {
+    let error = loop {
+        let (header, body) = match framing::read_frame(&mut reader, &limits).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break PdbError::ConnectionError("connection closed".into()),
+            Err(e) => break e,
+        };
+        // A response this version cannot read (e.g. from a newer server)
+        // only fails its request
+        let response = encoding
+            .decode(&body)
+            .unwrap_or_else(|e| Response::Error(e.into()));
+
+        let (id, route) = {
+            let mut pending = pending.lock().unwrap();
+            let Some(routes) = pending.as_mut() else { return };
+            let id = match header.has_id() {
+                true => header.id,
+                false => routes.keys().next().copied().unwrap_or_default(),
+            };
+            // A subscription's error is its last response
+            let last = matches!(response, Response::Error(_));
+            let route = match routes.get(&id) {
+                Some(Route::Stream(sender)) if !last => Some(Route::Stream(sender.clone())),
+                Some(Route::Stream(_)) => routes.remove(&id),
+                Some(Route::Reply(_)) => routes.remove(&id),
+                None => None,
+            };
+            (id, route)
+        };
+
+        match route {
+            Some(Route::Reply(sender)) => {
+                let _ = sender.send(Ok(response));
+            }
+            Some(Route::Stream(sender)) => match sender.try_send(response) {
+                Ok(()) => {}
+                // Waiting would hold up every other response, and dropping a
+                // delta would break the frames after it: end the stream
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    if let Some(routes) = pending.lock().unwrap().as_mut() {
+                        routes.remove(&id);
+                    }
+                    let error = PdbError::CaptureError(
+                        "frames arrived faster than they could be decoded".into(),
+                    );
+                    tokio::spawn(async move {
+                        let _ = sender.send(Response::Error(error.into())).await;
+                    });
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {
+                    if let Some(routes) = pending.lock().unwrap().as_mut() {
+                        routes.remove(&id);
+                    }
+                }
+            },
+            // E.g. frames still in flight after `Unsubscribe`
+            None => debug!("Dropping response to unknown request {}", id),
+        }
+    };
+
+    // Fail everything still waiting, and anything sent from now on
+    let routes = pending.lock().unwrap().take().unwrap_or_default();
+    for route in routes.into_values() {
+        if let Route::Reply(sender) = route {
+            let _ = sender.send(Err(PdbError::ConnectionError(error.to_string())));
+        }
+    }
+}
 
 /// Remote client - connects to PDB server (like ADB client)
+///
+/// The client and all its `RemoteDevice`s share one connection; commands
+/// from several tasks are pipelined over it and run concurrently on the
+/// server when they target different windows.
 pub struct Client {
-    stream: Arc<Mutex<TcpStream>>,
+    connection: Arc<Connection>,
+    capabilities: Capabilities,
+    decoders: Decoders,
 }
 
 impl Client {
     /// Connect to remote server
+    ///
+    /// Performs the `Hello` handshake; fails with `PdbError::VersionMismatch`
+    /// if the server speaks no compatible protocol version, and with
+    /// `PdbError::AuthenticationFailed` if it requires a key (see
+    /// `ClientOptions::with_key`).
     pub async fn connect(addr: &str) -> Result<Self> {
-        let stream = TcpStream::connect(addr).await?;
-        Ok(Self {
-            stream: Arc::new(Mutex::new(stream)),
-        })
+        Self::connect_with(addr, ClientOptions::default()).await
+    }
+
+    /// Connect to remote server with explicit options
+    ///
+    /// The address is `host:port` for TCP or `unix:/path/to.sock` for a Unix
+    /// domain socket.
+    pub async fn connect_with(addr: &str, options: ClientOptions) -> Result<Self> {
+        let addr = Address::parse(addr)?;
+        let stream = transport::connect(&addr).await?;
+        let (connection, capabilities) = Connection::open(stream, addr.host(), &options).await?;
+        Ok(Self::new(connection, capabilities))
+    }
+
+    /// Talk to a server over an already open byte stream
+    ///
+    /// Works with any `AsyncRead + AsyncWrite` stream; TLS from `options`
+    /// runs on top of it.
+    pub async fn connect_stream(stream: impl Transport, options: ClientOptions) -> Result<Self> {
+        let (connection, capabilities) = Connection::open(Box::new(stream), None, &options).await?;
+        Ok(Self::new(connection, capabilities))
+    }
+
+    fn new(connection: Connection, capabilities: Capabilities) -> Self {
+        Self {
+            connection: Arc::new(connection),
+            capabilities,
+            decoders: Decoders::default(),
+        }
     }
 
     /// Connect to localhost with default port
@@ -27,36 +346,34 @@ impl Client {
         Self::connect(&format!("127.0.0.1:{}", DEFAULT_PORT)).await
     }
 
-    /// Send command and receive response
-    async fn send_command(&self, command: Command) -> Result<Response> {
-        let mut stream = self.stream.lock().await;
-
-        // Serialize command
-        let command_json = serde_json::to_vec(&command)?;
-        let header = MessageHeader::new(command_json.len() as u32);
-
-        // Send header
-        let mut header_buf = [0u8; 8];
-        header_buf[0..4].copy_from_slice(&header.version.to_le_bytes());
-        header_buf[4..8].copy_from_slice(&header.length.to_le_bytes());
-        stream.write_all(&header_buf).await?;
+    /// Capabilities the server announced during the handshake
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
 
-        // Send body
-        stream.write_all(&command_json).await?;
-        stream.flush().await?;
+    /// Protocol version agreed with the server
+    pub fn protocol_version(&self) -> u32 {
+        self.capabilities.version
+    }
 
-        // Read response header
-        let mut resp_header_buf = [0u8; 8];
-        stream.read_exact(&mut resp_header_buf).await?;
-        let resp_length = u32::from_le_bytes(resp_header_buf[4..8].try_into().unwrap());
+    /// Wire encoding agreed with the server
+    pub fn encoding(&self) -> Encoding {
+        self.connection.encoding
+    }
 
-        // Read response body
-        let mut resp_body = vec![0u8; resp_length as usize];
-        stream.read_exact(&mut resp_body).await?;
+    /// Send any command and return the server's response as is
+    ///
+    /// Use `RemoteDevice::subscribe` for frame streams.
+    pub async fn send_command(&self, command: Command) -> Result<Response> {
+        self.connection.request(&command).await
+    }
 
-        // Parse response
-        let response: Response = serde_json::from_slice(&resp_body)?;
-        Ok(response)
+    fn remote_device(&self, info: WindowInfo) -> RemoteDevice {
+        RemoteDevice {
+            connection: self.connection.clone(),
+            decoders: self.decoders.clone(),
+            info,
+        }
     }
 
     /// Ping server
@@ -71,7 +388,34 @@ impl Client {
     pub async fn list_windows(&self) -> Result<Vec<WindowInfo>> {
         match self.send_command(Command::ListWindows).await? {
             Response::Windows(windows) => Ok(windows),
-            Response::Error(e) => Err(PdbError::ConnectionError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::ConnectionError)),
+            _ => Err(PdbError::ProtocolError("Unexpected response".into())),
+        }
+    }
+
+    /// List the sessions open on the remote server
+    ///
+    /// Fails with `PdbError::PermissionDenied` unless the server's policy
+    /// for this client grants `admin` (see `crate::policy`).
+    pub async fn list_sessions(&self) -> Result<Vec<SessionInfo>> {
+        if !self.capabilities.supports("ListSessions") {
+            return Err(PdbError::Unsupported("server cannot list sessions".into()));
+        }
+        match self.send_command(Command::ListSessions).await? {
+            Response::Sessions(sessions) => Ok(sessions),
+            Response::Error(e) => Err(e.into_error(PdbError::ConnectionError)),
+            _ => Err(PdbError::ProtocolError("Unexpected response".into())),
+        }
+    }
+
+    /// Release every window this client connected to
+    ///
+    /// The connection stays open; `RemoteDevice`s from before have to be
+    /// connected again.
+    pub async fn disconnect(&self) -> Result<()> {
+        match self.send_command(Command::Disconnect).await? {
+            Response::Ok => Ok(()),
+            Response::Error(e) => Err(e.into_error(PdbError::ConnectionError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -79,11 +423,8 @@ impl Client {
     /// Connect to a window by title
     pub async fn connect_window(&self, title: &str) -> Result<RemoteDevice> {
         match self.send_command(Command::Connect { title: title.to_string() }).await? {
-            Response::Window(info) => Ok(RemoteDevice {
-                client: self.stream.clone(),
-                info,
-            }),
-            Response::Error(e) => Err(PdbError::WindowNotFound(e)),
+            Response::Window(info) => Ok(self.remote_device(info)),
+            Response::Error(e) => Err(e.into_error(PdbError::WindowNotFound)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -91,19 +432,74 @@ impl Client {
     /// Connect to a window by hwnd
     pub async fn connect_window_by_hwnd(&self, hwnd: usize) -> Result<RemoteDevice> {
         match self.send_command(Command::ConnectByHwnd { hwnd }).await? {
-            Response::Window(info) => Ok(RemoteDevice {
-                client: self.stream.clone(),
-                info,
-            }),
-            Response::Error(e) => Err(PdbError::WindowNotFound(e)),
+            Response::Window(info) => Ok(self.remote_device(info)),
+            Response::Error(e) => Err(e.into_error(PdbError::WindowNotFound)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
 }
 
+/// Exchange `Hello` messages (always in JSON), answer the server's challenge
+/// if it sends one, and return the server capabilities
+async fn handshake(stream: &mut BoxedTransport, options: &ClientOptions) -> Result<Capabilities> {
+    let hello = Hello {
+        encodings: options.encodings.iter().map(|e| e.name().to_string()).collect(),
+        ..Hello::default()
+    };
+    let min_version = hello.min_version;
+    let mut response = exchange(stream, &Command::Hello(hello), &options.limits).await;
+    if let Ok(Response::Challenge(challenge)) = &response {
+        let Some(key) = &options.key else {
+            return Err(PdbError::AuthenticationFailed("server requires a key".into()));
+        };
+        let proof = auth::sign(key, challenge);
+        response = match exchange(stream, &Command::Authenticate { proof }, &options.limits).await {
+            Ok(Response::Error(e)) => return Err(e.into_error(PdbError::AuthenticationFailed)),
+            other => other,
+        };
+    }
+    match response {
+        Ok(Response::Hello(capabilities)) if capabilities.version >= min_version => {
+            Ok(capabilities)
+        }
+        Ok(Response::Hello(capabilities)) => Err(PdbError::VersionMismatch(format!(
+            "server chose protocol {}, client needs at least {}",
+            capabilities.version, min_version
+        ))),
+        Ok(Response::Error(e)) => Err(e.into_error(PdbError::VersionMismatch)),
+        Ok(_) => Err(PdbError::ProtocolError("Unexpected response".into())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lease time in the protocol's milliseconds
+fn lease_ms(duration: Duration) -> u32 {
+    duration.as_millis().min(MAX_LEASE_MS.into()) as u32
+}
+
+/// Send a handshake command and read the response
+async fn exchange(
+    stream: &mut BoxedTransport,
+    command: &Command,
+    limits: &FrameLimits,
+) -> Result<Response> {
+    let body = Encoding::Json.encode(command)?;
+    framing::write_frame(stream, &framing::encode_frame(HANDSHAKE_VERSION, 0, &body)?).await?;
+    match framing::read_frame(stream, limits).await? {
+        Some((_, body)) => Encoding::Json.decode(&body),
+        // Servers predating the handshake cannot parse `Hello` and hang up
+        None => Err(PdbError::VersionMismatch(
+            "server closed the connection during the handshake (protocol 1 server?)".into(),
+        )),
+    }
+}
+
 /// Remote device - represents a window on the remote machine
 pub struct RemoteDevice {
-    client: Arc<Mutex<TcpStream>>,
+    /// Connection of the owning `Client`
+    connection: Arc<Connection>,
+    /// Shared with the owning `Client` (the server tracks frames per connection)
+    decoders: Decoders,
     info: WindowInfo,
 }
 
@@ -120,34 +516,14 @@ impl RemoteDevice {
 
     /// Send command helper
     async fn send_command(&self, command: Command) -> Result<Response> {
-        let mut stream = self.client.lock().await;
-
-        let command_json = serde_json::to_vec(&command)?;
-        let header = MessageHeader::new(command_json.len() as u32);
-
-        let mut header_buf = [0u8; 8];
-        header_buf[0..4].copy_from_slice(&header.version.to_le_bytes());
-        header_buf[4..8].copy_from_slice(&header.length.to_le_bytes());
-        stream.write_all(&header_buf).await?;
-        stream.write_all(&command_json).await?;
-        stream.flush().await?;
-
-        let mut resp_header_buf = [0u8; 8];
-        stream.read_exact(&mut resp_header_buf).await?;
-        let resp_length = u32::from_le_bytes(resp_header_buf[4..8].try_into().unwrap());
-
-        let mut resp_body = vec![0u8; resp_length as usize];
-        stream.read_exact(&mut resp_body).await?;
-
-        let response: Response = serde_json::from_slice(&resp_body)?;
-        Ok(response)
+        self.connection.request(&command).await
     }
 
     /// Click at position
     pub async fn click(&self, x: i32, y: i32) -> Result<()> {
         match self.send_command(Command::Click { hwnd: self.info.hwnd, x, y }).await? {
             Response::Ok => Ok(()),
-            Response::Error(e) => Err(PdbError::InputError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::InputError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -165,20 +541,88 @@ impl RemoteDevice {
             duration_ms,
         }).await? {
             Response::Ok => Ok(()),
-            Response::Error(e) => Err(PdbError::InputError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::InputError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
 
     /// Take screenshot
     pub async fn screenshot(&self) -> Result<Screenshot> {
-        match self.send_command(Command::Screenshot { hwnd: self.info.hwnd }).await? {
+        self.screenshot_with(ScreenshotFormat::Raw, None).await
+    }
+
+    /// Take screenshot, compressed in transit and optionally downscaled
+    ///
+    /// The server encodes the image in `format`; it is decoded back into
+    /// RGBA pixels here. Repeated screenshots only transfer the tiles that
+    /// changed since the previous one.
+    pub async fn screenshot_with(
+        &self,
+        format: ScreenshotFormat,
+        scale: Option<f32>,
+    ) -> Result<Screenshot> {
+        let hwnd = self.info.hwnd;
+        let base = self.decoders.lock().unwrap().get(&hwnd).map_or(0, DeltaDecoder::seq);
+        let command = Command::Screenshot { hwnd, format, scale, base: Some(base) };
+        match self.send_command(command).await? {
+            Response::Frame(frame) => {
+                self.decoders.lock().unwrap().entry(hwnd).or_default().apply(frame)
+            }
             Response::Screenshot(s) => Ok(s),
-            Response::Error(e) => Err(PdbError::CaptureError(e)),
+            Response::Image(image) => image.decode(),
+            Response::Error(e) => Err(e.into_error(PdbError::CaptureError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
 
+    /// Subscribe to a live stream of frames at `fps` frames per second
+    ///
+    /// Frames arrive as deltas against the previous frame, interleaved with
+    /// other responses on the connection. At most one decoded frame is
+    /// buffered: frames that arrive while it is still unread are dropped
+    /// (see `FrameStream::dropped`). Should frames arrive faster than they
+    /// can even be decoded, the stream ends with an error instead of holding
+    /// up the connection. Dropping the stream unsubscribes. Needs a server
+    /// speaking `REQUEST_ID_VERSION`.
+    pub async fn subscribe(
+        &self,
+        fps: u32,
+        format: ScreenshotFormat,
+        scale: Option<f32>,
+    ) -> Result<FrameStream> {
+        if self.connection.version < REQUEST_ID_VERSION {
+            return Err(PdbError::Unsupported(format!(
+                "subscriptions need protocol {}, server speaks {}",
+                REQUEST_ID_VERSION, self.connection.version
+            )));
+        }
+
+        let command = Command::Subscribe { hwnd: self.info.hwnd, fps, format, scale, delta: true };
+        let (sender, mut responses) = mpsc::channel(STREAM_DEPTH);
+        let id = self.connection.send(&command, Route::Stream(sender)).await?;
+        let accepted = match responses.recv().await {
+            Some(Response::Ok) => Ok(()),
+            Some(Response::Error(e)) => Err(e.into_error(PdbError::CaptureError)),
+            Some(_) => Err(PdbError::ProtocolError("Unexpected response".into())),
+            None => Err(PdbError::ConnectionError("connection closed".into())),
+        };
+        if let Err(e) = accepted {
+            self.connection.forget(id);
+            return Err(e);
+        }
+
+        let (sender, receiver) = mpsc::channel(1);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let task = tokio::spawn(receive_frames(responses, sender, dropped.clone()));
+        Ok(FrameStream {
+            receiver,
+            dropped,
+            task,
+            connection: self.connection.clone(),
+            id,
+        })
+    }
+
     /// Input text
     pub async fn input_text(&self, text: &str) -> Result<()> {
         match self.send_command(Command::InputText {
@@ -186,7 +630,7 @@ impl RemoteDevice {
             text: text.to_string(),
         }).await? {
             Response::Ok => Ok(()),
-            Response::Error(e) => Err(PdbError::InputError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::InputError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -195,7 +639,7 @@ impl RemoteDevice {
     pub async fn key_event(&self, key: KeyCode) -> Result<()> {
         match self.send_command(Command::KeyEvent { hwnd: self.info.hwnd, key }).await? {
             Response::Ok => Ok(()),
-            Response::Error(e) => Err(PdbError::InputError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::InputError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -204,7 +648,7 @@ impl RemoteDevice {
     pub async fn get_size(&self) -> Result<(i32, i32)> {
         match self.send_command(Command::GetSize { hwnd: self.info.hwnd }).await? {
             Response::Size { width, height } => Ok((width, height)),
-            Response::Error(e) => Err(PdbError::HandleError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::HandleError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
@@ -213,8 +657,234 @@ impl RemoteDevice {
     pub async fn focus(&self) -> Result<()> {
         match self.send_command(Command::Focus { hwnd: self.info.hwnd }).await? {
             Response::Ok => Ok(()),
-            Response::Error(e) => Err(PdbError::HandleError(e)),
+            Response::Error(e) => Err(e.into_error(PdbError::HandleError)),
             _ => Err(PdbError::ProtocolError("Unexpected response".into())),
         }
     }
+
+    /// Take this window for this client alone
+    ///
+    /// Until the lease is released, ends after `duration` without a renewal
+    /// or the client disconnects, input from other clients fails with
+    /// `PdbError::DeviceLeased`; screenshots stay shared. If another client
+    /// holds the lease, waits up to `wait` for it to end and then fails the
+    /// same way. Durations are capped at `protocol::MAX_LEASE_MS`.
+    pub async fn acquire_lease(&self, duration: Duration, wait: Duration) -> Result<()> {
+        let command = Command::AcquireLease {
+            hwnd: self.info.hwnd,
+            duration_ms: lease_ms(duration),
+            wait_ms: lease_ms(wait),
+        };
+        self.lease_command(command).await
+    }
+
+    /// Extend this client's lease on the window to `duration` from now
+    pub async fn renew_lease(&self, duration: Duration) -> Result<()> {
+        let command = Command::RenewLease {
+            hwnd: self.info.hwnd,
+            duration_ms: lease_ms(duration),
+        };
+        self.lease_command(command).await
+    }
+
+    /// End this client's lease on the window
+    pub async fn release_lease(&self) -> Result<()> {
+        self.lease_command(Command::ReleaseLease { hwnd: self.info.hwnd }).await
+    }
+
+    async fn lease_command(&self, command: Command) -> Result<()> {
+        match self.send_command(command).await? {
+            Response::Ok => Ok(()),
+            Response::Error(e) => Err(e.into_error(PdbError::ProtocolError)),
+            _ => Err(PdbError::ProtocolError("Unexpected response".into())),
+        }
+    }
+
+    /// Start a batch of commands for this window, sent in one round trip
+    ///
+    /// ```rust,no_run
+    /// # async fn run(device: pdb::RemoteDevice) -> pdb::Result<()> {
+    /// use pdb::KeyCode;
+    /// use std::time::Duration;
+    ///
+    /// let responses = device
+    ///     .batch()
+    ///     .click(100, 40)
+    ///     .wait(Duration::from_millis(200))
+    ///     .input_text("hello")
+    ///     .key_event(KeyCode::Enter)
+    ///     .stop_on_error(true)
+    ///     .send()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn batch(&self) -> Batch<'_> {
+        Batch {
+            device: self,
+            steps: Vec::new(),
+            delay_ms: 0,
+            stop_on_error: false,
+        }
+    }
+}
+
+/// Commands for one window, run by the server in order (`RemoteDevice::batch`)
+pub struct Batch<'a> {
+    device: &'a RemoteDevice,
+    steps: Vec<BatchStep>,
+    /// Delay before the next step
+    delay_ms: u32,
+    stop_on_error: bool,
+}
+
+impl Batch<'_> {
+    /// Add any command; it need not be for this window
+    pub fn command(mut self, command: Command) -> Self {
+        self.steps.push(BatchStep { delay_ms: self.delay_ms, command });
+        self.delay_ms = 0;
+        self
+    }
+
+    /// Wait before the next step (waits add up)
+    pub fn wait(mut self, duration: Duration) -> Self {
+        let ms = u32::try_from(duration.as_millis()).unwrap_or(u32::MAX);
+        self.delay_ms = self.delay_ms.saturating_add(ms);
+        self
+    }
+
+    /// Skip the remaining steps after the first one that fails
+    pub fn stop_on_error(mut self, stop: bool) -> Self {
+        self.stop_on_error = stop;
+        self
+    }
+
+    /// Click at position
+    pub fn click(self, x: i32, y: i32) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::Click { hwnd, x, y })
+    }
+
+    /// Swipe from one position to another
+    pub fn swipe(self, x1: i32, y1: i32, x2: i32, y2: i32, duration_ms: u32) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::Swipe { hwnd, x1, y1, x2, y2, duration_ms })
+    }
+
+    /// Input text
+    pub fn input_text(self, text: &str) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::InputText { hwnd, text: text.to_string() })
+    }
+
+    /// Send key event
+    pub fn key_event(self, key: KeyCode) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::KeyEvent { hwnd, key })
+    }
+
+    /// Focus window
+    pub fn focus(self) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::Focus { hwnd })
+    }
+
+    /// Take a screenshot, replied to with `Response::Image` (or
+    /// `Response::Screenshot` for `Raw`)
+    pub fn screenshot(self, format: ScreenshotFormat, scale: Option<f32>) -> Self {
+        let hwnd = self.device.info.hwnd;
+        self.command(Command::Screenshot { hwnd, format, scale, base: None })
+    }
+
+    /// Run the batch and return one response per step that ran
+    ///
+    /// A failing step is answered with `Response::Error` in its place; with
+    /// `stop_on_error` it is the last response. A wait after the last step
+    /// is ignored.
+    pub async fn send(self) -> Result<Vec<Response>> {
+        if !self.device.connection.batch {
+            return Err(PdbError::Unsupported("server does not run batches".into()));
+        }
+        let command = Command::Batch {
+            steps: self.steps,
+            stop_on_error: self.stop_on_error,
+        };
+        match self.device.send_command(command).await? {
+            Response::Batch(responses) => Ok(responses),
+            Response::Error(e) => Err(e.into_error(PdbError::ProtocolError)),
+            _ => Err(PdbError::ProtocolError("Unexpected response".into())),
+        }
+    }
+}
+
+/// Live frames from `RemoteDevice::subscribe`
+///
+/// Yields decoded screenshots until the server ends the stream; a capture
+/// failure on the server is yielded as the last item.
+pub struct FrameStream {
+    receiver: mpsc::Receiver<Result<Screenshot>>,
+    dropped: Arc<AtomicU64>,
+    task: JoinHandle<()>,
+    connection: Arc<Connection>,
+    /// Request id of the `Subscribe` command
+    id: u32,
+}
+
+impl FrameStream {
+    /// Number of frames dropped because the consumer was not keeping up
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Screenshot>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.connection.forget(self.id);
+        // Tell the server to stop capturing, if the runtime is still up
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let connection = self.connection.clone();
+            let command = Command::Unsubscribe { id: self.id };
+            runtime.spawn(async move {
+                let _ = connection.request(&command).await;
+            });
+        }
+    }
+}
+
+/// Decode a subscription's frames and hand them to a `FrameStream`
+async fn receive_frames(
+    mut responses: mpsc::Receiver<Response>,
+    sender: mpsc::Sender<Result<Screenshot>>,
+    dropped: Arc<AtomicU64>,
+) {
+    // Every frame is applied, even those the consumer never sees
+    let mut decoder = DeltaDecoder::new();
+    // Ends when the connection closes
+    while let Some(response) = responses.recv().await {
+        let frame = match response {
+            Response::Frame(frame) => decoder.apply(frame),
+            Response::Error(e) => Err(e.into_error(PdbError::CaptureError)),
+            _ => Err(PdbError::ProtocolError("Unexpected response".into())),
+        };
+        if frame.is_err() {
+            let _ = sender.send(frame).await;
+            return;
+        }
+        match sender.try_send(frame) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => return,
+        }
+    }
 }
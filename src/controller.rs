@@ -1,36 +1,36 @@
 //! Window controller module
 
+use crate::backend::{self, Backend};
+use crate::device::Device;
 use crate::error::{PdbError, Result};
-use crate::types::{Rect, WindowInfo};
-use std::ffi::OsString;
-use std::os::windows::ffi::OsStringExt;
-use windows::Win32::Foundation::{BOOL, HWND, LPARAM};
-use windows::Win32::UI::WindowsAndMessaging::{
-    EnumWindows, GetClassNameW, GetWindowRect, GetWindowTextLengthW, GetWindowTextW,
-    IsWindowVisible,
-};
+use crate::types::WindowInfo;
+use std::sync::Arc;
 
 /// Window controller - entry point similar to ADB
-pub struct WindowController;
+#[derive(Debug, Clone)]
+pub struct WindowController {
+    backend: Arc<dyn Backend>,
+}
 
 impl WindowController {
-    /// Create a new window controller
+    /// Create a new window controller using the platform default backend
     pub fn new() -> Self {
-        Self
+        Self::with_backend(backend::default_backend())
     }
 
-    /// List all visible windows
-    pub fn list_windows(&self) -> Result<Vec<WindowInfo>> {
-        let mut windows: Vec<WindowInfo> = Vec::new();
+    /// Create a window controller on top of a specific backend
+    pub fn with_backend(backend: Arc<dyn Backend>) -> Self {
+        Self { backend }
+    }
 
-        unsafe {
-            let _ = EnumWindows(
-                Some(enum_windows_callback),
-                LPARAM(&mut windows as *mut Vec<WindowInfo> as isize),
-            );
-        }
+    /// Get the backend used by this controller
+    pub fn backend(&self) -> Arc<dyn Backend> {
+        self.backend.clone()
+    }
 
-        Ok(windows)
+    /// List all visible windows
+    pub fn list_windows(&self) -> Result<Vec<WindowInfo>> {
+        self.backend.list_windows()
     }
 
     /// Find window by title (partial match)
@@ -62,7 +62,12 @@ impl WindowController {
 
     /// Get window info by handle
     pub fn get_window_by_hwnd(&self, hwnd: usize) -> Result<WindowInfo> {
-        get_window_info(HWND(hwnd as *mut _))
+        self.backend.window_info(hwnd)
+    }
+
+    /// Create a device for a window, sharing this controller's backend
+    pub fn device(&self, info: WindowInfo) -> Device {
+        Device::with_backend(info, self.backend.clone())
     }
 }
 
@@ -71,60 +76,3 @@ impl Default for WindowController {
         Self::new()
     }
 }
-
-/// Callback for EnumWindows
-unsafe extern "system" fn enum_windows_callback(hwnd: HWND, lparam: LPARAM) -> BOOL {
-    let windows = &mut *(lparam.0 as *mut Vec<WindowInfo>);
-
-    // Only include visible windows
-    if IsWindowVisible(hwnd).as_bool() {
-        if let Ok(info) = get_window_info(hwnd) {
-            // Filter out empty titles
-            if !info.title.is_empty() {
-                windows.push(info);
-            }
-        }
-    }
-
-    BOOL(1) // Continue enumeration
-}
-
-/// Get window information
-fn get_window_info(hwnd: HWND) -> Result<WindowInfo> {
-    unsafe {
-        // Get window title
-        let title_len = GetWindowTextLengthW(hwnd);
-        let title = if title_len > 0 {
-            let mut buffer: Vec<u16> = vec![0; (title_len + 1) as usize];
-            GetWindowTextW(hwnd, &mut buffer);
-            OsString::from_wide(&buffer[..title_len as usize])
-                .to_string_lossy()
-                .to_string()
-        } else {
-            String::new()
-        };
-
-        // Get class name
-        let mut class_buffer: Vec<u16> = vec![0; 256];
-        let class_len = GetClassNameW(hwnd, &mut class_buffer);
-        let class_name = if class_len > 0 {
-            OsString::from_wide(&class_buffer[..class_len as usize])
-                .to_string_lossy()
-                .to_string()
-        } else {
-            String::new()
-        };
-
-        // Get window rect
-        let mut rect = windows::Win32::Foundation::RECT::default();
-        let _ = GetWindowRect(hwnd, &mut rect);
-
-        Ok(WindowInfo {
-            hwnd: hwnd.0 as usize,
-            title,
-            class_name,
-            rect: Rect::new(rect.left, rect.top, rect.right, rect.bottom),
-            visible: IsWindowVisible(hwnd).as_bool(),
-        })
-    }
-}
@@ -0,0 +1,247 @@
+//! Delta frame encoding
+//!
+//! Consecutive frames of a mostly static window differ in a few places. The
+//! sender remembers the last frame it sent and transmits only the tiles that
+//! changed since; the receiver pastes them onto its copy of that frame. A
+//! full keyframe is sent for the first frame, after a resize or format
+//! change, when the receiver holds a different frame than expected, and
+//! every `KEYFRAME_INTERVAL` frames.
+
+use crate::error::{PdbError, Result};
+use crate::protocol::{Frame, FrameData, Tile};
+use crate::types::{Screenshot, ScreenshotFormat};
+
+/// Side of the square tiles compared for changes
+pub const TILE_SIZE: u32 = 64;
+
+/// Frames between forced keyframes
+pub const KEYFRAME_INTERVAL: u64 = 60;
+
+/// Rectangles (x, y, width, height) covering the tiles that differ between
+/// two RGBA frames of the same size
+///
+/// Adjacent dirty tiles in a tile row are merged into one rectangle.
+pub fn dirty_rects(old: &[u8], new: &[u8], width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let (width, height, tile) = (width as usize, height as usize, TILE_SIZE as usize);
+    let stride = width * 4;
+    let mut rects = Vec::new();
+
+    for ty in (0..height).step_by(tile) {
+        let th = tile.min(height - ty);
+        let mut span: Option<(usize, usize)> = None;
+        for tx in (0..width).step_by(tile) {
+            let tw = tile.min(width - tx);
+            let dirty = (ty..ty + th).any(|row| {
+                let start = row * stride + tx * 4;
+                old[start..start + tw * 4] != new[start..start + tw * 4]
+            });
+            span = match (span, dirty) {
+                (Some((x, w)), true) => Some((x, w + tw)),
+                (None, true) => Some((tx, tw)),
+                (Some((x, w)), false) => {
+                    rects.push((x as u32, ty as u32, w as u32, th as u32));
+                    None
+                }
+                (None, false) => None,
+            };
+        }
+        if let Some((x, w)) = span {
+            rects.push((x as u32, ty as u32, w as u32, th as u32));
+        }
+    }
+    rects
+}
+
+/// Sender side: turns screenshots into keyframes and deltas
+#[derive(Debug, Clone)]
+pub struct DeltaEncoder {
+    /// Last frame sent and the format it was sent in
+    last: Option<(Screenshot, ScreenshotFormat)>,
+    /// Sequence number of the last frame sent (0 before the first)
+    seq: u64,
+    /// Deltas sent since the last keyframe
+    since_keyframe: u64,
+    keyframe_interval: u64,
+}
+
+impl Default for DeltaEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeltaEncoder {
+    /// Create an encoder that has not sent anything yet
+    pub fn new() -> Self {
+        Self {
+            last: None,
+            seq: 0,
+            since_keyframe: 0,
+            keyframe_interval: KEYFRAME_INTERVAL,
+        }
+    }
+
+    /// Force a keyframe every `frames` frames
+    pub fn with_keyframe_interval(mut self, frames: u64) -> Self {
+        self.keyframe_interval = frames.max(1);
+        self
+    }
+
+    /// Sequence number of the last frame sent (0 before the first)
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Encode the next frame
+    ///
+    /// `base` is the frame the receiver holds; a delta is only produced
+    /// against it, anything else (including `None`) yields a keyframe.
+    pub fn encode(
+        &mut self,
+        screenshot: Screenshot,
+        format: ScreenshotFormat,
+        base: Option<u64>,
+    ) -> Result<Frame> {
+        let delta_base = match &self.last {
+            Some((last, last_format))
+                if base == Some(self.seq)
+                    && *last_format == format
+                    && (last.width, last.height) == (screenshot.width, screenshot.height)
+                    && self.since_keyframe + 1 < self.keyframe_interval =>
+            {
+                Some(last)
+            }
+            _ => None,
+        };
+
+        let data = match delta_base {
+            Some(last) => {
+                let rects = dirty_rects(
+                    &last.data,
+                    &screenshot.data,
+                    screenshot.width,
+                    screenshot.height,
+                );
+                let tiles = rects
+                    .into_iter()
+                    .map(|(x, y, w, h)| {
+                        Ok(Tile {
+                            x,
+                            y,
+                            image: crop(&screenshot, x, y, w, h).encode(format)?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                self.since_keyframe += 1;
+                FrameData::Delta {
+                    base: self.seq,
+                    tiles,
+                }
+            }
+            None => {
+                self.since_keyframe = 0;
+                FrameData::Key(screenshot.encode(format)?)
+            }
+        };
+
+        self.seq += 1;
+        self.last = Some((screenshot, format));
+        Ok(Frame {
+            seq: self.seq,
+            data,
+        })
+    }
+}
+
+/// Receiver side: rebuilds full screenshots from keyframes and deltas
+#[derive(Debug, Clone, Default)]
+pub struct DeltaDecoder {
+    /// Last frame rebuilt and its sequence number
+    last: Option<(u64, Screenshot)>,
+}
+
+impl DeltaDecoder {
+    /// Create a decoder that holds no frame yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the frame held (0 if none)
+    pub fn seq(&self) -> u64 {
+        self.last.as_ref().map_or(0, |(seq, _)| *seq)
+    }
+
+    /// Apply a received frame and return the full screenshot
+    pub fn apply(&mut self, frame: Frame) -> Result<Screenshot> {
+        let screenshot = match frame.data {
+            FrameData::Key(image) => image.decode()?,
+            FrameData::Delta { base, tiles } => {
+                let mut screenshot = match self.last.take() {
+                    Some((seq, screenshot)) if seq == base => screenshot,
+                    _ => {
+                        return Err(PdbError::ProtocolError(format!(
+                            "delta against frame {} but holding frame {}",
+                            base,
+                            self.seq()
+                        )))
+                    }
+                };
+                for tile in tiles {
+                    paste(&mut screenshot, tile.x, tile.y, &tile.image.decode()?)?;
+                }
+                screenshot
+            }
+        };
+        self.last = Some((frame.seq, screenshot.clone()));
+        Ok(screenshot)
+    }
+}
+
+/// Copy a rectangle out of a screenshot
+fn crop(screenshot: &Screenshot, x: u32, y: u32, width: u32, height: u32) -> Screenshot {
+    let stride = screenshot.width as usize * 4;
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in y as usize..(y + height) as usize {
+        let start = row * stride + x as usize * 4;
+        data.extend_from_slice(&screenshot.data[start..start + width as usize * 4]);
+    }
+    Screenshot {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Draw `tile` onto `screenshot` at (x, y)
+fn paste(screenshot: &mut Screenshot, x: u32, y: u32, tile: &Screenshot) -> Result<()> {
+    // Tiles come from the peer, so nothing about them is trusted
+    if tile.width == 0 || tile.height == 0 {
+        return Err(PdbError::ProtocolError(format!(
+            "empty {}x{} tile",
+            tile.width, tile.height
+        )));
+    }
+    if tile.data.len() as u64 != tile.width as u64 * tile.height as u64 * 4 {
+        return Err(PdbError::ProtocolError(format!(
+            "{}x{} tile of {} bytes",
+            tile.width,
+            tile.height,
+            tile.data.len()
+        )));
+    }
+    if x as u64 + tile.width as u64 > screenshot.width as u64
+        || y as u64 + tile.height as u64 > screenshot.height as u64
+    {
+        return Err(PdbError::ProtocolError(format!(
+            "{}x{} tile at ({}, {}) is outside the {}x{} frame",
+            tile.width, tile.height, x, y, screenshot.width, screenshot.height
+        )));
+    }
+    let stride = screenshot.width as usize * 4;
+    let row_len = tile.width as usize * 4;
+    for (i, row) in tile.data.chunks_exact(row_len).enumerate() {
+        let start = (y as usize + i) * stride + x as usize * 4;
+        screenshot.data[start..start + row_len].copy_from_slice(row);
+    }
+    Ok(())
+}
@@ -1,31 +1,28 @@
 //! Device abstraction - represents a connected window (similar to ADB device)
 
-use crate::capture;
+use crate::backend::{self, Backend};
 use crate::error::Result;
-use crate::input;
 use crate::types::{KeyCode, Rect, Screenshot, WindowInfo};
-use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::{
-    GetClientRect, GetWindowRect, SetForegroundWindow, IsIconic, ShowWindow,
-    SW_SHOWNOACTIVATE, SW_MINIMIZE,
-};
+use std::sync::Arc;
 
 /// Device represents a connected window, similar to an ADB device
 #[derive(Debug, Clone)]
 pub struct Device {
-    /// Window handle
-    hwnd: HWND,
     /// Window info
     info: WindowInfo,
+    /// Platform backend
+    backend: Arc<dyn Backend>,
 }
 
 impl Device {
-    /// Create a new device from window info
+    /// Create a new device from window info using the platform default backend
     pub fn new(info: WindowInfo) -> Self {
-        Self {
-            hwnd: HWND(info.hwnd as *mut _),
-            info,
-        }
+        Self::with_backend(info, backend::default_backend())
+    }
+
+    /// Create a new device on top of a specific backend
+    pub fn with_backend(info: WindowInfo, backend: Arc<dyn Backend>) -> Self {
+        Self { info, backend }
     }
 
     /// Get window info
@@ -38,57 +35,47 @@ impl Device {
         self.info.hwnd
     }
 
+    /// Get the backend driving this device
+    pub fn backend(&self) -> &Arc<dyn Backend> {
+        &self.backend
+    }
+
     /// Check if window is minimized
     pub fn is_minimized(&self) -> bool {
-        unsafe { IsIconic(self.hwnd).as_bool() }
+        self.backend.is_minimized(self.info.hwnd)
     }
 
     /// Restore window if minimized (without activating)
     /// Returns true if window was minimized
     fn ensure_visible(&self) -> bool {
-        unsafe {
-            let was_minimized = IsIconic(self.hwnd).as_bool();
-            if was_minimized {
-                let _ = ShowWindow(self.hwnd, SW_SHOWNOACTIVATE);
-                std::thread::sleep(std::time::Duration::from_millis(100));
-            }
-            was_minimized
+        let was_minimized = self.is_minimized();
+        if was_minimized {
+            let _ = self.backend.restore(self.info.hwnd);
+            std::thread::sleep(std::time::Duration::from_millis(100));
         }
+        was_minimized
     }
 
     /// Re-minimize window if it was minimized before
     fn restore_minimized(&self, was_minimized: bool) {
         if was_minimized {
-            unsafe {
-                let _ = ShowWindow(self.hwnd, SW_MINIMIZE);
-            }
+            let _ = self.backend.minimize(self.info.hwnd);
         }
     }
 
     /// Bring window to foreground
     pub fn focus(&self) -> Result<()> {
-        unsafe {
-            let _ = SetForegroundWindow(self.hwnd);
-        }
-        Ok(())
+        self.backend.focus(self.info.hwnd)
     }
 
     /// Get window size (client area)
     pub fn get_size(&self) -> Result<(i32, i32)> {
-        unsafe {
-            let mut rect = windows::Win32::Foundation::RECT::default();
-            let _ = GetClientRect(self.hwnd, &mut rect);
-            Ok((rect.right - rect.left, rect.bottom - rect.top))
-        }
+        self.backend.client_size(self.info.hwnd)
     }
 
     /// Get window rectangle
     pub fn get_rect(&self) -> Result<Rect> {
-        unsafe {
-            let mut rect = windows::Win32::Foundation::RECT::default();
-            let _ = GetWindowRect(self.hwnd, &mut rect);
-            Ok(Rect::new(rect.left, rect.top, rect.right, rect.bottom))
-        }
+        self.backend.window_rect(self.info.hwnd)
     }
 
     /// Click at position (relative to window client area)
@@ -98,7 +85,7 @@ impl Device {
         self.focus()?;
         let (screen_x, screen_y) = self.client_to_screen(x, y)?;
         std::thread::sleep(std::time::Duration::from_millis(50));
-        let result = input::mouse_click(screen_x, screen_y);
+        let result = self.backend.mouse_click(screen_x, screen_y);
         self.restore_minimized(was_minimized);
         result
     }
@@ -116,19 +103,21 @@ impl Device {
         let (screen_x1, screen_y1) = self.client_to_screen(x1, y1)?;
         let (screen_x2, screen_y2) = self.client_to_screen(x2, y2)?;
         std::thread::sleep(std::time::Duration::from_millis(50));
-        let result = input::mouse_swipe(screen_x1, screen_y1, screen_x2, screen_y2, duration_ms);
+        let result = self
+            .backend
+            .mouse_swipe(screen_x1, screen_y1, screen_x2, screen_y2, duration_ms);
         self.restore_minimized(was_minimized);
         result
     }
 
     /// Take screenshot of window
     pub fn screenshot(&self) -> Result<Screenshot> {
-        capture::capture_window(self.hwnd)
+        self.backend.capture_window(self.info.hwnd)
     }
 
     /// Take screenshot of window client area
     pub fn screenshot_client(&self) -> Result<Screenshot> {
-        capture::capture_window_client(self.hwnd)
+        self.backend.capture_window_client(self.info.hwnd)
     }
 
     /// Input text
@@ -137,7 +126,7 @@ impl Device {
         let was_minimized = self.ensure_visible();
         self.focus()?;
         std::thread::sleep(std::time::Duration::from_millis(50));
-        let result = input::input_text(text);
+        let result = self.backend.input_text(text);
         self.restore_minimized(was_minimized);
         result
     }
@@ -148,7 +137,7 @@ impl Device {
         let was_minimized = self.ensure_visible();
         self.focus()?;
         std::thread::sleep(std::time::Duration::from_millis(50));
-        let result = input::key_event(key);
+        let result = self.backend.key_event(key);
         self.restore_minimized(was_minimized);
         result
     }
@@ -170,29 +159,17 @@ impl Device {
 
     /// Get current cursor position relative to window client area
     pub fn get_cursor_pos(&self) -> Result<(i32, i32)> {
-        let (screen_x, screen_y) = input::get_cursor_pos()?;
+        let (screen_x, screen_y) = self.backend.cursor_pos()?;
         self.screen_to_client(screen_x, screen_y)
     }
 
     /// Convert client coordinates to screen coordinates
     fn client_to_screen(&self, x: i32, y: i32) -> Result<(i32, i32)> {
-        unsafe {
-            let mut point = windows::Win32::Foundation::POINT { x, y };
-            let _ = windows::Win32::Graphics::Gdi::ClientToScreen(self.hwnd, &mut point);
-            Ok((point.x, point.y))
-        }
+        self.backend.client_to_screen(self.info.hwnd, x, y)
     }
 
     /// Convert screen coordinates to client coordinates
     fn screen_to_client(&self, x: i32, y: i32) -> Result<(i32, i32)> {
-        unsafe {
-            let mut point = windows::Win32::Foundation::POINT { x, y };
-            let _ = windows::Win32::Graphics::Gdi::ScreenToClient(self.hwnd, &mut point);
-            Ok((point.x, point.y))
-        }
+        self.backend.screen_to_client(self.info.hwnd, x, y)
     }
 }
-
-// Make Device Send + Sync for async usage
-unsafe impl Send for Device {}
-unsafe impl Sync for Device {}
@@ -1,5 +1,6 @@
 //! Error types for the PDB library
 
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Result type alias for PDB operations
@@ -20,14 +21,32 @@ pub enum PdbError {
     #[error("Input simulation failed: {0}")]
     InputError(String),
 
+    /// Command for a window the server has not connected to
+    #[error("Device not connected: 0x{0:X}")]
+    DeviceNotConnected(usize),
+
+    /// Input for a window another client holds a lease on
+    #[error("Device leased by another client: 0x{0:X}")]
+    DeviceLeased(usize),
+
     /// Screenshot capture failed
     #[error("Screenshot capture failed: {0}")]
     CaptureError(String),
 
     /// Windows API error
+    #[cfg(windows)]
     #[error("Windows API error: {0}")]
     WindowsError(#[from] windows::core::Error),
 
+    /// X11 protocol or connection error
+    #[cfg(all(unix, feature = "x11"))]
+    #[error("X11 error: {0}")]
+    X11Error(String),
+
+    /// Operation not supported by the active backend
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
     /// IO error
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
@@ -40,11 +59,177 @@ pub enum PdbError {
     #[error("Protocol error: {0}")]
     ProtocolError(String),
 
+    /// Client and server have no protocol version in common
+    #[error("Protocol version mismatch: {0}")]
+    VersionMismatch(String),
+
+    /// Frame longer than the reading side accepts (see `framing::FrameLimits`)
+    #[error("Frame too large: {length} bytes, at most {max} accepted")]
+    FrameTooLarge { length: u64, max: u64 },
+
+    /// Stream ended or stalled in the middle of a frame
+    #[error("Truncated frame: {0}")]
+    TruncatedFrame(String),
+
+    /// The server rejected the client's credentials, or the client has none
+    #[error("Authentication failed: {0}")]
+    AuthenticationFailed(String),
+
+    /// The server's policy does not allow the command or window
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    /// TLS configuration or handshake error
+    #[cfg(feature = "tls")]
+    #[error("TLS error: {0}")]
+    TlsError(String),
+
     /// Serialization error
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
 
+    /// MessagePack encoding error
+    #[error("MessagePack error: {0}")]
+    MessagePackError(String),
+
     /// Image error
     #[error("Image error: {0}")]
     ImageError(#[from] image::ImageError),
 }
+
+impl PdbError {
+    /// What went wrong, independent of platform and message
+    ///
+    /// Sent along with error responses (see `protocol::ErrorInfo`), so
+    /// remote callers can tell failures apart like local ones.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            PdbError::WindowNotFound(_) => ErrorKind::WindowNotFound,
+            PdbError::HandleError(_) => ErrorKind::Handle,
+            PdbError::InputError(_) => ErrorKind::Input,
+            PdbError::DeviceNotConnected(_) => ErrorKind::DeviceNotConnected,
+            PdbError::DeviceLeased(_) => ErrorKind::DeviceLeased,
+            PdbError::CaptureError(_) => ErrorKind::Capture,
+            #[cfg(windows)]
+            PdbError::WindowsError(_) => ErrorKind::Platform,
+            #[cfg(all(unix, feature = "x11"))]
+            PdbError::X11Error(_) => ErrorKind::Platform,
+            PdbError::Unsupported(_) => ErrorKind::Unsupported,
+            PdbError::IoError(_) => ErrorKind::Io,
+            PdbError::ConnectionError(_) => ErrorKind::Connection,
+            PdbError::ProtocolError(_) => ErrorKind::Protocol,
+            PdbError::VersionMismatch(_) => ErrorKind::VersionMismatch,
+            PdbError::FrameTooLarge { .. } => ErrorKind::FrameTooLarge,
+            PdbError::TruncatedFrame(_) => ErrorKind::TruncatedFrame,
+            PdbError::AuthenticationFailed(_) => ErrorKind::Authentication,
+            PdbError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            #[cfg(feature = "tls")]
+            PdbError::TlsError(_) => ErrorKind::Tls,
+            PdbError::SerializationError(_) | PdbError::MessagePackError(_) => {
+                ErrorKind::Serialization
+            }
+            PdbError::ImageError(_) => ErrorKind::Image,
+        }
+    }
+}
+
+/// Kind of a `PdbError`, as sent over the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// `PdbError::WindowNotFound`
+    WindowNotFound,
+    /// `PdbError::HandleError`
+    Handle,
+    /// `PdbError::InputError`
+    Input,
+    /// `PdbError::DeviceNotConnected`
+    DeviceNotConnected,
+    /// `PdbError::DeviceLeased`
+    DeviceLeased,
+    /// `PdbError::CaptureError`
+    Capture,
+    /// Windows API or X11 error
+    Platform,
+    /// `PdbError::Unsupported`
+    Unsupported,
+    /// `PdbError::IoError`
+    Io,
+    /// `PdbError::ConnectionError`
+    Connection,
+    /// `PdbError::ProtocolError`
+    Protocol,
+    /// `PdbError::VersionMismatch`
+    VersionMismatch,
+    /// `PdbError::FrameTooLarge`
+    FrameTooLarge,
+    /// `PdbError::TruncatedFrame`
+    TruncatedFrame,
+    /// `PdbError::AuthenticationFailed`
+    Authentication,
+    /// `PdbError::PermissionDenied`
+    PermissionDenied,
+    /// `PdbError::TlsError`
+    Tls,
+    /// JSON or MessagePack error
+    Serialization,
+    /// `PdbError::ImageError`
+    Image,
+    /// Not known: sent without a kind (by servers before protocol 4), or a
+    /// kind this version does not know
+    #[serde(other)]
+    Unknown,
+}
+
+impl From<rmp_serde::encode::Error> for PdbError {
+    fn from(e: rmp_serde::encode::Error) -> Self {
+        PdbError::MessagePackError(e.to_string())
+    }
+}
+
+impl From<rmp_serde::decode::Error> for PdbError {
+    fn from(e: rmp_serde::decode::Error) -> Self {
+        PdbError::MessagePackError(e.to_string())
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<tokio_rustls::rustls::Error> for PdbError {
+    fn from(e: tokio_rustls::rustls::Error) -> Self {
+        PdbError::TlsError(e.to_string())
+    }
+}
+
+#[cfg(feature = "websocket")]
+impl From<tokio_tungstenite::tungstenite::Error> for PdbError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        PdbError::ConnectionError(e.to_string())
+    }
+}
+
+#[cfg(feature = "http")]
+impl From<hyper::Error> for PdbError {
+    fn from(e: hyper::Error) -> Self {
+        PdbError::ConnectionError(e.to_string())
+    }
+}
+
+#[cfg(all(unix, feature = "x11"))]
+impl From<x11rb::errors::ConnectError> for PdbError {
+    fn from(e: x11rb::errors::ConnectError) -> Self {
+        PdbError::X11Error(e.to_string())
+    }
+}
+
+#[cfg(all(unix, feature = "x11"))]
+impl From<x11rb::errors::ConnectionError> for PdbError {
+    fn from(e: x11rb::errors::ConnectionError) -> Self {
+        PdbError::X11Error(e.to_string())
+    }
+}
+
+#[cfg(all(unix, feature = "x11"))]
+impl From<x11rb::errors::ReplyError> for PdbError {
+    fn from(e: x11rb::errors::ReplyError) -> Self {
+        PdbError::X11Error(e.to_string())
+    }
+}
@@ -0,0 +1,146 @@
+//! Framing of `Command`/`Response` messages on a byte stream
+//!
+//! Every message is a `MessageHeader` (version and body length as
+//! little-endian `u32`s, plus the request id from `REQUEST_ID_VERSION` on)
+//! followed by the body. The length comes from the peer, so reading is
+//! bounded by `FrameLimits`: longer frames fail with
+//! `PdbError::FrameTooLarge` before anything is allocated, and a frame that
+//! stops arriving halfway fails with `PdbError::TruncatedFrame`.
+
+use crate::error::{PdbError, Result};
+use crate::protocol::MessageHeader;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest frame a server reads by default (commands are small)
+pub const DEFAULT_SERVER_MAX_FRAME: u32 = 16 << 20;
+
+/// Largest frame a client reads by default (raw screenshots are not)
+pub const DEFAULT_CLIENT_MAX_FRAME: u32 = 512 << 20;
+
+/// Time a frame may take to arrive once it started, by default
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Bytes of a header without request id
+const BASE_HEADER_LEN: usize = 8;
+
+/// Body bytes allocated ahead of the data actually arriving
+const READ_CHUNK: usize = 64 << 10;
+
+/// Bounds for reading frames from a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    /// Largest accepted body, in bytes
+    pub max_frame: u32,
+    /// Time a frame may take from its first byte to its last (`None`: no
+    /// limit); waiting for the next frame is never limited
+    pub read_timeout: Option<Duration>,
+}
+
+impl FrameLimits {
+    /// Defaults for frames read by a server
+    pub fn server() -> Self {
+        Self {
+            max_frame: DEFAULT_SERVER_MAX_FRAME,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+        }
+    }
+
+    /// Defaults for frames read by a client
+    pub fn client() -> Self {
+        Self {
+            max_frame: DEFAULT_CLIENT_MAX_FRAME,
+            read_timeout: Some(DEFAULT_READ_TIMEOUT),
+        }
+    }
+}
+
+/// Read one frame; `None` if the stream ended cleanly before it
+///
+/// A frame of an unsupported version fails with `PdbError::VersionMismatch`
+/// after its body was skipped (if within limits), so the caller can still
+/// answer before closing.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    limits: &FrameLimits,
+) -> Result<Option<(MessageHeader, Vec<u8>)>> {
+    let mut header_buf = [0u8; BASE_HEADER_LEN];
+    // Idle connections wait here without a time limit
+    if reader.read(&mut header_buf[..1]).await? == 0 {
+        return Ok(None);
+    }
+    let frame = async {
+        reader.read_exact(&mut header_buf[1..]).await?;
+        let mut header = MessageHeader::with_version(
+            u32::from_le_bytes(header_buf[0..4].try_into().unwrap()),
+            u32::from_le_bytes(header_buf[4..8].try_into().unwrap()),
+        );
+        // Reject unknown versions before their body can fail to parse
+        if let Err(e) = header.check_version() {
+            if header.length <= limits.max_frame {
+                let mut body = (&mut *reader).take(header.length.into());
+                tokio::io::copy(&mut body, &mut tokio::io::sink()).await?;
+            }
+            return Err(e);
+        }
+        if header.has_id() {
+            header.id = reader.read_u32_le().await?;
+        }
+        if header.length > limits.max_frame {
+            return Err(PdbError::FrameTooLarge {
+                length: header.length.into(),
+                max: limits.max_frame.into(),
+            });
+        }
+
+        // Grows as the body arrives, not to whatever the header claims
+        let length = header.length as usize;
+        let mut body = Vec::with_capacity(length.min(READ_CHUNK));
+        (&mut *reader)
+            .take(length as u64)
+            .read_to_end(&mut body)
+            .await?;
+        if body.len() < length {
+            return Err(PdbError::TruncatedFrame(format!(
+                "stream ended after {} of {} bytes",
+                body.len(),
+                length
+            )));
+        }
+        Ok((header, body))
+    };
+
+    let frame = match limits.read_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, frame).await.map_err(|_| {
+            PdbError::TruncatedFrame(format!("frame incomplete after {:?}", timeout))
+        })?,
+        None => frame.await,
+    };
+    match frame {
+        Err(PdbError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(
+            PdbError::TruncatedFrame("stream ended inside a header".into()),
+        ),
+        frame => frame.map(Some),
+    }
+}
+
+/// A complete frame: header for `version` and `id`, then `body`
+pub fn encode_frame(version: u32, id: u32, body: &[u8]) -> Result<Vec<u8>> {
+    let length = u32::try_from(body.len()).map_err(|_| PdbError::FrameTooLarge {
+        length: body.len() as u64,
+        max: u32::MAX.into(),
+    })?;
+    let mut frame = MessageHeader::with_version(version, length)
+        .with_id(id)
+        .to_bytes();
+    frame.extend_from_slice(body);
+    Ok(frame)
+}
+
+/// Write a complete frame and flush it
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, frame: &[u8]) -> Result<()> {
+    writer.write_all(frame).await?;
+    // Flushed per frame, or TLS would hold it back
+    writer.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,407 @@
+//! HTTP REST gateway
+//!
+//! For HTTP-based tooling the server can also answer plain HTTP/1.1
+//! requests (`Server::serve_http`). Each route builds the `Command` a
+//! framed client would send and runs it through the same handlers:
+//!
+//! | Route                                   | Command                  |
+//! |-----------------------------------------|--------------------------|
+//! | `GET /ping`                             | `Ping`                   |
+//! | `GET /windows`                          | `ListWindows`            |
+//! | `POST /devices` `{"title"}`             | `Connect`                |
+//! | `POST /devices/{hwnd}`                  | `ConnectByHwnd`          |
+//! | `POST /devices/{hwnd}/click` `{"x","y"}`| `Click`                  |
+//! | `POST /devices/{hwnd}/swipe`            | `Swipe`                  |
+//! | `POST /devices/{hwnd}/text` `{"text"}`  | `InputText`              |
+//! | `POST /devices/{hwnd}/key` `{"key"}`    | `KeyEvent`               |
+//! | `POST /devices/{hwnd}/focus`            | `Focus`                  |
+//! | `GET /devices/{hwnd}/size`              | `GetSize`                |
+//! | `GET /devices/{hwnd}/screenshot.png`    | `Screenshot`             |
+//! | `GET /sessions`                         | `ListSessions`           |
+//!
+//! Request bodies are JSON objects with the command's fields; `{hwnd}` is
+//! decimal or `0x` hex. Screenshots are also served as `.jpg` (`?quality=`)
+//! and `.webp`, and take `?scale=`. Data is returned as JSON, commands
+//! without a result as `204 No Content`, and errors as
+//! `{"error": "...", "kind": "..."}` with a matching status code (`kind` is
+//! the `ErrorKind`, left out for errors of the request itself). A server
+//! with keys expects one of them as `Authorization: Bearer <key>`.
+//! Commands the server's policy does not allow are answered with
+//! `403 Forbidden`.
+
+use crate::auth;
+use crate::error::{ErrorKind, PdbError, Result};
+use crate::protocol::{Command, Response};
+use crate::server::{self, Encoders, Shared};
+use crate::session::Session;
+use crate::transport::Transport;
+use crate::types::ScreenshotFormat;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::Arc;
+
+/// Largest accepted request body
+const MAX_BODY: usize = 1 << 20;
+
+/// JPEG quality when the request does not give one
+const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+type HttpResponse = hyper::Response<Full<Bytes>>;
+
+/// Sessions of a gateway, shared by all its connections
+pub(crate) struct Gateway {
+    /// Session of requests without a key of their own policy
+    session: Session,
+    /// Sessions of requests with a key that has its own policy, by key
+    keys: HashMap<Vec<u8>, Session>,
+}
+
+impl Gateway {
+    /// Open the gateway's sessions for a listener at `peer`
+    pub(crate) fn open(shared: &Shared, peer: String) -> Self {
+        let keys = shared
+            .policies
+            .keys
+            .iter()
+            .map(|(key, policy)| {
+                let session = shared.sessions.open(peer.clone(), "http");
+                session.set_policy(policy.clone());
+                (key.clone(), session)
+            })
+            .collect();
+        Self {
+            session: shared.open_session(peer, "http"),
+            keys,
+        }
+    }
+
+    /// Session of a request authorized with `keys[key]`
+    fn session(&self, shared: &Shared, key: Option<usize>) -> &Session {
+        key.and_then(|key| self.keys.get(&shared.keys[key]))
+            .unwrap_or(&self.session)
+    }
+}
+
+/// Serve HTTP requests on one connection until it closes
+pub(crate) async fn handle_connection(
+    stream: impl Transport,
+    shared: Shared,
+    gateway: Arc<Gateway>,
+) -> Result<()> {
+    let service = service_fn(move |request| {
+        let shared = shared.clone();
+        let gateway = gateway.clone();
+        async move { Ok::<_, Infallible>(handle_request(request, &shared, &gateway).await) }
+    });
+    http1::Builder::new()
+        .serve_connection(TokioIo::new(stream), service)
+        .await?;
+    Ok(())
+}
+
+async fn handle_request(
+    request: Request<Incoming>,
+    shared: &Shared,
+    gateway: &Gateway,
+) -> HttpResponse {
+    respond(request, shared, gateway)
+        .await
+        .unwrap_or_else(HttpError::into_response)
+}
+
+async fn respond(
+    request: Request<Incoming>,
+    shared: &Shared,
+    gateway: &Gateway,
+) -> std::result::Result<HttpResponse, HttpError> {
+    let session = gateway.session(shared, authorize(&request, &shared.keys)?);
+    let (parts, body) = request.into_parts();
+    let body = match Limited::new(body, MAX_BODY).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return Err(HttpError::new(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("request body over {} bytes", MAX_BODY),
+            ))
+        }
+        Err(e) => return Err(HttpError::new(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+
+    let command = route(&parts.method, parts.uri.path(), parts.uri.query(), &body)?;
+    let controller = shared.controller();
+    // Requests are independent, so there are no previous frames to diff
+    let response =
+        server::execute(command, &controller, session, &Encoders::default()).await?;
+    Ok(reply(response))
+}
+
+/// Check the bearer key, if the server has keys, and return its index
+fn authorize(
+    request: &Request<Incoming>,
+    keys: &[Vec<u8>],
+) -> std::result::Result<Option<usize>, HttpError> {
+    if keys.is_empty() {
+        return Ok(None);
+    }
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented.map(|key| auth::find_presented_key(keys, key.trim().as_bytes())) {
+        Some(Some(key)) => Ok(Some(key)),
+        Some(None) => Err(HttpError::new(StatusCode::UNAUTHORIZED, "wrong key")),
+        None => Err(HttpError::new(
+            StatusCode::UNAUTHORIZED,
+            "authentication required",
+        )),
+    }
+}
+
+/// Build the command for a request
+fn route(
+    method: &Method,
+    path: &str,
+    query: Option<&str>,
+    body: &[u8],
+) -> std::result::Result<Command, HttpError> {
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    match segments.as_slice() {
+        ["ping"] => {
+            allow(method, Method::GET)?;
+            Ok(Command::Ping)
+        }
+        ["windows"] => {
+            allow(method, Method::GET)?;
+            Ok(Command::ListWindows)
+        }
+        ["sessions"] => {
+            allow(method, Method::GET)?;
+            Ok(Command::ListSessions)
+        }
+        ["devices"] => {
+            allow(method, Method::POST)?;
+            command("Connect", None, body)
+        }
+        ["devices", hwnd] => {
+            allow(method, Method::POST)?;
+            Ok(Command::ConnectByHwnd {
+                hwnd: parse_hwnd(hwnd)?,
+            })
+        }
+        ["devices", hwnd, "size"] => {
+            allow(method, Method::GET)?;
+            Ok(Command::GetSize {
+                hwnd: parse_hwnd(hwnd)?,
+            })
+        }
+        ["devices", hwnd, file] if file.starts_with("screenshot.") => {
+            allow(method, Method::GET)?;
+            screenshot(parse_hwnd(hwnd)?, file, query)
+        }
+        ["devices", hwnd, action] => {
+            let name = match *action {
+                "click" => "Click",
+                "swipe" => "Swipe",
+                "text" => "InputText",
+                "key" => "KeyEvent",
+                "focus" => "Focus",
+                _ => return Err(HttpError::not_found(path)),
+            };
+            allow(method, Method::POST)?;
+            command(name, Some(parse_hwnd(hwnd)?), body)
+        }
+        _ => Err(HttpError::not_found(path)),
+    }
+}
+
+fn allow(method: &Method, allowed: Method) -> std::result::Result<(), HttpError> {
+    if *method == allowed {
+        Ok(())
+    } else {
+        Err(HttpError::new(
+            StatusCode::METHOD_NOT_ALLOWED,
+            format!("use {}", allowed),
+        ))
+    }
+}
+
+fn parse_hwnd(s: &str) -> std::result::Result<usize, HttpError> {
+    let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    parsed.map_err(|_| HttpError::new(StatusCode::BAD_REQUEST, format!("invalid hwnd {}", s)))
+}
+
+/// Command `name` with the fields of a JSON body, plus the window from the path
+fn command(
+    name: &str,
+    hwnd: Option<usize>,
+    body: &[u8],
+) -> std::result::Result<Command, HttpError> {
+    let mut fields = if body.iter().all(u8::is_ascii_whitespace) {
+        serde_json::Map::new()
+    } else {
+        serde_json::from_slice(body).map_err(PdbError::from)?
+    };
+    if let Some(hwnd) = hwnd {
+        fields.insert("hwnd".to_string(), hwnd.into());
+    }
+    let mut command = serde_json::Map::new();
+    command.insert(name.to_string(), fields.into());
+    Ok(serde_json::from_value(command.into()).map_err(PdbError::from)?)
+}
+
+/// `Screenshot` command for `screenshot.<ext>?scale=..&quality=..`
+fn screenshot(
+    hwnd: usize,
+    file: &str,
+    query: Option<&str>,
+) -> std::result::Result<Command, HttpError> {
+    let mut scale = None;
+    let mut quality = DEFAULT_JPEG_QUALITY;
+    for pair in query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|p| !p.is_empty())
+    {
+        let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let invalid = || HttpError::new(StatusCode::BAD_REQUEST, format!("invalid {}", pair));
+        match name {
+            "scale" => scale = Some(value.parse().map_err(|_| invalid())?),
+            "quality" => quality = value.parse().map_err(|_| invalid())?,
+            _ => return Err(invalid()),
+        }
+    }
+    let format = match file.trim_start_matches("screenshot.") {
+        "png" => ScreenshotFormat::Png,
+        "jpg" | "jpeg" => ScreenshotFormat::Jpeg { quality },
+        "webp" => ScreenshotFormat::WebP,
+        other => {
+            return Err(HttpError::new(
+                StatusCode::NOT_FOUND,
+                format!("no screenshot format {}", other),
+            ))
+        }
+    };
+    Ok(Command::Screenshot {
+        hwnd,
+        format,
+        scale,
+        base: None,
+    })
+}
+
+/// Turn a command's result into an HTTP response
+fn reply(response: Response) -> HttpResponse {
+    match response {
+        Response::Ok | Response::Pong => with_status(StatusCode::NO_CONTENT, Full::default()),
+        Response::Windows(windows) => json(StatusCode::OK, &windows),
+        Response::Sessions(sessions) => json(StatusCode::OK, &sessions),
+        Response::Window(window) => json(StatusCode::OK, &window),
+        Response::Size { width, height } => json(
+            StatusCode::OK,
+            &serde_json::json!({ "width": width, "height": height }),
+        ),
+        Response::Image(image) => {
+            let content_type = match image.format {
+                ScreenshotFormat::Png => "image/png",
+                ScreenshotFormat::Jpeg { .. } => "image/jpeg",
+                ScreenshotFormat::WebP => "image/webp",
+                ScreenshotFormat::Raw => "application/octet-stream",
+            };
+            let mut response = with_status(StatusCode::OK, Full::new(image.data.into()));
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+            response
+        }
+        Response::Error(error) => {
+            HttpError::from(error.into_error(PdbError::ProtocolError)).into_response()
+        }
+        // Not produced by any route
+        other => json(StatusCode::OK, &other),
+    }
+}
+
+fn with_status(status: StatusCode, body: Full<Bytes>) -> HttpResponse {
+    let mut response = HttpResponse::new(body);
+    *response.status_mut() = status;
+    response
+}
+
+fn json(status: StatusCode, value: &impl Serialize) -> HttpResponse {
+    let body = serde_json::to_vec(value).expect("JSON response");
+    let mut response = with_status(status, Full::new(body.into()));
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+/// Failed request, answered with a status code and `{"error": message}`
+struct HttpError {
+    status: StatusCode,
+    message: String,
+    /// Kind of the command's error, if it got to run
+    kind: Option<ErrorKind>,
+}
+
+impl HttpError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            kind: None,
+        }
+    }
+
+    fn not_found(path: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, format!("no route {}", path))
+    }
+
+    fn into_response(self) -> HttpResponse {
+        let mut body = serde_json::json!({ "error": self.message });
+        if let Some(kind) = self.kind {
+            body["kind"] = serde_json::json!(kind);
+        }
+        let mut response = json(self.status, &body);
+        if self.status == StatusCode::UNAUTHORIZED {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+impl From<PdbError> for HttpError {
+    fn from(e: PdbError) -> Self {
+        let status = match &e {
+            PdbError::WindowNotFound(_) | PdbError::DeviceNotConnected(_) => StatusCode::NOT_FOUND,
+            PdbError::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+            PdbError::DeviceLeased(_) => StatusCode::CONFLICT,
+            PdbError::ProtocolError(_)
+            | PdbError::VersionMismatch(_)
+            | PdbError::SerializationError(_)
+            | PdbError::MessagePackError(_) => StatusCode::BAD_REQUEST,
+            PdbError::AuthenticationFailed(_) => StatusCode::UNAUTHORIZED,
+            PdbError::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self {
+            kind: Some(e.kind()),
+            ..Self::new(status, e.to_string())
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Exclusive device leases
+//!
+//! Sessions attach windows independently, so two clients (e.g. CI jobs)
+//! driving the same window would interleave their input. A client that
+//! needs a window to itself takes a lease on it (`Command::AcquireLease`):
+//! while the lease lasts, input commands for the window from every other
+//! session fail with `PdbError::DeviceLeased`, while screenshots, sizes and
+//! frame subscriptions stay shared. A lease ends when it is released, when
+//! its duration passes without a renewal, or when the session holding it
+//! ends.
+
+use crate::error::{PdbError, Result};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+/// Leases held on a server's windows
+#[derive(Default)]
+pub(crate) struct Leases {
+    held: std::sync::Mutex<HashMap<usize, Lease>>,
+    /// Woken whenever a lease is released
+    released: Notify,
+}
+
+struct Lease {
+    /// Id of the holding session
+    session: u64,
+    expires: Instant,
+}
+
+impl Lease {
+    fn excludes(&self, session: u64) -> bool {
+        self.session != session && self.expires > Instant::now()
+    }
+}
+
+impl Leases {
+    /// Take the lease on `hwnd` for `duration`, waiting up to `wait` for
+    /// another session's lease to end; renews a lease `session` holds
+    pub(crate) async fn acquire(
+        &self,
+        hwnd: usize,
+        session: u64,
+        duration: Duration,
+        wait: Duration,
+    ) -> Result<()> {
+        let deadline = Instant::now() + wait;
+        loop {
+            // Registered before checking, so a release in between is not missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            let expires = {
+                let mut held = self.held.lock().unwrap();
+                match held.get(&hwnd) {
+                    Some(lease) if lease.excludes(session) => lease.expires,
+                    _ => {
+                        let expires = Instant::now() + duration;
+                        held.insert(hwnd, Lease { session, expires });
+                        return Ok(());
+                    }
+                }
+            };
+            if Instant::now() >= deadline {
+                return Err(PdbError::DeviceLeased(hwnd));
+            }
+            let _ = tokio::time::timeout_at(expires.min(deadline), released).await;
+        }
+    }
+
+    /// Extend the lease `session` holds on `hwnd` to `duration` from now
+    pub(crate) fn renew(&self, hwnd: usize, session: u64, duration: Duration) -> Result<()> {
+        let mut held = self.held.lock().unwrap();
+        match held.get_mut(&hwnd) {
+            Some(lease) if lease.excludes(session) => Err(PdbError::DeviceLeased(hwnd)),
+            Some(lease) if lease.session == session && lease.expires > Instant::now() => {
+                lease.expires = Instant::now() + duration;
+                Ok(())
+            }
+            _ => Err(PdbError::ProtocolError(format!(
+                "No lease on 0x{:X} to renew",
+                hwnd
+            ))),
+        }
+    }
+
+    /// End the lease `session` holds on `hwnd`, if any
+    pub(crate) fn release(&self, hwnd: usize, session: u64) -> Result<()> {
+        let mut held = self.held.lock().unwrap();
+        match held.get(&hwnd) {
+            Some(lease) if lease.excludes(session) => return Err(PdbError::DeviceLeased(hwnd)),
+            Some(_) => {
+                held.remove(&hwnd);
+            }
+            None => return Ok(()),
+        }
+        drop(held);
+        self.released.notify_waiters();
+        Ok(())
+    }
+
+    /// End every lease `session` holds
+    pub(crate) fn release_all(&self, session: u64) {
+        let mut held = self.held.lock().unwrap();
+        let before = held.len();
+        held.retain(|_, lease| lease.session != session);
+        if held.len() != before {
+            drop(held);
+            self.released.notify_waiters();
+        }
+    }
+
+    /// Fail if another session holds the lease on `hwnd`
+    pub(crate) fn check(&self, hwnd: usize, session: u64) -> Result<()> {
+        match self.held.lock().unwrap().get(&hwnd) {
+            Some(lease) if lease.excludes(session) => Err(PdbError::DeviceLeased(hwnd)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Windows `session` holds a lease on, in order
+    pub(crate) fn held_by(&self, session: u64) -> Vec<usize> {
+        let now = Instant::now();
+        let mut windows: Vec<usize> = self
+            .held
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, lease)| lease.session == session && lease.expires > now)
+            .map(|(hwnd, _)| *hwnd)
+            .collect();
+        windows.sort_unstable();
+        windows
+    }
+}
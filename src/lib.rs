@@ -52,21 +52,49 @@
 //! }
 //! ```
 
-pub mod capture;
+pub mod auth;
+pub mod backend;
 pub mod client;
 pub mod controller;
+pub mod delta;
 pub mod device;
 pub mod error;
-pub mod input;
+pub mod framing;
+mod lease;
+#[cfg(feature = "mcp")]
+pub mod mcp;
+#[cfg(feature = "http")]
+mod http;
+pub mod policy;
 pub mod protocol;
+#[cfg(feature = "vnc")]
+pub mod rfb;
+pub mod rpc;
 pub mod server;
+pub mod session;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod transport;
 pub mod types;
+#[cfg(feature = "websocket")]
+mod websocket;
+
+// The Win32 modules lived at the crate root before the backends
+#[cfg(windows)]
+pub use backend::win32::{capture, input};
 
 // Re-export commonly used types
-pub use client::{Client, RemoteDevice};
+pub use backend::Backend;
+pub use client::{Batch, Client, ClientOptions, FrameStream, RemoteDevice};
 pub use controller::WindowController;
 pub use device::Device;
-pub use error::{PdbError, Result};
-pub use protocol::{Command, Response, DEFAULT_PORT};
+pub use error::{ErrorKind, PdbError, Result};
+pub use policy::Policy;
+pub use protocol::{Capabilities, Command, Response, DEFAULT_PORT};
+#[cfg(feature = "vnc")]
+pub use rfb::server::RfbServer;
 pub use server::Server;
-pub use types::{KeyCode, Point, Rect, Screenshot, WindowInfo};
+pub use session::SessionInfo;
+#[cfg(feature = "tls")]
+pub use tls::{TlsClientConfig, TlsServerConfig};
+pub use types::{EncodedImage, KeyCode, Point, Rect, Screenshot, ScreenshotFormat, WindowInfo};
@@ -0,0 +1,218 @@
+//! Model Context Protocol server, exposing windows as tools for LLM agents
+//!
+//! `McpServer::serve` speaks MCP over stdio (`pdb-client mcp`): JSON-RPC
+//! 2.0, one message per line. Each tool runs one `Command` and its input
+//! schema is generated from that command's fields, so tools follow the
+//! protocol as it grows. Screenshots are returned as image content, other
+//! results as JSON text; failures are reported as tool errors
+//! (`isError`) the agent can react to.
+
+use crate::client::Client;
+use crate::controller::WindowController;
+use crate::error::Result;
+use crate::protocol::{Command, Response};
+use crate::rpc::{self, Methods, RpcError, RpcServer, INVALID_PARAMS, METHOD_NOT_FOUND};
+use crate::types::ScreenshotFormat;
+use base64::Engine;
+use schemars::generate::SchemaSettings;
+use serde_json::{json, Map, Value};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// MCP revisions this server speaks, newest first
+pub const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+/// Tools: name, command, description
+const TOOLS: &[(&str, &str, &str)] = &[
+    (
+        "list_windows",
+        "ListWindows",
+        "List the visible windows with their hwnd, title, class and screen position.",
+    ),
+    (
+        "screenshot",
+        "Screenshot",
+        "Capture a window (PNG unless another format is given). Optionally downscale it; \
+         coordinates for input tools are in unscaled pixels.",
+    ),
+    (
+        "click",
+        "Click",
+        "Click at x, y relative to the window's client area.",
+    ),
+    (
+        "swipe",
+        "Swipe",
+        "Drag from x1, y1 to x2, y2 (client area coordinates) over duration_ms milliseconds.",
+    ),
+    ("type_text", "InputText", "Type text into the window."),
+    ("key", "KeyEvent", "Press and release a key in the window."),
+    (
+        "get_size",
+        "GetSize",
+        "Get the size of the window's client area.",
+    ),
+    ("focus", "Focus", "Bring the window to the foreground."),
+];
+
+/// Serves pdb tools to MCP clients
+pub struct McpServer {
+    rpc: RpcServer,
+    /// `tools/list` result, generated once
+    tools: Value,
+}
+
+impl McpServer {
+    /// Control the windows of this machine
+    pub fn local(controller: WindowController) -> Self {
+        Self::new(RpcServer::local(controller))
+    }
+
+    /// Control the windows of the server `client` is connected to
+    pub fn remote(client: Client) -> Self {
+        Self::new(RpcServer::remote(client))
+    }
+
+    fn new(rpc: RpcServer) -> Self {
+        Self {
+            rpc,
+            tools: json!({ "tools": tools() }),
+        }
+    }
+
+    /// Answer messages, one per line, until `input` ends
+    pub async fn serve<R, W>(&self, input: R, output: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        rpc::serve(self, input, output).await
+    }
+
+    /// Answer one message; `None` when nothing is to be sent back
+    pub async fn handle_message(&self, message: &str) -> Option<Value> {
+        rpc::handle_message(self, message).await
+    }
+
+    async fn call_tool(&self, params: Value) -> std::result::Result<Value, RpcError> {
+        let name = params["name"].as_str().unwrap_or_default();
+        let Some((_, command, _)) = TOOLS.iter().find(|(tool, _, _)| *tool == name) else {
+            return Err(RpcError::new(INVALID_PARAMS, format!("no tool {}", name)));
+        };
+        let mut arguments = match params.get("arguments") {
+            Some(Value::Object(arguments)) => arguments.clone(),
+            None | Some(Value::Null) => Map::new(),
+            Some(_) => return Err(RpcError::new(INVALID_PARAMS, "arguments must be an object")),
+        };
+        if *command == "Screenshot" {
+            // Delta frames make no sense to an agent
+            arguments.remove("base");
+            arguments
+                .entry("format")
+                .or_insert(json!(ScreenshotFormat::Png));
+        }
+
+        let result = match rpc::command(command, arguments) {
+            Ok(command) => self.run(command).await,
+            Err(e) => Err(e),
+        };
+        Ok(match result {
+            Ok(content) => json!({ "content": content, "isError": false }),
+            Err(e) => json!({ "content": [text(e.message)], "isError": true }),
+        })
+    }
+
+    /// Run a tool's command and describe the result as content
+    async fn run(&self, command: Command) -> std::result::Result<Vec<Value>, RpcError> {
+        if matches!(
+            command,
+            Command::Screenshot {
+                format: ScreenshotFormat::Raw,
+                ..
+            }
+        ) {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "raw pixels cannot be shown; use Png, Jpeg or WebP",
+            ));
+        }
+        let content = match self.rpc.run(command).await? {
+            Response::Ok => text("done"),
+            Response::Windows(windows) => text(to_json(&windows)),
+            Response::Window(window) => text(to_json(&window)),
+            Response::Size { width, height } => {
+                text(to_json(&json!({ "width": width, "height": height })))
+            }
+            Response::Image(image) => json!({
+                "type": "image",
+                "data": base64::engine::general_purpose::STANDARD.encode(&image.data),
+                "mimeType": format!("image/{}", rpc::format_name(image.format)),
+            }),
+            other => text(format!("{:?}", other)),
+        };
+        Ok(vec![content])
+    }
+}
+
+impl Methods for McpServer {
+    async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+        match method {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = PROTOCOL_VERSIONS
+                    .iter()
+                    .find(|v| **v == requested)
+                    .unwrap_or(&PROTOCOL_VERSIONS[0]);
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": {} },
+                    "serverInfo": { "name": "pdb", "version": env!("CARGO_PKG_VERSION") },
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.tools.clone()),
+            "tools/call" => self.call_tool(params).await,
+            // e.g. notifications/initialized, which needs no reply
+            method if method.starts_with("notifications/") => Ok(Value::Null),
+            method => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no method {}", method),
+            )),
+        }
+    }
+}
+
+/// Tool definitions, with input schemas generated from `Command`
+fn tools() -> Vec<Value> {
+    let generator = SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let schema = generator.into_root_schema_for::<Command>().to_value();
+    let variants = schema["oneOf"].as_array().cloned().unwrap_or_default();
+
+    TOOLS
+        .iter()
+        .map(|(name, command, description)| {
+            // Unit variants are plain strings and take no input
+            let mut input = variants
+                .iter()
+                .find_map(|variant| variant["properties"].get(*command))
+                .cloned()
+                .unwrap_or_else(|| json!({ "type": "object", "properties": {} }));
+            if *command == "Screenshot" {
+                if let Some(properties) = input["properties"].as_object_mut() {
+                    properties.remove("base");
+                }
+                input["properties"]["format"]["default"] = json!(ScreenshotFormat::Png);
+            }
+            json!({ "name": name, "description": description, "inputSchema": input })
+        })
+        .collect()
+}
+
+fn text(text: impl Into<String>) -> Value {
+    json!({ "type": "text", "text": text.into() })
+}
+
+fn to_json(value: &impl serde::Serialize) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_default()
+}
@@ -0,0 +1,241 @@
+//! Authorization policies
+//!
+//! A `Policy` decides which commands a client may send and which windows it
+//! may connect to. A server has one for all its clients
+//! (`Server::with_policy`) and may grant others per key
+//! (`Server::with_key_policy`), e.g. a view-only key for teammates. Denied
+//! commands fail with `PdbError::PermissionDenied`; windows a policy does
+//! not allow are left out of `ListWindows` and cannot be connected to, so
+//! no command can reach them.
+//!
+//! Policies are usually loaded from a JSON file (`PolicyFile`,
+//! `pdb-server --policy <file>`):
+//!
+//! ```json
+//! {
+//!     "deny": ["Swipe"],
+//!     "titles": ["* - Notepad"],
+//!     "keys": [
+//!         { "key_file": "/etc/pdb/viewer.key", "read_only": true },
+//!         { "key_file": "/etc/pdb/admin.key", "admin": true }
+//!     ]
+//! }
+//! ```
+
+use crate::error::{PdbError, Result};
+use crate::protocol::Command;
+use crate::types::WindowInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Commands every client may send, whatever its policy
+const ALWAYS_ALLOWED: &[&str] = &["Hello", "Authenticate", "Ping", "Disconnect"];
+
+/// Commands that reveal other clients, only allowed with `Policy::admin`
+const ADMIN_ONLY: &[&str] = &["ListSessions"];
+
+/// What a client may do
+///
+/// The default allows everything but administration.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Deny every command that acts on a window (see `Command::is_input`)
+    /// and taking leases; screenshots, sizes and listings stay allowed
+    pub read_only: bool,
+    /// Names of the only commands allowed (empty: all)
+    pub allow: Vec<String>,
+    /// Names of commands denied
+    pub deny: Vec<String>,
+    /// Patterns of the only window titles allowed (empty: all); `*` stands
+    /// for any text and `?` for one character
+    pub titles: Vec<String>,
+    /// Patterns of the only window class names allowed (empty: all)
+    pub classes: Vec<String>,
+    /// Allow listing the sessions of every client (`ListSessions`)
+    pub admin: bool,
+}
+
+impl Policy {
+    /// Allow observing windows only
+    pub fn read_only() -> Self {
+        Self {
+            read_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// Allow everything, including administration
+    pub fn admin() -> Self {
+        Self {
+            admin: true,
+            ..Self::default()
+        }
+    }
+
+    /// Fail with `PdbError::PermissionDenied` if `command` is not allowed
+    ///
+    /// The steps of a batch are checked along with it. Windows are checked
+    /// when they are connected to (`check_window`).
+    pub fn check(&self, command: &Command) -> Result<()> {
+        let name = command.name();
+        if ALWAYS_ALLOWED.contains(&name) {
+            return Ok(());
+        }
+        let listed = |names: &[String]| names.iter().any(|n| n == name);
+        if (!self.allow.is_empty() && !listed(&self.allow)) || listed(&self.deny) {
+            return Err(PdbError::PermissionDenied(format!(
+                "{} is not allowed",
+                name
+            )));
+        }
+        if !self.admin && ADMIN_ONLY.contains(&name) {
+            return Err(PdbError::PermissionDenied(format!(
+                "{} is only allowed for administrators",
+                name
+            )));
+        }
+        let takes_lease = matches!(
+            command,
+            Command::AcquireLease { .. } | Command::RenewLease { .. }
+        );
+        if self.read_only && (command.is_input() || takes_lease) {
+            return Err(PdbError::PermissionDenied(format!(
+                "{} is not allowed in read-only mode",
+                name
+            )));
+        }
+        if let Command::Batch { steps, .. } = command {
+            for step in steps {
+                self.check(&step.command)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a client may see and connect to `window`
+    pub fn allows_window(&self, window: &WindowInfo) -> bool {
+        let matches = |patterns: &[String], text: &str| {
+            patterns.is_empty() || patterns.iter().any(|p| matches_pattern(p, text))
+        };
+        matches(&self.titles, &window.title) && matches(&self.classes, &window.class_name)
+    }
+
+    /// Fail with `PdbError::PermissionDenied` if `window` is not allowed
+    pub fn check_window(&self, window: &WindowInfo) -> Result<()> {
+        if self.allows_window(window) {
+            Ok(())
+        } else {
+            Err(PdbError::PermissionDenied(format!(
+                "window 0x{:X} \"{}\" ({}) is not allowed",
+                window.hwnd, window.title, window.class_name
+            )))
+        }
+    }
+
+    /// Fail on command names this version does not know, e.g. typos in a
+    /// policy file
+    pub fn validate(&self) -> Result<()> {
+        let unknown = self
+            .allow
+            .iter()
+            .chain(&self.deny)
+            .find(|name| !Command::NAMES.contains(&name.as_str()));
+        match unknown {
+            Some(name) => Err(PdbError::ProtocolError(format!(
+                "Unknown command {} in policy",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Contents of a policy file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyFile {
+    /// Policy of clients without a key of their own below
+    #[serde(flatten)]
+    pub policy: Policy,
+    /// Additional keys, each granting its own policy
+    #[serde(default)]
+    pub keys: Vec<KeyPolicy>,
+}
+
+/// Key of a policy file and the policy it grants
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyPolicy {
+    /// File holding the key (trailing whitespace is ignored)
+    pub key_file: PathBuf,
+    #[serde(flatten)]
+    pub policy: Policy,
+}
+
+impl PolicyFile {
+    /// Read and validate a policy file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let file: PolicyFile = serde_json::from_slice(&std::fs::read(path)?)?;
+        file.policy.validate()?;
+        for key in &file.keys {
+            key.policy.validate()?;
+        }
+        Ok(file)
+    }
+}
+
+impl KeyPolicy {
+    /// Read the key from `key_file`
+    pub fn key(&self) -> Result<Vec<u8>> {
+        let key = std::fs::read_to_string(&self.key_file)?;
+        Ok(key.trim_end().as_bytes().to_vec())
+    }
+}
+
+/// Policies of a server: one for every client, and others by key
+#[derive(Clone, Default)]
+pub(crate) struct Policies {
+    pub(crate) default: Arc<Policy>,
+    pub(crate) keys: HashMap<Vec<u8>, Arc<Policy>>,
+}
+
+impl Policies {
+    /// Policy of a client authenticated with `key`, if any
+    pub(crate) fn for_key(&self, key: Option<&[u8]>) -> Arc<Policy> {
+        key.and_then(|key| self.keys.get(key))
+            .unwrap_or(&self.default)
+            .clone()
+    }
+}
+
+/// Match `text` against a pattern with `*` and `?` wildcards
+fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position after the last `*` and the text it was tried against
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the last `*` swallow one more character
+                Some((after, tried)) => {
+                    p = after;
+                    t = tried + 1;
+                    star = Some((after, tried + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
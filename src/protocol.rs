@@ -1,11 +1,26 @@
 //! Network protocol for remote operations
 
-use crate::types::{KeyCode, Screenshot, WindowInfo};
-use serde::{Deserialize, Serialize};
+use crate::error::{ErrorKind, PdbError, Result};
+use crate::session::SessionInfo;
+use crate::types::{EncodedImage, KeyCode, Screenshot, ScreenshotFormat, WindowInfo};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 
 /// Command sent from client to server
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "mcp", derive(schemars::JsonSchema))]
 pub enum Command {
+    /// Handshake: negotiate protocol version and query capabilities
+    Hello(Hello),
+    
+    /// Answer to `Response::Challenge`: HMAC of the challenge under a shared key
+    Authenticate {
+        #[serde(with = "serde_bytes")]
+        #[cfg_attr(feature = "mcp", schemars(with = "Vec<u8>"))]
+        proof: Vec<u8>,
+    },
+    
     /// List all windows
     ListWindows,
     
@@ -28,8 +43,45 @@ pub enum Command {
         duration_ms: u32,
     },
     
-    /// Take screenshot
-    Screenshot { hwnd: usize },
+    /// Take screenshot, optionally downscaled and compressed
+    Screenshot {
+        hwnd: usize,
+        /// Image format of the reply (`Raw` replies with `Response::Screenshot`)
+        #[serde(default)]
+        format: ScreenshotFormat,
+        /// Downscale factor in (0, 1]
+        #[serde(default)]
+        scale: Option<f32>,
+        /// Sequence number of the last frame of this device the client holds
+        /// (0 for none); the reply is then a `Response::Frame`, sent as a
+        /// delta against that frame when the server still has it
+        #[serde(default)]
+        base: Option<u64>,
+    },
+    
+    /// Stream frames at a target rate (protocol 3+)
+    ///
+    /// Replied to with `Response::Ok`, followed by one `Response::Frame` per
+    /// captured frame, all carrying this command's request id, until
+    /// `Unsubscribe` or the connection closes. Reusing the id of a running
+    /// subscription replaces it; at most `MAX_SUBSCRIPTIONS` run at once.
+    Subscribe {
+        hwnd: usize,
+        /// Target frames per second (1..=`MAX_FPS`)
+        fps: u32,
+        /// Image format of each frame
+        #[serde(default)]
+        format: ScreenshotFormat,
+        /// Downscale factor in (0, 1]
+        #[serde(default)]
+        scale: Option<f32>,
+        /// Send changed tiles between keyframes instead of full frames
+        #[serde(default)]
+        delta: bool,
+    },
+    
+    /// Stop the subscription started by the request with this id
+    Unsubscribe { id: u32 },
     
     /// Input text
     InputText { hwnd: usize, text: String },
@@ -46,10 +98,256 @@ pub enum Command {
     /// Ping to check connection
     Ping,
     
-    /// Disconnect
+    /// Run several commands in order in one round trip
+    ///
+    /// Replied to with `Response::Batch`, holding one response per step that
+    /// ran. Handshake, subscription and nested batch commands cannot be
+    /// steps.
+    Batch {
+        steps: Vec<BatchStep>,
+        /// Skip the remaining steps after the first `Response::Error`
+        #[serde(default)]
+        stop_on_error: bool,
+    },
+    
+    /// Take a window for this connection alone
+    ///
+    /// While the lease lasts, input commands for the window from other
+    /// connections fail with `PdbError::DeviceLeased`; screenshots stay
+    /// shared. Waits up to `wait_ms` for another connection's lease to end
+    /// before failing the same way. Acquiring a lease this connection holds
+    /// renews it. Replied to with `Response::Ok`.
+    AcquireLease {
+        hwnd: usize,
+        /// Time until the lease ends unless renewed (at most `MAX_LEASE_MS`)
+        duration_ms: u32,
+        /// Time to wait for the window to become free (at most `MAX_LEASE_MS`)
+        #[serde(default)]
+        wait_ms: u32,
+    },
+    
+    /// Extend this connection's lease on a window to `duration_ms` from now
+    RenewLease { hwnd: usize, duration_ms: u32 },
+    
+    /// End this connection's lease on a window
+    ReleaseLease { hwnd: usize },
+    
+    /// List the sessions open on the server (see `crate::session`); only
+    /// allowed for clients whose policy grants `admin`
+    ListSessions,
+    
+    /// Release the devices this connection attached; the connection stays
+    /// open
     Disconnect,
 }
 
+impl Command {
+    /// Names of all commands, as reported in `Capabilities::commands`
+    pub const NAMES: &'static [&'static str] = &[
+        "Hello",
+        "Authenticate",
+        "ListWindows",
+        "Connect",
+        "ConnectByHwnd",
+        "Click",
+        "Swipe",
+        "Screenshot",
+        "Subscribe",
+        "Unsubscribe",
+        "InputText",
+        "KeyEvent",
+        "GetSize",
+        "Focus",
+        "Ping",
+        "Batch",
+        "AcquireLease",
+        "RenewLease",
+        "ReleaseLease",
+        "ListSessions",
+        "Disconnect",
+    ];
+
+    /// Parse a message body
+    ///
+    /// Commands this version does not know (e.g. from a newer client) fail
+    /// with `PdbError::Unsupported`, known commands with invalid fields and
+    /// bodies that are no command at all with `PdbError::ProtocolError`.
+    pub fn decode(encoding: Encoding, body: &[u8]) -> Result<Command> {
+        let error = match encoding.decode(body) {
+            Ok(command) => return Ok(command),
+            Err(e) => e,
+        };
+        // Unit variants are plain names, the others single-key maps
+        let name = match encoding.decode::<serde_json::Value>(body) {
+            Ok(serde_json::Value::String(name)) => Some(name),
+            Ok(serde_json::Value::Object(fields)) if fields.len() == 1 => {
+                fields.keys().next().cloned()
+            }
+            _ => None,
+        };
+        Err(match name {
+            Some(name) if !Self::NAMES.contains(&name.as_str()) => {
+                PdbError::Unsupported(format!("Unknown command {}", name))
+            }
+            Some(name) => PdbError::ProtocolError(format!("Invalid {} command: {}", name, error)),
+            None => PdbError::ProtocolError(format!("Not a command: {}", error)),
+        })
+    }
+
+    /// Command name (the serialized variant name)
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Hello(_) => "Hello",
+            Command::Authenticate { .. } => "Authenticate",
+            Command::ListWindows => "ListWindows",
+            Command::Connect { .. } => "Connect",
+            Command::ConnectByHwnd { .. } => "ConnectByHwnd",
+            Command::Click { .. } => "Click",
+            Command::Swipe { .. } => "Swipe",
+            Command::Screenshot { .. } => "Screenshot",
+            Command::Subscribe { .. } => "Subscribe",
+            Command::Unsubscribe { .. } => "Unsubscribe",
+            Command::InputText { .. } => "InputText",
+            Command::KeyEvent { .. } => "KeyEvent",
+            Command::GetSize { .. } => "GetSize",
+            Command::Focus { .. } => "Focus",
+            Command::Ping => "Ping",
+            Command::Batch { .. } => "Batch",
+            Command::AcquireLease { .. } => "AcquireLease",
+            Command::RenewLease { .. } => "RenewLease",
+            Command::ReleaseLease { .. } => "ReleaseLease",
+            Command::ListSessions => "ListSessions",
+            Command::Disconnect => "Disconnect",
+        }
+    }
+
+    /// Window the command operates on, if any
+    ///
+    /// Commands for the same window are processed in order; others may run
+    /// concurrently. A batch belongs to the window its steps operate on, or
+    /// to none if they operate on several.
+    pub fn hwnd(&self) -> Option<usize> {
+        match self {
+            Command::Batch { steps, .. } => {
+                let mut windows = steps.iter().filter_map(|step| step.command.hwnd());
+                let first = windows.next();
+                if windows.all(|hwnd| Some(hwnd) == first) {
+                    first
+                } else {
+                    None
+                }
+            }
+            Command::ConnectByHwnd { hwnd }
+            | Command::Click { hwnd, .. }
+            | Command::Swipe { hwnd, .. }
+            | Command::Screenshot { hwnd, .. }
+            | Command::Subscribe { hwnd, .. }
+            | Command::InputText { hwnd, .. }
+            | Command::KeyEvent { hwnd, .. }
+            | Command::GetSize { hwnd }
+            | Command::Focus { hwnd }
+            | Command::AcquireLease { hwnd, .. }
+            | Command::RenewLease { hwnd, .. }
+            | Command::ReleaseLease { hwnd } => Some(*hwnd),
+            Command::Hello(_)
+            | Command::Authenticate { .. }
+            | Command::ListWindows
+            | Command::Connect { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Ping
+            | Command::ListSessions
+            | Command::Disconnect => None,
+        }
+    }
+
+    /// Whether the command acts on its window (input or focus) rather than
+    /// observing it
+    ///
+    /// While a window is leased, only the lease holder may send these.
+    pub fn is_input(&self) -> bool {
+        matches!(
+            self,
+            Command::Click { .. }
+                | Command::Swipe { .. }
+                | Command::InputText { .. }
+                | Command::KeyEvent { .. }
+                | Command::Focus { .. }
+        )
+    }
+}
+
+/// Step of a `Command::Batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "mcp", derive(schemars::JsonSchema))]
+pub struct BatchStep {
+    /// Milliseconds to wait before running the command (at most
+    /// `MAX_BATCH_DELAY_MS`)
+    #[serde(default)]
+    pub delay_ms: u32,
+    pub command: Command,
+}
+
+impl From<Command> for BatchStep {
+    fn from(command: Command) -> Self {
+        Self { delay_ms: 0, command }
+    }
+}
+
+/// Client greeting, sent as the first command on a connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "mcp", derive(schemars::JsonSchema))]
+pub struct Hello {
+    /// Highest protocol version the client speaks
+    pub version: u32,
+    /// Lowest protocol version the client accepts
+    pub min_version: u32,
+    /// Client software, e.g. "pdb/0.1.0"
+    pub agent: String,
+    /// Wire encodings the client accepts, most preferred first (JSON if empty)
+    #[serde(default)]
+    pub encodings: Vec<String>,
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            min_version: MIN_PROTOCOL_VERSION,
+            agent: AGENT.to_string(),
+            encodings: Encoding::ALL.iter().map(|e| e.name().to_string()).collect(),
+        }
+    }
+}
+
+/// Server capabilities, sent in reply to `Hello`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Protocol version agreed for this connection
+    pub version: u32,
+    /// Server software, e.g. "pdb/0.1.0"
+    pub agent: String,
+    /// Supported command names
+    pub commands: Vec<String>,
+    /// Supported wire encodings
+    pub encodings: Vec<String>,
+    /// Wire encoding used after the handshake (empty means JSON)
+    #[serde(default)]
+    pub encoding: String,
+    /// Platform backend driving the server (e.g. "win32", "x11")
+    pub backend: String,
+    /// Server operating system (e.g. "windows", "linux")
+    pub os: String,
+    /// Screen size, if the backend can report it
+    pub screen_size: Option<(i32, i32)>,
+}
+
+impl Capabilities {
+    /// Check whether the server supports a command
+    pub fn supports(&self, command: &str) -> bool {
+        self.commands.iter().any(|c| c == command)
+    }
+}
+
 /// Response sent from server to client
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Response {
@@ -65,34 +363,409 @@ pub enum Response {
     /// Screenshot data
     Screenshot(Screenshot),
     
+    /// Compressed screenshot
+    Image(EncodedImage),
+    
+    /// One frame of a `Subscribe` stream
+    Frame(Frame),
+    
     /// Window size
     Size { width: i32, height: i32 },
     
-    /// Error message
-    Error(String),
+    /// Handshake reply
+    Hello(Capabilities),
+    
+    /// Handshake reply of a server that requires a key: prove you hold it
+    /// with `Command::Authenticate` (see `crate::auth`)
+    Challenge(#[serde(with = "serde_bytes")] Vec<u8>),
+    
+    /// Error, with its kind from protocol 4 on (see `ErrorInfo`)
+    Error(ErrorInfo),
+    
+    /// Responses to the steps of a `Batch`, in order
+    Batch(Vec<Response>),
+    
+    /// Open sessions, oldest first
+    Sessions(Vec<SessionInfo>),
     
     /// Pong response
     Pong,
 }
 
+impl Response {
+    /// This response as a peer speaking protocol `version` expects it;
+    /// `None` if it is the same
+    ///
+    /// Before `ERROR_KIND_VERSION`, errors are plain text.
+    pub fn for_version(&self, version: u32) -> Option<Response> {
+        if version >= ERROR_KIND_VERSION {
+            return None;
+        }
+        match self {
+            Response::Error(error) if error.kind != ErrorKind::Unknown || error.details.is_some() => {
+                Some(Response::Error(error.to_string().into()))
+            }
+            Response::Batch(responses) if responses.iter().any(|r| matches!(r, Response::Error(_))) => {
+                let responses = responses
+                    .iter()
+                    .map(|r| r.for_version(version).unwrap_or_else(|| r.clone()))
+                    .collect();
+                Some(Response::Batch(responses))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Payload of `Response::Error`
+///
+/// Peers before `ERROR_KIND_VERSION` send and expect a plain message; that
+/// is what an error of kind `Unknown` without details is sent as, and what
+/// such a message is read back as.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorInfo {
+    /// What went wrong
+    pub kind: ErrorKind,
+    /// Message of the error, without the text its kind implies
+    pub message: String,
+    /// Values the error is about, e.g. `{"hwnd": 4660}` for
+    /// `DeviceNotConnected`
+    pub details: Option<serde_json::Value>,
+}
+
+impl ErrorInfo {
+    /// The `PdbError` this stands for
+    ///
+    /// Errors without a kind, as sent by servers before protocol 4, are
+    /// wrapped with `untyped`.
+    pub fn into_error(self, untyped: impl FnOnce(String) -> PdbError) -> PdbError {
+        let message = self.message;
+        match self.kind {
+            ErrorKind::WindowNotFound => PdbError::WindowNotFound(message),
+            ErrorKind::Handle | ErrorKind::Platform => PdbError::HandleError(message),
+            ErrorKind::Input => PdbError::InputError(message),
+            ErrorKind::DeviceNotConnected | ErrorKind::DeviceLeased => {
+                let hwnd = self
+                    .details
+                    .as_ref()
+                    .and_then(|details| details["hwnd"].as_u64())
+                    .and_then(|hwnd| usize::try_from(hwnd).ok());
+                match (hwnd, self.kind) {
+                    (Some(hwnd), ErrorKind::DeviceLeased) => PdbError::DeviceLeased(hwnd),
+                    (Some(hwnd), _) => PdbError::DeviceNotConnected(hwnd),
+                    (None, _) => untyped(message),
+                }
+            }
+            ErrorKind::Capture => PdbError::CaptureError(message),
+            ErrorKind::Unsupported => PdbError::Unsupported(message),
+            ErrorKind::Io => PdbError::IoError(std::io::Error::other(message)),
+            ErrorKind::Connection => PdbError::ConnectionError(message),
+            ErrorKind::Protocol => PdbError::ProtocolError(message),
+            ErrorKind::VersionMismatch => PdbError::VersionMismatch(message),
+            ErrorKind::FrameTooLarge => {
+                let size = |name: &str| {
+                    self.details
+                        .as_ref()
+                        .and_then(|details| details[name].as_u64())
+                };
+                match (size("length"), size("max")) {
+                    (Some(length), Some(max)) => PdbError::FrameTooLarge { length, max },
+                    _ => untyped(message),
+                }
+            }
+            ErrorKind::TruncatedFrame => PdbError::TruncatedFrame(message),
+            ErrorKind::Authentication => PdbError::AuthenticationFailed(message),
+            ErrorKind::PermissionDenied => PdbError::PermissionDenied(message),
+            #[cfg(feature = "tls")]
+            ErrorKind::Tls => PdbError::TlsError(message),
+            #[cfg(not(feature = "tls"))]
+            ErrorKind::Tls => PdbError::ConnectionError(message),
+            ErrorKind::Serialization => {
+                PdbError::SerializationError(serde::de::Error::custom(message))
+            }
+            ErrorKind::Image => PdbError::ImageError(image::ImageError::IoError(
+                std::io::Error::other(message),
+            )),
+            ErrorKind::Unknown => untyped(message),
+        }
+    }
+}
+
+impl From<PdbError> for ErrorInfo {
+    fn from(e: PdbError) -> Self {
+        let kind = e.kind();
+        let mut details = None;
+        let message = match e {
+            PdbError::WindowNotFound(message)
+            | PdbError::HandleError(message)
+            | PdbError::InputError(message)
+            | PdbError::CaptureError(message)
+            | PdbError::Unsupported(message)
+            | PdbError::ConnectionError(message)
+            | PdbError::ProtocolError(message)
+            | PdbError::VersionMismatch(message)
+            | PdbError::TruncatedFrame(message)
+            | PdbError::AuthenticationFailed(message)
+            | PdbError::PermissionDenied(message)
+            | PdbError::MessagePackError(message) => message,
+            #[cfg(all(unix, feature = "x11"))]
+            PdbError::X11Error(message) => message,
+            #[cfg(feature = "tls")]
+            PdbError::TlsError(message) => message,
+            PdbError::DeviceNotConnected(hwnd) | PdbError::DeviceLeased(hwnd) => {
+                details = Some(serde_json::json!({ "hwnd": hwnd }));
+                format!("0x{:X}", hwnd)
+            }
+            PdbError::FrameTooLarge { length, max } => {
+                details = Some(serde_json::json!({ "length": length, "max": max }));
+                format!("{} bytes, at most {} accepted", length, max)
+            }
+            #[cfg(windows)]
+            PdbError::WindowsError(e) => e.to_string(),
+            PdbError::IoError(e) => e.to_string(),
+            PdbError::SerializationError(e) => e.to_string(),
+            PdbError::ImageError(e) => e.to_string(),
+        };
+        Self { kind, message, details }
+    }
+}
+
+/// Error without a kind
+impl From<String> for ErrorInfo {
+    fn from(message: String) -> Self {
+        Self {
+            kind: ErrorKind::Unknown,
+            message,
+            details: None,
+        }
+    }
+}
+
+impl fmt::Display for ErrorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ErrorKind::Unknown => f.write_str(&self.message),
+            _ => write!(f, "{}", self.clone().into_error(PdbError::ProtocolError)),
+        }
+    }
+}
+
+/// `ErrorInfo` with a kind, from protocol 4 on
+#[derive(Serialize, Deserialize)]
+struct KindedError {
+    kind: ErrorKind,
+    message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    details: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorRepr {
+    Message(String),
+    Kinded(KindedError),
+}
+
+impl Serialize for ErrorInfo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.kind == ErrorKind::Unknown && self.details.is_none() {
+            return serializer.serialize_str(&self.message);
+        }
+        KindedError {
+            kind: self.kind,
+            message: self.message.clone(),
+            details: self.details.clone(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(match ErrorRepr::deserialize(deserializer)? {
+            ErrorRepr::Message(message) => message.into(),
+            ErrorRepr::Kinded(KindedError { kind, message, details }) => {
+                Self { kind, message, details }
+            }
+        })
+    }
+}
+
+/// Frame of a `Subscribe` stream or a delta `Screenshot`
+///
+/// See `crate::delta` for producing and applying frames.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    /// Sequence number, counting from 1 per device and connection
+    pub seq: u64,
+    /// Frame contents
+    pub data: FrameData,
+}
+
+/// Contents of a `Frame`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FrameData {
+    /// Complete image
+    Key(EncodedImage),
+    /// Changed regions on top of frame `base`
+    Delta { base: u64, tiles: Vec<Tile> },
+}
+
+/// Changed region of a delta frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tile {
+    /// Left edge in the frame
+    pub x: u32,
+    /// Top edge in the frame
+    pub y: u32,
+    /// New pixels of the region
+    pub image: EncodedImage,
+}
+
 /// Default server port
 pub const DEFAULT_PORT: u16 = 5037; // Same as ADB
 
 /// Protocol version
-pub const PROTOCOL_VERSION: u32 = 1;
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Oldest protocol version still accepted (1 predates the `Hello` handshake)
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// First protocol version whose headers carry a request id
+///
+/// From this version on, commands on a connection are processed
+/// concurrently and responses may arrive out of order.
+pub const REQUEST_ID_VERSION: u32 = 3;
+
+/// First protocol version whose error responses carry an `ErrorKind`
+pub const ERROR_KIND_VERSION: u32 = 4;
+
+/// Header version of the `Hello` exchange
+///
+/// Understood by every server that knows `Hello`, so newer clients can still
+/// negotiate down with older servers.
+pub const HANDSHAKE_VERSION: u32 = 2;
+
+/// Highest frame rate a subscription can ask for
+pub const MAX_FPS: u32 = 60;
+
+/// Most subscriptions running at once on one connection
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+/// Most steps a `Batch` can hold
+pub const MAX_BATCH_STEPS: usize = 256;
+
+/// Longest delay before a `Batch` step
+pub const MAX_BATCH_DELAY_MS: u32 = 60_000;
+
+/// Longest lease, and longest wait for one
+pub const MAX_LEASE_MS: u32 = 3_600_000;
+
+/// Software identifier sent in `Hello` and `Capabilities`
+pub const AGENT: &str = concat!("pdb/", env!("CARGO_PKG_VERSION"));
+
+/// Body encoding of framed messages
+///
+/// Every connection starts in JSON; the `Hello` exchange may switch it to
+/// MessagePack for all following messages. In MessagePack, pixel data is
+/// sent as length-prefixed binary blobs instead of number arrays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    /// JSON, readable when debugging
+    #[default]
+    Json,
+    /// MessagePack, compact binary
+    MessagePack,
+}
+
+impl Encoding {
+    /// All encodings, in order of preference
+    pub const ALL: [Encoding; 2] = [Encoding::MessagePack, Encoding::Json];
+
+    /// Name used in `Hello` and `Capabilities`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Json => "json",
+            Encoding::MessagePack => "msgpack",
+        }
+    }
+
+    /// Parse an encoding name
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|e| e.name() == name)
+    }
+
+    /// Serialize a message body
+    pub fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Json => Ok(serde_json::to_vec(value)?),
+            Encoding::MessagePack => Ok(rmp_serde::to_vec_named(value)?),
+        }
+    }
+
+    /// Deserialize a message body
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T> {
+        match self {
+            Encoding::Json => Ok(serde_json::from_slice(bytes)?),
+            Encoding::MessagePack => Ok(rmp_serde::from_slice(bytes)?),
+        }
+    }
+}
 
 /// Message header for framing
+///
+/// On the wire: version and body length as little-endian `u32`s, followed
+/// by the request id from `REQUEST_ID_VERSION` on.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageHeader {
     pub version: u32,
     pub length: u32,
+    /// Request id, echoed in the response (0 before `REQUEST_ID_VERSION`)
+    #[serde(default)]
+    pub id: u32,
 }
 
 impl MessageHeader {
     pub fn new(length: u32) -> Self {
-        Self {
-            version: PROTOCOL_VERSION,
-            length,
+        Self::with_version(PROTOCOL_VERSION, length)
+    }
+
+    /// Header for a connection that negotiated an older protocol version
+    pub fn with_version(version: u32, length: u32) -> Self {
+        Self { version, length, id: 0 }
+    }
+
+    /// Set the request id
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Whether this header carries a request id
+    pub fn has_id(&self) -> bool {
+        self.version >= REQUEST_ID_VERSION
+    }
+
+    /// Serialize the header
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(12);
+        bytes.extend_from_slice(&self.version.to_le_bytes());
+        bytes.extend_from_slice(&self.length.to_le_bytes());
+        if self.has_id() {
+            bytes.extend_from_slice(&self.id.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Check that the version is one this build understands
+    pub fn check_version(&self) -> Result<()> {
+        if (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&self.version) {
+            Ok(())
+        } else {
+            Err(PdbError::VersionMismatch(format!(
+                "peer speaks protocol {}, this side supports {}..={}",
+                self.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            )))
         }
     }
 }
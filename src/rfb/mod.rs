@@ -0,0 +1,237 @@
+//! RFB (VNC) protocol primitives
+//!
+//! Message constants, pixel format handling and VNC authentication shared by
+//! the VNC client backend and the RFB server. Only the subset of RFB 3.3-3.8
+//! needed by pdb is implemented.
+
+pub mod server;
+pub mod zrle;
+
+use crate::error::{PdbError, Result};
+use des::cipher::{BlockEncrypt, KeyInit};
+use des::Des;
+use std::io::{Read, Write};
+
+/// Protocol version string sent by pdb (RFB 3.8)
+pub const RFB_VERSION: &[u8; 12] = b"RFB 003.008\n";
+
+/// Security type: no authentication
+pub const SECURITY_NONE: u8 = 1;
+/// Security type: VNC DES challenge-response
+pub const SECURITY_VNC_AUTH: u8 = 2;
+
+/// Encoding: raw pixels
+pub const ENCODING_RAW: i32 = 0;
+/// Encoding: copy a rectangle from elsewhere in the framebuffer
+pub const ENCODING_COPY_RECT: i32 = 1;
+/// Encoding: zlib run-length
+pub const ENCODING_ZRLE: i32 = 16;
+/// Pseudo-encoding: framebuffer resize
+pub const ENCODING_DESKTOP_SIZE: i32 = -223;
+
+/// Client message: SetPixelFormat
+pub const CLIENT_SET_PIXEL_FORMAT: u8 = 0;
+/// Client message: SetEncodings
+pub const CLIENT_SET_ENCODINGS: u8 = 2;
+/// Client message: FramebufferUpdateRequest
+pub const CLIENT_FRAMEBUFFER_UPDATE_REQUEST: u8 = 3;
+/// Client message: KeyEvent
+pub const CLIENT_KEY_EVENT: u8 = 4;
+/// Client message: PointerEvent
+pub const CLIENT_POINTER_EVENT: u8 = 5;
+/// Client message: ClientCutText
+pub const CLIENT_CUT_TEXT: u8 = 6;
+
+/// Server message: FramebufferUpdate
+pub const SERVER_FRAMEBUFFER_UPDATE: u8 = 0;
+/// Server message: SetColourMapEntries
+pub const SERVER_SET_COLOUR_MAP_ENTRIES: u8 = 1;
+/// Server message: Bell
+pub const SERVER_BELL: u8 = 2;
+/// Server message: ServerCutText
+pub const SERVER_CUT_TEXT: u8 = 3;
+
+/// Pointer button mask bit for the left button
+pub const BUTTON_LEFT: u8 = 1;
+
+/// RFB pixel format (16 bytes on the wire)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelFormat {
+    pub bits_per_pixel: u8,
+    pub depth: u8,
+    pub big_endian: bool,
+    pub true_colour: bool,
+    pub red_max: u16,
+    pub green_max: u16,
+    pub blue_max: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl PixelFormat {
+    /// 32-bit little-endian true colour with bytes laid out as R, G, B, X
+    ///
+    /// This is the format pdb asks for, so pixels map directly onto RGBA.
+    pub fn rgbx() -> Self {
+        Self {
+            bits_per_pixel: 32,
+            depth: 24,
+            big_endian: false,
+            true_colour: true,
+            red_max: 255,
+            green_max: 255,
+            blue_max: 255,
+            red_shift: 0,
+            green_shift: 8,
+            blue_shift: 16,
+        }
+    }
+
+    /// Read from the wire
+    pub fn read_from(r: &mut impl Read) -> Result<Self> {
+        let mut buf = [0u8; 16];
+        r.read_exact(&mut buf)?;
+        Ok(Self {
+            bits_per_pixel: buf[0],
+            depth: buf[1],
+            big_endian: buf[2] != 0,
+            true_colour: buf[3] != 0,
+            red_max: u16::from_be_bytes([buf[4], buf[5]]),
+            green_max: u16::from_be_bytes([buf[6], buf[7]]),
+            blue_max: u16::from_be_bytes([buf[8], buf[9]]),
+            red_shift: buf[10],
+            green_shift: buf[11],
+            blue_shift: buf[12],
+        })
+    }
+
+    /// Encode for the wire
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut buf = [0u8; 16];
+        buf[0] = self.bits_per_pixel;
+        buf[1] = self.depth;
+        buf[2] = self.big_endian as u8;
+        buf[3] = self.true_colour as u8;
+        buf[4..6].copy_from_slice(&self.red_max.to_be_bytes());
+        buf[6..8].copy_from_slice(&self.green_max.to_be_bytes());
+        buf[8..10].copy_from_slice(&self.blue_max.to_be_bytes());
+        buf[10] = self.red_shift;
+        buf[11] = self.green_shift;
+        buf[12] = self.blue_shift;
+        buf
+    }
+}
+
+/// Read a big-endian u8
+pub fn read_u8(r: &mut impl Read) -> Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+/// Read a big-endian u16
+pub fn read_u16(r: &mut impl Read) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    r.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+/// Read a big-endian u32
+pub fn read_u32(r: &mut impl Read) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// Read a big-endian i32
+pub fn read_i32(r: &mut impl Read) -> Result<i32> {
+    Ok(read_u32(r)? as i32)
+}
+
+/// Read `len` bytes, refusing absurd lengths from a misbehaving peer
+pub fn read_bytes(r: &mut impl Read, len: usize, limit: usize) -> Result<Vec<u8>> {
+    if len > limit {
+        return Err(PdbError::ProtocolError(format!(
+            "RFB payload of {} bytes exceeds limit of {}",
+            len, limit
+        )));
+    }
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read and discard `len` bytes
+pub fn skip(r: &mut impl Read, len: u64) -> Result<()> {
+    std::io::copy(&mut r.take(len), &mut std::io::sink())?;
+    Ok(())
+}
+
+/// Parse a `RFB xxx.yyy\n` version string into (major, minor)
+pub fn parse_version(buf: &[u8; 12]) -> Result<(u32, u32)> {
+    let text = std::str::from_utf8(buf)
+        .ok()
+        .filter(|s| s.starts_with("RFB ") && s.ends_with('\n'))
+        .ok_or_else(|| PdbError::ProtocolError("Not an RFB server".into()))?;
+    let parse = |s: &str| {
+        s.parse::<u32>()
+            .map_err(|_| PdbError::ProtocolError(format!("Bad RFB version: {:?}", text)))
+    };
+    Ok((parse(&text[4..7])?, parse(&text[8..11])?))
+}
+
+/// Compute the VNC authentication response for a 16-byte challenge
+///
+/// The password (truncated/padded to 8 bytes, each byte bit-reversed) is the
+/// DES key used to encrypt the challenge.
+pub fn vnc_auth_response(challenge: &[u8; 16], password: &str) -> [u8; 16] {
+    let mut key = [0u8; 8];
+    for (k, b) in key.iter_mut().zip(password.bytes()) {
+        *k = b.reverse_bits();
+    }
+    let cipher = Des::new(&key.into());
+
+    let mut response = *challenge;
+    for block in response.chunks_exact_mut(8) {
+        cipher.encrypt_block(block.into());
+    }
+    response
+}
+
+/// Write a KeyEvent message
+pub fn write_key_event(w: &mut impl Write, keysym: u32, down: bool) -> Result<()> {
+    let mut msg = [0u8; 8];
+    msg[0] = CLIENT_KEY_EVENT;
+    msg[1] = down as u8;
+    msg[4..8].copy_from_slice(&keysym.to_be_bytes());
+    w.write_all(&msg)?;
+    Ok(())
+}
+
+/// Write a PointerEvent message
+pub fn write_pointer_event(w: &mut impl Write, buttons: u8, x: u16, y: u16) -> Result<()> {
+    let mut msg = [0u8; 6];
+    msg[0] = CLIENT_POINTER_EVENT;
+    msg[1] = buttons;
+    msg[2..4].copy_from_slice(&x.to_be_bytes());
+    msg[4..6].copy_from_slice(&y.to_be_bytes());
+    w.write_all(&msg)?;
+    Ok(())
+}
+
+/// Write a FramebufferUpdateRequest message
+pub fn write_update_request(
+    w: &mut impl Write,
+    incremental: bool,
+    width: u16,
+    height: u16,
+) -> Result<()> {
+    let mut msg = [0u8; 10];
+    msg[0] = CLIENT_FRAMEBUFFER_UPDATE_REQUEST;
+    msg[1] = incremental as u8;
+    msg[6..8].copy_from_slice(&width.to_be_bytes());
+    msg[8..10].copy_from_slice(&height.to_be_bytes());
+    w.write_all(&msg)?;
+    Ok(())
+}
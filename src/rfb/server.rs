@@ -0,0 +1,564 @@
+//! RFB (VNC) server exposing a single `Device`
+//!
+//! Lets standard VNC viewers watch and drive one window. The framebuffer is
+//! the window's client area, captured with `Device::screenshot_client` and
+//! sent as Raw rectangles covering only the 64x64 tiles that changed. Pointer
+//! and key events are translated into `Device` calls in client coordinates:
+//!
+//! * a left-button press and release within a few pixels is a `click`
+//! * a left-button drag is replayed as a `swipe` on release
+//! * printable keysyms are typed with `input_text`, other keys are sent with
+//!   `key_event` (modifier keys on their own are ignored)
+//!
+//! A view-only server (`RfbServer::with_view_only`) ignores all viewer
+//! input. One made by `Server::rfb_server_for_title` follows that server's
+//! policy and drops input while another client holds a lease on the window.
+
+use super::{
+    PixelFormat, BUTTON_LEFT, CLIENT_CUT_TEXT, CLIENT_FRAMEBUFFER_UPDATE_REQUEST, CLIENT_KEY_EVENT,
+    CLIENT_POINTER_EVENT, CLIENT_SET_ENCODINGS, CLIENT_SET_PIXEL_FORMAT, ENCODING_DESKTOP_SIZE,
+    ENCODING_RAW, RFB_VERSION, SECURITY_NONE, SECURITY_VNC_AUTH, SERVER_FRAMEBUFFER_UPDATE,
+};
+use crate::backend::keysym_char;
+use crate::delta::dirty_rects;
+use crate::device::Device;
+use crate::error::{PdbError, Result};
+use crate::session::Session;
+use crate::types::{KeyCode, Screenshot};
+use log::{error, info, warn};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum pointer travel (in pixels) for a press/release to count as a click
+const CLICK_SLOP: i32 = 3;
+
+/// Upper bound for client cut text
+const MAX_CUT_TEXT: u64 = 1 << 20;
+
+/// Serves one device to VNC viewers
+#[derive(Debug, Clone)]
+pub struct RfbServer {
+    device: Device,
+    password: Option<String>,
+    poll_interval: Duration,
+    view_only: bool,
+    leases: Option<Leases>,
+}
+
+/// Session of the `Server` whose leases viewer input respects
+#[derive(Clone)]
+struct Leases(Arc<Session>);
+
+impl fmt::Debug for Leases {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Leases")
+    }
+}
+
+impl RfbServer {
+    /// Create a server for a device, without authentication
+    pub fn new(device: Device) -> Self {
+        Self {
+            device,
+            password: None,
+            poll_interval: Duration::from_millis(100),
+            view_only: false,
+            leases: None,
+        }
+    }
+
+    /// Let viewers watch only, ignoring their pointer and key events
+    pub fn with_view_only(mut self, view_only: bool) -> Self {
+        self.view_only = view_only;
+        self
+    }
+
+    /// Drop viewer input while another session of the server holds a lease
+    /// on the window
+    pub(crate) fn with_leases(mut self, session: Session) -> Self {
+        self.leases = Some(Leases(Arc::new(session)));
+        self
+    }
+
+    /// Require VNC authentication with this password (max. 8 bytes are used)
+    pub fn with_password(mut self, password: &str) -> Self {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// Set how often the window is re-captured while a viewer waits for changes
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Get the served device
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    /// Listen on `addr` (e.g. `"0.0.0.0:5900"`) and serve viewers until an error
+    pub fn run(&self, addr: &str) -> Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!(
+            "RFB server for \"{}\" listening on {}",
+            self.device.info().title,
+            addr
+        );
+        self.serve(listener)
+    }
+
+    /// Serve viewers accepted from an existing listener, one thread each
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = self.clone();
+                    thread::spawn(move || {
+                        if let Ok(addr) = stream.peer_addr() {
+                            info!("New RFB viewer from {}", addr);
+                        }
+                        if let Err(e) = server.handle_client(stream) {
+                            error!("RFB connection error: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Accept error: {}", e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the RFB protocol on a connected viewer until it disconnects
+    pub fn handle_client(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_nodelay(true)?;
+        self.handshake(&mut stream)?;
+
+        // ClientInit: the shared flag does not matter, viewers never exclude each other
+        let _shared = super::read_u8(&mut stream)?;
+
+        // ServerInit
+        let frame = self.device.screenshot_client()?;
+        let size = frame_size(&frame)?;
+        let name = self.device.info().title.as_bytes();
+        let mut init = Vec::with_capacity(24 + name.len());
+        init.extend_from_slice(&size.0.to_be_bytes());
+        init.extend_from_slice(&size.1.to_be_bytes());
+        init.extend_from_slice(&PixelFormat::rgbx().to_bytes());
+        init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+        init.extend_from_slice(name);
+        stream.write_all(&init)?;
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(ClientState {
+                format: PixelFormat::rgbx(),
+                desktop_size: false,
+                request: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let reader = stream.try_clone()?;
+        let reader_shared = shared.clone();
+        let mut input = Input {
+            device: self.device.clone(),
+            view_only: self.view_only,
+            leases: self.leases.clone(),
+            pressed: None,
+        };
+        thread::spawn(move || {
+            if let Err(e) = read_loop(reader, &reader_shared, &mut input) {
+                log::debug!("RFB viewer stopped: {}", e);
+            }
+            reader_shared.state().closed = true;
+            reader_shared.changed.notify_all();
+        });
+
+        let result = self.update_loop(&mut stream, &shared, size);
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+        result
+    }
+
+    /// Exchange protocol versions and authenticate the viewer
+    fn handshake(&self, stream: &mut TcpStream) -> Result<()> {
+        stream.write_all(RFB_VERSION)?;
+        let mut version = [0u8; 12];
+        stream.read_exact(&mut version)?;
+        let (major, minor) = super::parse_version(&version)?;
+        if major != 3 {
+            return Err(PdbError::ProtocolError(format!(
+                "Unsupported RFB version {}.{}",
+                major, minor
+            )));
+        }
+        let minor = match minor {
+            8.. => 8,
+            7 => 7,
+            _ => 3,
+        };
+
+        let security = if self.password.is_some() {
+            SECURITY_VNC_AUTH
+        } else {
+            SECURITY_NONE
+        };
+        if minor >= 7 {
+            stream.write_all(&[1, security])?;
+            let chosen = super::read_u8(stream)?;
+            if chosen != security {
+                return Err(PdbError::ProtocolError(format!(
+                    "Viewer chose unoffered security type {}",
+                    chosen
+                )));
+            }
+        } else {
+            stream.write_all(&(security as u32).to_be_bytes())?;
+        }
+
+        if let Some(password) = &self.password {
+            let challenge = challenge()?;
+            stream.write_all(&challenge)?;
+            let mut response = [0u8; 16];
+            stream.read_exact(&mut response)?;
+            if response != super::vnc_auth_response(&challenge, password) {
+                stream.write_all(&1u32.to_be_bytes())?;
+                if minor >= 8 {
+                    let reason = b"authentication failed";
+                    stream.write_all(&(reason.len() as u32).to_be_bytes())?;
+                    stream.write_all(reason)?;
+                }
+                return Err(PdbError::ConnectionError(
+                    "VNC authentication failed".to_string(),
+                ));
+            }
+        }
+
+        // RFB 3.8 always sends a SecurityResult; older versions skip it for None
+        if self.password.is_some() || minor >= 8 {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Answer FramebufferUpdateRequests until the viewer disconnects
+    ///
+    /// Incremental requests are only answered once something changed, so the
+    /// window is re-captured every poll interval while one is pending.
+    fn update_loop(
+        &self,
+        stream: &mut TcpStream,
+        shared: &Shared,
+        mut size: (u16, u16),
+    ) -> Result<()> {
+        // Pixels the viewer currently shows, at `size`
+        let mut shown: Option<Vec<u8>> = None;
+        // Pending request: Some(incremental)
+        let mut pending: Option<bool> = None;
+
+        loop {
+            let (format, desktop_size) = {
+                let mut state = shared.state();
+                if pending.is_some() && state.request.is_none() && !state.closed {
+                    state = shared
+                        .changed
+                        .wait_timeout(state, self.poll_interval)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                }
+                while pending.is_none() && state.request.is_none() && !state.closed {
+                    state = shared
+                        .changed
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                if state.closed {
+                    return Ok(());
+                }
+                if let Some(incremental) = state.request.take() {
+                    pending = Some(pending.map_or(incremental, |p| p && incremental));
+                }
+                (state.format, state.desktop_size)
+            };
+            let incremental = pending.unwrap_or(false);
+
+            let frame = self.device.screenshot_client()?;
+            let frame_size = frame_size(&frame)?;
+            let mut msg = vec![SERVER_FRAMEBUFFER_UPDATE, 0, 0, 0];
+            let mut count: u16 = 0;
+
+            if frame_size != size && desktop_size {
+                size = frame_size;
+                shown = None;
+                rect_header(&mut msg, 0, 0, size.0, size.1, ENCODING_DESKTOP_SIZE);
+                count += 1;
+            }
+            let pixels = if frame_size == size {
+                frame.data
+            } else {
+                fit(&frame, size)
+            };
+
+            let rects = match &shown {
+                Some(old) if incremental => {
+                    dirty_rects(old, &pixels, size.0 as u32, size.1 as u32)
+                        .into_iter()
+                        .map(|(x, y, w, h)| (x as u16, y as u16, w as u16, h as u16))
+                        .collect()
+                }
+                _ => vec![(0, 0, size.0, size.1)],
+            };
+            if rects.is_empty() && count == 0 {
+                continue;
+            }
+
+            let stride = size.0 as usize * 4;
+            for &(x, y, w, h) in &rects {
+                rect_header(&mut msg, x, y, w, h, ENCODING_RAW);
+                for row in y as usize..(y + h) as usize {
+                    let start = row * stride + x as usize * 4;
+                    encode_pixels(&format, &pixels[start..start + w as usize * 4], &mut msg);
+                }
+            }
+            count += rects.len() as u16;
+            msg[2..4].copy_from_slice(&count.to_be_bytes());
+            stream.write_all(&msg)?;
+            stream.flush()?;
+
+            shown = Some(pixels);
+            pending = None;
+        }
+    }
+}
+
+/// Viewer settings and requests shared between the reader and update threads
+#[derive(Debug)]
+struct ClientState {
+    format: PixelFormat,
+    /// Viewer understands the DesktopSize pseudo-encoding
+    desktop_size: bool,
+    /// Pending FramebufferUpdateRequest: Some(incremental)
+    request: Option<bool>,
+    /// Viewer disconnected
+    closed: bool,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<ClientState>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, ClientState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Translates viewer input into device operations
+struct Input {
+    device: Device,
+    /// Ignore all input
+    view_only: bool,
+    leases: Option<Leases>,
+    /// Position and time of the left-button press in progress
+    pressed: Option<(i32, i32, Instant)>,
+}
+
+impl Input {
+    /// Fail if another client leased the window
+    fn check_lease(&self) -> Result<()> {
+        match &self.leases {
+            Some(Leases(session)) => session.check_lease(self.device.hwnd()),
+            None => Ok(()),
+        }
+    }
+
+    fn pointer(&mut self, buttons: u8, x: i32, y: i32) {
+        if self.view_only {
+            return;
+        }
+        let down = buttons & BUTTON_LEFT != 0;
+        let result = match (self.pressed.take(), down) {
+            (None, true) => {
+                self.pressed = Some((x, y, Instant::now()));
+                Ok(())
+            }
+            (Some(press), true) => {
+                self.pressed = Some(press);
+                Ok(())
+            }
+            (Some((x1, y1, at)), false) => self.check_lease().and_then(|_| {
+                if (x - x1).abs() <= CLICK_SLOP && (y - y1).abs() <= CLICK_SLOP {
+                    self.device.click(x1, y1)
+                } else {
+                    let duration_ms = at.elapsed().as_millis().min(u32::MAX as u128) as u32;
+                    self.device.swipe(x1, y1, x, y, duration_ms)
+                }
+            }),
+            (None, false) => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("RFB pointer input failed: {}", e);
+        }
+    }
+
+    fn key(&mut self, keysym: u32, down: bool) {
+        if !down || self.view_only {
+            return;
+        }
+        let result = if let Err(e) = self.check_lease() {
+            Err(e)
+        } else if let Some(ch) = keysym_char(keysym) {
+            self.device.input_text(ch.encode_utf8(&mut [0u8; 4]))
+        } else {
+            match KeyCode::from_keysym(keysym) {
+                Some(
+                    KeyCode::Shift
+                    | KeyCode::Ctrl
+                    | KeyCode::Alt
+                    | KeyCode::CapsLock
+                    | KeyCode::LWin
+                    | KeyCode::RWin,
+                )
+                | None => Ok(()),
+                Some(key) => self.device.key_event(key),
+            }
+        };
+        if let Err(e) = result {
+            warn!("RFB key input failed: {}", e);
+        }
+    }
+}
+
+/// Reader thread: handle viewer messages until the connection fails
+fn read_loop(mut stream: TcpStream, shared: &Shared, input: &mut Input) -> Result<()> {
+    loop {
+        match super::read_u8(&mut stream)? {
+            CLIENT_SET_PIXEL_FORMAT => {
+                super::skip(&mut stream, 3)?;
+                let format = PixelFormat::read_from(&mut stream)?;
+                check_format(&format)?;
+                shared.state().format = format;
+            }
+            CLIENT_SET_ENCODINGS => {
+                super::skip(&mut stream, 1)?;
+                let count = super::read_u16(&mut stream)?;
+                let mut desktop_size = false;
+                for _ in 0..count {
+                    desktop_size |= super::read_i32(&mut stream)? == ENCODING_DESKTOP_SIZE;
+                }
+                shared.state().desktop_size = desktop_size;
+            }
+            CLIENT_FRAMEBUFFER_UPDATE_REQUEST => {
+                let incremental = super::read_u8(&mut stream)? != 0;
+                // The requested region is ignored; updates cover the whole window
+                super::skip(&mut stream, 8)?;
+                let mut state = shared.state();
+                state.request = Some(state.request.map_or(incremental, |p| p && incremental));
+                shared.changed.notify_all();
+            }
+            CLIENT_KEY_EVENT => {
+                let down = super::read_u8(&mut stream)? != 0;
+                super::skip(&mut stream, 2)?;
+                let keysym = super::read_u32(&mut stream)?;
+                input.key(keysym, down);
+            }
+            CLIENT_POINTER_EVENT => {
+                let buttons = super::read_u8(&mut stream)?;
+                let x = super::read_u16(&mut stream)?;
+                let y = super::read_u16(&mut stream)?;
+                input.pointer(buttons, x as i32, y as i32);
+            }
+            CLIENT_CUT_TEXT => {
+                super::skip(&mut stream, 3)?;
+                let len = super::read_u32(&mut stream)? as u64;
+                if len > MAX_CUT_TEXT {
+                    return Err(PdbError::ProtocolError("RFB cut text too large".into()));
+                }
+                super::skip(&mut stream, len)?;
+            }
+            other => {
+                return Err(PdbError::ProtocolError(format!(
+                    "Unknown RFB client message {}",
+                    other
+                )))
+            }
+        }
+    }
+}
+
+/// Only true-colour formats with whole-byte pixels are supported
+fn check_format(format: &PixelFormat) -> Result<()> {
+    let shifts_ok = [format.red_shift, format.green_shift, format.blue_shift]
+        .iter()
+        .all(|&s| s < format.bits_per_pixel);
+    if !format.true_colour || ![8, 16, 32].contains(&format.bits_per_pixel) || !shifts_ok {
+        return Err(PdbError::ProtocolError(format!(
+            "Unsupported RFB pixel format {:?}",
+            format
+        )));
+    }
+    Ok(())
+}
+
+/// Random VNC authentication challenge, from the OS's secure source
+fn challenge() -> Result<[u8; 16]> {
+    let mut challenge = [0u8; 16];
+    getrandom::getrandom(&mut challenge)
+        .map_err(|e| PdbError::ProtocolError(format!("no randomness: {}", e)))?;
+    Ok(challenge)
+}
+
+fn frame_size(frame: &Screenshot) -> Result<(u16, u16)> {
+    let width = u16::try_from(frame.width);
+    let height = u16::try_from(frame.height);
+    match (width, height) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(PdbError::CaptureError(format!(
+            "{}x{} frame is too large for RFB",
+            frame.width, frame.height
+        ))),
+    }
+}
+
+/// Crop or pad a frame to the size announced to a viewer without DesktopSize
+fn fit(frame: &Screenshot, (width, height): (u16, u16)) -> Vec<u8> {
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    let row_len = (width as usize).min(frame.width as usize) * 4;
+    for row in 0..(height as usize).min(frame.height as usize) {
+        let src = row * frame.width as usize * 4;
+        let dst = row * width as usize * 4;
+        pixels[dst..dst + row_len].copy_from_slice(&frame.data[src..src + row_len]);
+    }
+    pixels
+}
+
+fn rect_header(msg: &mut Vec<u8>, x: u16, y: u16, w: u16, h: u16, encoding: i32) {
+    for v in [x, y, w, h] {
+        msg.extend_from_slice(&v.to_be_bytes());
+    }
+    msg.extend_from_slice(&encoding.to_be_bytes());
+}
+
+/// Convert RGBA pixels to the viewer's pixel format
+fn encode_pixels(format: &PixelFormat, rgba: &[u8], out: &mut Vec<u8>) {
+    let bytes = format.bits_per_pixel as usize / 8;
+    let scale = |c: u8, max: u16| (c as u32 * max as u32 + 127) / 255;
+    for px in rgba.chunks_exact(4) {
+        let value = (scale(px[0], format.red_max) << format.red_shift)
+            | (scale(px[1], format.green_max) << format.green_shift)
+            | (scale(px[2], format.blue_max) << format.blue_shift);
+        if format.big_endian {
+            out.extend_from_slice(&value.to_be_bytes()[4 - bytes..]);
+        } else {
+            out.extend_from_slice(&value.to_le_bytes()[..bytes]);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! ZRLE (encoding 16) decoder
+//!
+//! Assumes the `PixelFormat::rgbx` format negotiated by pdb, for which each
+//! compressed pixel (CPIXEL) is the three R, G, B bytes.
+
+use super::{read_u32, read_u8};
+use crate::error::{PdbError, Result};
+use flate2::{Decompress, FlushDecompress, Status};
+use std::io::Read;
+
+/// ZRLE tile size
+const TILE: usize = 64;
+
+/// Stateful ZRLE decoder
+///
+/// The zlib stream spans the whole connection, so one decoder must be used
+/// for every ZRLE rectangle received from a server.
+pub struct ZrleDecoder {
+    inflater: Decompress,
+}
+
+impl std::fmt::Debug for ZrleDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ZrleDecoder").finish_non_exhaustive()
+    }
+}
+
+impl Default for ZrleDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ZrleDecoder {
+    /// Create a decoder for a new connection
+    pub fn new() -> Self {
+        Self {
+            inflater: Decompress::new(true),
+        }
+    }
+
+    /// Read one ZRLE rectangle body and return its RGBA pixels
+    pub fn decode(&mut self, r: &mut impl Read, width: usize, height: usize) -> Result<Vec<u8>> {
+        let len = read_u32(r)? as usize;
+        // Worst case is plain RLE with one run per pixel: 3 + 1 bytes each,
+        // plus a few bytes per tile; anything far beyond that is garbage.
+        let limit = width * height * 5 + 1024;
+        let compressed = super::read_bytes(r, len, limit)?;
+        let data = self.inflate(&compressed, limit)?;
+
+        let mut pixels = vec![0u8; width * height * 4];
+        let mut cursor = data.as_slice();
+        for ty in (0..height).step_by(TILE) {
+            for tx in (0..width).step_by(TILE) {
+                let tw = TILE.min(width - tx);
+                let th = TILE.min(height - ty);
+                let tile = decode_tile(&mut cursor, tw, th)?;
+                for row in 0..th {
+                    let dst = ((ty + row) * width + tx) * 4;
+                    let src = row * tw * 4;
+                    pixels[dst..dst + tw * 4].copy_from_slice(&tile[src..src + tw * 4]);
+                }
+            }
+        }
+        Ok(pixels)
+    }
+
+    fn inflate(&mut self, input: &[u8], limit: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(input.len() * 4);
+        let mut consumed = 0;
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(input.len().max(4096));
+            }
+            let before_in = self.inflater.total_in();
+            let before_out = self.inflater.total_out();
+            let status = self
+                .inflater
+                .decompress_vec(&input[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| PdbError::ProtocolError(format!("ZRLE inflate failed: {}", e)))?;
+            let read = (self.inflater.total_in() - before_in) as usize;
+            let produced = self.inflater.total_out() - before_out;
+            consumed += read;
+
+            if out.len() > limit {
+                return Err(PdbError::ProtocolError("ZRLE data too large".into()));
+            }
+            // Done once all input is consumed and the output buffer was not
+            // filled up, i.e. nothing is left pending inside the inflater
+            if status == Status::StreamEnd
+                || (consumed == input.len() && out.len() < out.capacity())
+            {
+                return Ok(out);
+            }
+            if read == 0 && produced == 0 {
+                return Err(PdbError::ProtocolError("ZRLE inflate stalled".into()));
+            }
+        }
+    }
+}
+
+/// Read one CPIXEL as RGBA
+fn read_cpixel(r: &mut impl Read) -> Result<[u8; 4]> {
+    let mut buf = [0u8; 3];
+    r.read_exact(&mut buf)?;
+    Ok([buf[0], buf[1], buf[2], 255])
+}
+
+fn read_palette(r: &mut impl Read, size: usize) -> Result<Vec<[u8; 4]>> {
+    (0..size).map(|_| read_cpixel(r)).collect()
+}
+
+/// Read an RLE run length (sum of bytes until one is not 255, plus one)
+fn read_run_length(r: &mut impl Read) -> Result<usize> {
+    let mut len = 1;
+    loop {
+        let b = read_u8(r)?;
+        len += b as usize;
+        if b != 255 {
+            return Ok(len);
+        }
+    }
+}
+
+fn decode_tile(r: &mut impl Read, tw: usize, th: usize) -> Result<Vec<u8>> {
+    let count = tw * th;
+    let mut tile = Vec::with_capacity(count * 4);
+    let subencoding = read_u8(r)?;
+
+    match subencoding {
+        // Raw
+        0 => {
+            for _ in 0..count {
+                tile.extend_from_slice(&read_cpixel(r)?);
+            }
+        }
+        // Solid
+        1 => {
+            let pixel = read_cpixel(r)?;
+            for _ in 0..count {
+                tile.extend_from_slice(&pixel);
+            }
+        }
+        // Packed palette
+        2..=16 => {
+            let palette = read_palette(r, subencoding as usize)?;
+            let bits = match subencoding {
+                2 => 1,
+                3..=4 => 2,
+                _ => 4,
+            };
+            let row_bytes = (tw * bits).div_ceil(8);
+            let mut row = vec![0u8; row_bytes];
+            for _ in 0..th {
+                r.read_exact(&mut row)?;
+                for x in 0..tw {
+                    let bit = x * bits;
+                    let index = (row[bit / 8] >> (8 - bits - bit % 8)) & ((1 << bits) - 1);
+                    let pixel = palette.get(index as usize).ok_or_else(|| {
+                        PdbError::ProtocolError("ZRLE palette index out of range".into())
+                    })?;
+                    tile.extend_from_slice(pixel);
+                }
+            }
+        }
+        // Plain RLE
+        128 => {
+            while tile.len() < count * 4 {
+                let pixel = read_cpixel(r)?;
+                let run = read_run_length(r)?;
+                push_run(&mut tile, pixel, run, count)?;
+            }
+        }
+        // Palette RLE
+        130..=255 => {
+            let palette = read_palette(r, (subencoding - 128) as usize)?;
+            while tile.len() < count * 4 {
+                let index = read_u8(r)?;
+                let run = if index & 0x80 != 0 {
+                    read_run_length(r)?
+                } else {
+                    1
+                };
+                let pixel = palette.get((index & 0x7F) as usize).ok_or_else(|| {
+                    PdbError::ProtocolError("ZRLE palette index out of range".into())
+                })?;
+                push_run(&mut tile, *pixel, run, count)?;
+            }
+        }
+        _ => {
+            return Err(PdbError::ProtocolError(format!(
+                "Invalid ZRLE subencoding {}",
+                subencoding
+            )))
+        }
+    }
+
+    Ok(tile)
+}
+
+fn push_run(tile: &mut Vec<u8>, pixel: [u8; 4], run: usize, count: usize) -> Result<()> {
+    if tile.len() / 4 + run > count {
+        return Err(PdbError::ProtocolError("ZRLE run exceeds tile".into()));
+    }
+    for _ in 0..run {
+        tile.extend_from_slice(&pixel);
+    }
+    Ok(())
+}
@@ -0,0 +1,421 @@
+//! JSON-RPC 2.0 over a byte stream, for editors and agent frameworks
+//!
+//! `RpcServer::serve` reads one JSON-RPC request (or batch) per line and
+//! writes one response per line, as tools expect on stdin/stdout
+//! (`pdb-client rpc`). Methods are the commands in snake case, with the
+//! command's fields as named parameters:
+//!
+//! ```text
+//! --> {"jsonrpc": "2.0", "id": 1, "method": "click", "params": {"hwnd": 4660, "x": 10, "y": 20}}
+//! <-- {"jsonrpc": "2.0", "id": 1, "result": null}
+//! ```
+//!
+//! Calls run against this machine's windows (`RpcServer::local`) or a
+//! server's (`RpcServer::remote`). Windows are connected on first use.
+//! Screenshots default to PNG and are returned as
+//! `{"width", "height", "format", "data"}` with base64 data. Failed commands
+//! carry their `ErrorKind` as `{"kind"}` in the error's data.
+
+use crate::client::Client;
+use crate::controller::WindowController;
+use crate::error::{ErrorKind, PdbError, Result};
+use crate::protocol::{Command, ErrorInfo, Response};
+use crate::server::{self, Encoders};
+use crate::session::{Session, Sessions};
+use crate::types::ScreenshotFormat;
+use base64::Engine;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::Mutex;
+
+/// Invalid JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// Not a JSON-RPC 2.0 request
+pub const INVALID_REQUEST: i64 = -32600;
+/// Unknown method
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Missing or mistyped parameters
+pub const INVALID_PARAMS: i64 = -32602;
+/// Unexpected failure
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Command failed without a more specific code (e.g. reported by a server)
+pub const SERVER_ERROR: i64 = -32000;
+/// No window matches
+pub const WINDOW_NOT_FOUND: i64 = -32001;
+/// Input could not be delivered
+pub const INPUT_FAILED: i64 = -32002;
+/// Screenshot could not be taken
+pub const CAPTURE_FAILED: i64 = -32003;
+/// Not supported by the backend
+pub const UNSUPPORTED: i64 = -32004;
+/// Connection to the server failed
+pub const CONNECTION_FAILED: i64 = -32005;
+/// Another client holds a lease on the window
+pub const DEVICE_LEASED: i64 = -32006;
+/// The server's policy does not allow the call
+pub const PERMISSION_DENIED: i64 = -32007;
+
+/// Methods and the commands they run
+const METHODS: &[(&str, &str)] = &[
+    ("ping", "Ping"),
+    ("list_windows", "ListWindows"),
+    ("connect", "Connect"),
+    ("connect_by_hwnd", "ConnectByHwnd"),
+    ("click", "Click"),
+    ("swipe", "Swipe"),
+    ("screenshot", "Screenshot"),
+    ("input_text", "InputText"),
+    ("key_event", "KeyEvent"),
+    ("get_size", "GetSize"),
+    ("focus", "Focus"),
+    ("acquire_lease", "AcquireLease"),
+    ("renew_lease", "RenewLease"),
+    ("release_lease", "ReleaseLease"),
+    ("list_sessions", "ListSessions"),
+];
+
+/// JSON-RPC error object
+#[derive(Debug, Clone, Serialize)]
+pub struct RpcError {
+    /// One of the codes in this module
+    pub code: i64,
+    /// Description of the failure
+    pub message: String,
+    /// Additional information, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Error of a command that refused to run, where protocol errors are
+    /// about its arguments (e.g. a zero lease duration)
+    fn rejected(e: PdbError) -> Self {
+        match e {
+            PdbError::ProtocolError(_) => Self {
+                data: Some(json!({ "kind": e.kind() })),
+                ..Self::new(INVALID_PARAMS, e.to_string())
+            },
+            e => e.into(),
+        }
+    }
+}
+
+impl From<PdbError> for RpcError {
+    fn from(e: PdbError) -> Self {
+        let code = match &e {
+            PdbError::WindowNotFound(_) | PdbError::DeviceNotConnected(_) => WINDOW_NOT_FOUND,
+            PdbError::InputError(_) => INPUT_FAILED,
+            PdbError::DeviceLeased(_) => DEVICE_LEASED,
+            PdbError::PermissionDenied(_) => PERMISSION_DENIED,
+            PdbError::CaptureError(_) => CAPTURE_FAILED,
+            PdbError::Unsupported(_) => UNSUPPORTED,
+            // Replies that could not be decoded; see `RpcError::rejected`
+            // for commands that refused their arguments
+            PdbError::IoError(_)
+            | PdbError::ProtocolError(_)
+            | PdbError::ConnectionError(_)
+            | PdbError::VersionMismatch(_)
+            | PdbError::AuthenticationFailed(_) => CONNECTION_FAILED,
+            _ => SERVER_ERROR,
+        };
+        Self {
+            data: Some(json!({ "kind": e.kind() })),
+            ..Self::new(code, e.to_string())
+        }
+    }
+}
+
+impl From<ErrorInfo> for RpcError {
+    fn from(error: ErrorInfo) -> Self {
+        match error.kind {
+            // From a server before protocol 4
+            ErrorKind::Unknown => Self::new(SERVER_ERROR, error.message),
+            _ => Self::rejected(error.into_error(PdbError::ProtocolError)),
+        }
+    }
+}
+
+/// Where commands run
+enum Target {
+    /// Windows of this machine
+    Local {
+        controller: WindowController,
+        session: Session,
+    },
+    /// Windows of a server
+    Remote(Client),
+}
+
+/// Answers JSON-RPC calls by running pdb commands
+pub struct RpcServer {
+    target: Target,
+    /// Windows already connected
+    connected: Mutex<HashSet<usize>>,
+}
+
+impl RpcServer {
+    /// Control the windows of this machine
+    pub fn local(controller: WindowController) -> Self {
+        Self::new(Target::Local {
+            controller,
+            session: Sessions::default().detached(),
+        })
+    }
+
+    /// Control the windows of the server `client` is connected to
+    pub fn remote(client: Client) -> Self {
+        Self::new(Target::Remote(client))
+    }
+
+    fn new(target: Target) -> Self {
+        Self {
+            target,
+            connected: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Answer requests, one per line, until `input` ends
+    pub async fn serve<R, W>(&self, input: R, output: W) -> Result<()>
+    where
+        R: AsyncRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        serve(self, input, output).await
+    }
+
+    /// Answer one request or batch; `None` when nothing is to be sent back
+    pub async fn handle_message(&self, message: &str) -> Option<Value> {
+        handle_message(self, message).await
+    }
+
+    /// Run a method with named parameters
+    pub async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+        let Some((_, name)) = METHODS.iter().find(|(m, _)| *m == method) else {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("no method {}", method),
+            ));
+        };
+        let Value::Object(mut params) = params else {
+            return Err(RpcError::new(INVALID_PARAMS, "parameters must be named"));
+        };
+        if *name == "Screenshot" {
+            // A delta frame has no JSON result
+            params.remove("base");
+            params
+                .entry("format")
+                .or_insert(json!(ScreenshotFormat::Png));
+        }
+        let command = command(name, params)?;
+        result(self.run(command).await?)
+    }
+
+    /// Run a command, connecting its window first if needed
+    pub(crate) async fn run(&self, command: Command) -> std::result::Result<Response, RpcError> {
+        let mut connected = self.connected.lock().await;
+        if let Some(hwnd) = command.hwnd() {
+            if !connected.contains(&hwnd) && !matches!(command, Command::ConnectByHwnd { .. }) {
+                self.execute(Command::ConnectByHwnd { hwnd }).await?;
+                connected.insert(hwnd);
+            }
+        }
+        drop(connected);
+
+        let response = self.execute(command).await?;
+        if let Response::Window(info) = &response {
+            self.connected.lock().await.insert(info.hwnd);
+        }
+        Ok(response)
+    }
+
+    async fn execute(&self, command: Command) -> std::result::Result<Response, RpcError> {
+        match &self.target {
+            Target::Local {
+                controller,
+                session,
+            } => {
+                // Calls are independent, so there are no previous frames to diff
+                server::execute(command, controller, session, &Encoders::default())
+                    .await
+                    .map_err(RpcError::rejected)
+            }
+            Target::Remote(client) => match client.send_command(command).await? {
+                Response::Error(error) => Err(error.into()),
+                response => Ok(response),
+            },
+        }
+    }
+}
+
+impl Methods for RpcServer {
+    async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError> {
+        RpcServer::call(self, method, params).await
+    }
+}
+
+/// Answers JSON-RPC method calls
+pub(crate) trait Methods {
+    async fn call(&self, method: &str, params: Value) -> std::result::Result<Value, RpcError>;
+}
+
+/// Answer requests, one per line, until `input` ends
+pub(crate) async fn serve<R, W>(methods: &impl Methods, input: R, mut output: W) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(input).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(reply) = handle_message(methods, &line).await {
+            output.write_all(reply.to_string().as_bytes()).await?;
+            output.write_all(b"\n").await?;
+            output.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Answer one request or batch; `None` when nothing is to be sent back
+pub(crate) async fn handle_message(methods: &impl Methods, message: &str) -> Option<Value> {
+    let message: Value = match serde_json::from_str(message) {
+        Ok(message) => message,
+        Err(e) => {
+            return Some(error_reply(
+                Value::Null,
+                RpcError::new(PARSE_ERROR, e.to_string()),
+            ))
+        }
+    };
+    match message {
+        Value::Array(batch) if batch.is_empty() => Some(error_reply(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "empty batch"),
+        )),
+        Value::Array(batch) => {
+            let mut replies = Vec::new();
+            for request in batch {
+                replies.extend(handle_request(methods, request).await);
+            }
+            (!replies.is_empty()).then_some(Value::Array(replies))
+        }
+        request => handle_request(methods, request).await,
+    }
+}
+
+async fn handle_request(methods: &impl Methods, request: Value) -> Option<Value> {
+    let Value::Object(mut request) = request else {
+        return Some(error_reply(
+            Value::Null,
+            RpcError::new(INVALID_REQUEST, "not an object"),
+        ));
+    };
+    let id = request.remove("id");
+    let version = request.remove("jsonrpc");
+    let method = match (version, request.remove("method")) {
+        (Some(version), Some(Value::String(method))) if version == "2.0" => method,
+        _ => {
+            let error = RpcError::new(INVALID_REQUEST, "expected a JSON-RPC 2.0 request");
+            return Some(error_reply(id.unwrap_or(Value::Null), error));
+        }
+    };
+    let params = request
+        .remove("params")
+        .unwrap_or(Value::Object(Map::new()));
+
+    let result = methods.call(&method, params).await;
+    // Notifications get no reply
+    let id = id?;
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => error_reply(id, error),
+    })
+}
+
+/// Command `name` with the given fields
+pub(crate) fn command(
+    name: &str,
+    fields: Map<String, Value>,
+) -> std::result::Result<Command, RpcError> {
+    let parse = |body| {
+        let mut command = Map::new();
+        command.insert(name.to_string(), body);
+        serde_json::from_value::<Command>(Value::Object(command))
+    };
+    let empty = fields.is_empty();
+    parse(Value::Object(fields))
+        // Commands without fields are unit variants
+        .or_else(|e| {
+            if empty {
+                parse(Value::Null).map_err(|_| e)
+            } else {
+                Err(e)
+            }
+        })
+        .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+/// JSON result of a command
+fn result(response: Response) -> std::result::Result<Value, RpcError> {
+    match response {
+        Response::Ok => Ok(Value::Null),
+        Response::Pong => Ok(Value::Bool(true)),
+        Response::Windows(windows) => Ok(json!(windows)),
+        Response::Sessions(sessions) => Ok(json!(sessions)),
+        Response::Window(window) => Ok(json!(window)),
+        Response::Size { width, height } => Ok(json!({ "width": width, "height": height })),
+        Response::Image(image) => Ok(image_json(
+            image.width,
+            image.height,
+            image.format,
+            &image.data,
+        )),
+        Response::Screenshot(shot) => Ok(image_json(
+            shot.width,
+            shot.height,
+            ScreenshotFormat::Raw,
+            &shot.data,
+        )),
+        Response::Error(error) => Err(error.into()),
+        other => Err(RpcError::new(
+            INTERNAL_ERROR,
+            format!("unexpected response {:?}", other),
+        )),
+    }
+}
+
+/// Image with base64 data
+fn image_json(width: u32, height: u32, format: ScreenshotFormat, data: &[u8]) -> Value {
+    json!({
+        "width": width,
+        "height": height,
+        "format": format_name(format),
+        "data": base64::engine::general_purpose::STANDARD.encode(data),
+    })
+}
+
+/// Short name of an image format ("raw" is RGBA pixels)
+pub(crate) fn format_name(format: ScreenshotFormat) -> &'static str {
+    match format {
+        ScreenshotFormat::Raw => "raw",
+        ScreenshotFormat::Png => "png",
+        ScreenshotFormat::Jpeg { .. } => "jpeg",
+        ScreenshotFormat::WebP => "webp",
+    }
+}
+
+fn error_reply(id: Value, error: RpcError) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
@@ -1,50 +1,350 @@
 //! Server implementation for remote connections
 
+use crate::auth;
+use crate::backend::{self, Backend};
 use crate::controller::WindowController;
+use crate::delta::DeltaEncoder;
 use crate::device::Device;
-use crate::error::Result;
-use crate::protocol::{Command, MessageHeader, Response, DEFAULT_PORT};
+use crate::error::{PdbError, Result};
+use crate::framing::{self, FrameLimits};
+#[cfg(feature = "http")]
+use crate::http::{self, Gateway};
+use crate::policy::{Policies, Policy};
+use crate::protocol::{
+    BatchStep, Capabilities, Command, Encoding, Hello, MessageHeader, Response, AGENT,
+    DEFAULT_PORT, HANDSHAKE_VERSION, MAX_BATCH_DELAY_MS, MAX_BATCH_STEPS, MAX_FPS,
+    MAX_LEASE_MS, MAX_SUBSCRIPTIONS, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, REQUEST_ID_VERSION,
+};
+#[cfg(feature = "vnc")]
+use crate::rfb::server::RfbServer;
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsServerConfig};
+use crate::session::{Session, SessionInfo, Sessions};
+use crate::transport::{Address, Listener, Transport};
+#[cfg(feature = "vnc")]
+use crate::types::KeyCode;
+use crate::types::{Screenshot, ScreenshotFormat, WindowInfo};
+#[cfg(feature = "websocket")]
+use crate::websocket;
 use log::{error, info};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, WriteHalf};
+#[cfg(feature = "tls")]
+use tokio_rustls::TlsAcceptor;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+/// Last frame sent per device on a connection, for delta screenshots
+pub(crate) type Encoders = Arc<std::sync::Mutex<HashMap<usize, DeltaEncoder>>>;
+
+/// Commands queued per window before a connection stops reading
+const LANE_DEPTH: usize = 32;
+
+/// Responses queued for writing before senders have to wait
+const OUTGOING_DEPTH: usize = 16;
+
+/// Server state every connection works with
+#[derive(Clone)]
+pub(crate) struct Shared {
+    /// Platform backend used for all devices
+    pub(crate) backend: Arc<dyn Backend>,
+    /// Open sessions, each owning the devices it attached
+    pub(crate) sessions: Sessions,
+    /// Keys a client may authenticate with (none: no authentication)
+    pub(crate) keys: Arc<Vec<Vec<u8>>>,
+    /// What clients may do, by the key they authenticated with
+    pub(crate) policies: Arc<Policies>,
+    /// Bounds for frames read from clients
+    pub(crate) limits: FrameLimits,
+    /// Origins web pages may open WebSockets from (none: no page may)
+    #[cfg(feature = "websocket")]
+    pub(crate) origins: Arc<Vec<String>>,
+}
+
+impl Shared {
+    pub(crate) fn controller(&self) -> WindowController {
+        WindowController::with_backend(self.backend.clone())
+    }
+
+    /// Policy of a client authenticated with `keys[key]`, or without a key
+    pub(crate) fn policy(&self, key: Option<usize>) -> Arc<Policy> {
+        self.policies.for_key(key.map(|key| self.keys[key].as_slice()))
+    }
+
+    /// Open a session under the policy of clients without a key
+    pub(crate) fn open_session(&self, peer: impl Into<String>, endpoint: &'static str) -> Session {
+        let session = self.sessions.open(peer, endpoint);
+        session.set_policy(self.policy(None));
+        session
+    }
+}
+
+/// Protocol spoken on a listener
+#[derive(Clone)]
+enum Endpoint {
+    /// Framed `Command`/`Response` messages
+    Framed,
+    /// One `Command`/`Response` per WebSocket message
+    #[cfg(feature = "websocket")]
+    WebSocket,
+    /// REST requests over HTTP/1.1, run in the gateway's sessions as they
+    /// do not share a connection
+    #[cfg(feature = "http")]
+    Http(Arc<Gateway>),
+}
 
 /// PDB Server - listens for remote connections (like ADB daemon)
 pub struct Server {
     /// Server address
     addr: String,
-    /// Connected devices (hwnd -> Device)
-    devices: Arc<Mutex<HashMap<usize, Device>>>,
+    shared: Shared,
+    /// Session of commands run through `handle_command`
+    local: Session,
+    /// TLS settings, if connections are encrypted
+    #[cfg(feature = "tls")]
+    tls: Option<TlsServerConfig>,
 }
 
 impl Server {
     /// Create a new server
     pub fn new(addr: &str) -> Self {
+        Self::with_backend(addr, backend::default_backend())
+    }
+
+    /// Create a new server on top of a specific backend
+    pub fn with_backend(addr: &str, backend: Arc<dyn Backend>) -> Self {
+        let sessions = Sessions::default();
         Self {
             addr: addr.to_string(),
-            devices: Arc::new(Mutex::new(HashMap::new())),
+            local: sessions.detached(),
+            shared: Shared {
+                backend,
+                sessions,
+                keys: Arc::new(Vec::new()),
+                policies: Arc::default(),
+                limits: FrameLimits::server(),
+                #[cfg(feature = "websocket")]
+                origins: Arc::new(Vec::new()),
+            },
+            #[cfg(feature = "tls")]
+            tls: None,
         }
     }
 
+    /// Only accept TLS connections
+    #[cfg(feature = "tls")]
+    pub fn with_tls(mut self, tls: TlsServerConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Require clients to prove they hold `key` before accepting commands
+    ///
+    /// May be called more than once; any of the keys is accepted (e.g. while
+    /// rotating keys).
+    pub fn with_key(mut self, key: impl Into<Vec<u8>>) -> Self {
+        Arc::make_mut(&mut self.shared.keys).push(key.into());
+        self
+    }
+
+    /// Restrict what clients may do (see `crate::policy`)
+    ///
+    /// Applies to every client, except those authenticated with a key added
+    /// by `with_key_policy`, and to `handle_command`.
+    pub fn with_policy(mut self, policy: Policy) -> Self {
+        let policy = Arc::new(policy);
+        self.local.set_policy(policy.clone());
+        Arc::make_mut(&mut self.shared.policies).default = policy;
+        self
+    }
+
+    /// Accept `key` like `with_key`, granting clients holding it `policy`
+    /// instead of the server's (e.g. `Policy::read_only()` for viewers)
+    pub fn with_key_policy(mut self, key: impl Into<Vec<u8>>, policy: Policy) -> Self {
+        let key = key.into();
+        Arc::make_mut(&mut self.shared.policies)
+            .keys
+            .insert(key.clone(), Arc::new(policy));
+        self.with_key(key)
+    }
+
+    /// Largest frame accepted from clients, in bytes
+    /// (default `framing::DEFAULT_SERVER_MAX_FRAME`)
+    ///
+    /// Longer frames are answered with an error and the connection is closed.
+    pub fn with_max_frame(mut self, bytes: u32) -> Self {
+        self.shared.limits.max_frame = bytes;
+        self
+    }
+
+    /// Time a frame may take to arrive once it started (default
+    /// `framing::DEFAULT_READ_TIMEOUT`; `None` waits forever)
+    ///
+    /// Idle connections are not affected.
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.shared.limits.read_timeout = timeout;
+        self
+    }
+
+    /// Only accept WebSockets opened by pages from this origin
+    /// (e.g. "https://panel.example"); may be called more than once
+    ///
+    /// Without any, only clients that send no `Origin` header (i.e. not
+    /// browsers) may connect.
+    #[cfg(feature = "websocket")]
+    pub fn with_websocket_origin(mut self, origin: &str) -> Self {
+        Arc::make_mut(&mut self.shared.origins).push(origin.to_string());
+        self
+    }
+
     /// Create server with default port
     pub fn default_addr() -> Self {
         Self::new(&format!("0.0.0.0:{}", DEFAULT_PORT))
     }
 
+    /// Handle a single command against this server's devices
+    ///
+    /// This is what every connection does for each received command; it is
+    /// exposed so the dispatch logic can be driven without a socket. These
+    /// commands share one session, which is not listed by `sessions`.
+    pub async fn handle_command(&self, command: Command) -> Response {
+        let controller = self.shared.controller();
+        // No connection, so no previous frames to send deltas against
+        handle_command(command, &controller, &self.local, &Encoders::default()).await
+    }
+
+    /// Serve the window whose title contains `title` to VNC viewers under
+    /// this server's rules
+    ///
+    /// The window is found through this server's backend, so viewers drive
+    /// the same window its clients do. Viewers get the policy of clients
+    /// without a key: the window must be allowed, and a policy that denies
+    /// input makes the endpoint view-only. Viewer input is dropped while a
+    /// client of this server holds a lease on the window. Viewers are not
+    /// asked for this server's keys; give the endpoint a password
+    /// (`RfbServer::with_password`) when clients must authenticate.
+    #[cfg(feature = "vnc")]
+    pub fn rfb_server_for_title(&self, title: &str) -> Result<RfbServer> {
+        let controller = self.shared.controller();
+        let device = controller.device(controller.find_window(title)?);
+        let policy = self.shared.policy(None);
+        policy.check_window(device.info())?;
+        let (hwnd, text) = (device.hwnd(), String::new());
+        let input = [
+            Command::Click { hwnd, x: 0, y: 0 },
+            Command::Swipe { hwnd, x1: 0, y1: 0, x2: 0, y2: 0, duration_ms: 0 },
+            Command::InputText { hwnd, text },
+            Command::KeyEvent { hwnd, key: KeyCode::Enter },
+        ];
+        let view_only = input.iter().any(|command| policy.check(command).is_err());
+        Ok(RfbServer::new(device)
+            .with_view_only(view_only)
+            .with_leases(self.shared.sessions.detached()))
+    }
+
+    /// Sessions currently open on this server, oldest first
+    ///
+    /// Every connection has one, and the HTTP gateway one for all its
+    /// requests; see `crate::session`.
+    pub async fn sessions(&self) -> Vec<SessionInfo> {
+        self.shared.sessions.list().await
+    }
+
     /// Start the server
+    ///
+    /// The address is `host:port` for TCP or `unix:/path/to.sock` for a Unix
+    /// domain socket.
     pub async fn start(&self) -> Result<()> {
-        let listener = TcpListener::bind(&self.addr).await?;
+        let listener = Listener::bind(&Address::parse(&self.addr)?).await?;
         info!("PDB Server listening on {}", self.addr);
+        self.serve(listener).await
+    }
+
+    /// Serve connections accepted from an existing listener
+    /// (`tokio::net::TcpListener`, `UnixListener` or `transport::Listener`)
+    pub async fn serve(&self, listener: impl Into<Listener>) -> Result<()> {
+        self.accept_loop(listener.into(), Endpoint::Framed).await
+    }
 
+    /// Start a WebSocket endpoint for browsers on `addr`
+    ///
+    /// Runs alongside `start` on the same server state.
+    #[cfg(feature = "websocket")]
+    pub async fn start_websocket(&self, addr: &str) -> Result<()> {
+        let listener = Listener::bind(&Address::parse(addr)?).await?;
+        info!("PDB WebSocket endpoint listening on {}", addr);
+        self.serve_websocket(listener).await
+    }
+
+    /// Serve WebSocket connections accepted from an existing listener
+    ///
+    /// Each text message carries one JSON `Command` and is answered with a
+    /// JSON `Response` text message; binary messages do the same in
+    /// MessagePack. Commands are answered in order. With TLS configured the
+    /// endpoint speaks `wss://`.
+    #[cfg(feature = "websocket")]
+    pub async fn serve_websocket(&self, listener: impl Into<Listener>) -> Result<()> {
+        self.accept_loop(listener.into(), Endpoint::WebSocket).await
+    }
+
+    /// Start an HTTP REST gateway on `addr`
+    ///
+    /// Runs alongside `start` on the same server state.
+    #[cfg(feature = "http")]
+    pub async fn start_http(&self, addr: &str) -> Result<()> {
+        let listener = Listener::bind(&Address::parse(addr)?).await?;
+        info!("PDB HTTP gateway listening on {}", addr);
+        self.serve_http(listener).await
+    }
+
+    /// Serve HTTP requests on connections accepted from an existing listener
+    ///
+    /// Routes such as `GET /windows`, `POST /devices/{hwnd}/click` or
+    /// `GET /devices/{hwnd}/screenshot.png` run the matching `Command` and
+    /// answer with JSON or image data; errors come back as
+    /// `{"error": "..."}` with a matching status code. A server with keys
+    /// expects `Authorization: Bearer <key>`. With TLS configured the
+    /// gateway speaks HTTPS. Devices attached through the gateway stay
+    /// attached while it is served; requests with a key that has its own
+    /// policy run in a session of their own.
+    #[cfg(feature = "http")]
+    pub async fn serve_http(&self, listener: impl Into<Listener>) -> Result<()> {
+        let listener = listener.into();
+        let addr = match &listener {
+            Listener::Tcp(listener) => listener.local_addr()?.to_string(),
+            #[cfg(unix)]
+            Listener::Unix(_) => "local socket".to_string(),
+        };
+        let gateway = Gateway::open(&self.shared, addr);
+        self.accept_loop(listener, Endpoint::Http(Arc::new(gateway))).await
+    }
+
+    async fn accept_loop(&self, listener: Listener, endpoint: Endpoint) -> Result<()> {
+        #[cfg(feature = "tls")]
+        let acceptor = self.tls.as_ref().map(TlsServerConfig::acceptor).transpose()?;
         loop {
             match listener.accept().await {
                 Ok((stream, addr)) => {
                     info!("New connection from {}", addr);
-                    let devices = self.devices.clone();
+                    let shared = self.shared.clone();
+                    let endpoint = endpoint.clone();
+                    #[cfg(feature = "tls")]
+                    let acceptor = acceptor.clone();
                     tokio::spawn(async move {
-                        if let Err(e) = handle_connection(stream, devices).await {
+                        if let Err(e) = accept_connection(
+                            stream,
+                            #[cfg(feature = "tls")]
+                            acceptor,
+                            shared,
+                            endpoint,
+                            addr,
+                        )
+                        .await
+                        {
                             error!("Connection error: {}", e);
                         }
                     });
@@ -55,179 +355,779 @@ impl Server {
             }
         }
     }
+
+    /// Serve a single connection over any byte stream, until it closes
+    ///
+    /// The TLS handshake runs first if the server uses TLS.
+    pub async fn serve_connection(&self, stream: impl Transport) -> Result<()> {
+        accept_connection(
+            stream,
+            #[cfg(feature = "tls")]
+            self.tls.as_ref().map(TlsServerConfig::acceptor).transpose()?,
+            self.shared.clone(),
+            Endpoint::Framed,
+            "stream".to_string(),
+        )
+        .await
+    }
 }
 
-/// Handle a single client connection
-async fn handle_connection(
-    mut stream: TcpStream,
-    devices: Arc<Mutex<HashMap<usize, Device>>>,
+/// State shared by the tasks serving one connection
+struct Connection {
+    controller: WindowController,
+    session: Session,
+    encoders: Encoders,
+    /// Complete messages for the writer task, so aborting a task that is
+    /// replying can never leave half a message on the wire
+    outgoing: mpsc::Sender<Vec<u8>>,
+}
+
+impl Connection {
+    async fn handle(&self, command: Command) -> Response {
+        handle_command(command, &self.controller, &self.session, &self.encoders).await
+    }
+
+    /// Send a response to the request with `header`
+    async fn reply(
+        &self,
+        header: &MessageHeader,
+        encoding: Encoding,
+        response: &Response,
+    ) -> Result<()> {
+        let message = encode_response(header, encoding, response)?;
+        self.outgoing
+            .send(message)
+            .await
+            .map_err(|_| PdbError::ConnectionError("connection closed".into()))
+    }
+}
+
+/// Write queued messages until every sender is gone
+async fn write_messages<S: AsyncWrite>(
+    mut writer: WriteHalf<S>,
+    mut outgoing: mpsc::Receiver<Vec<u8>>,
+) {
+    while let Some(message) = outgoing.recv().await {
+        if let Err(e) = framing::write_frame(&mut writer, &message).await {
+            error!("Connection error: {}", e);
+            return;
+        }
+    }
+}
+
+/// Command waiting in a lane
+struct Job {
+    header: MessageHeader,
+    encoding: Encoding,
+    command: Command,
+}
+
+/// Run the TLS handshake, if the server uses TLS, and serve the connection
+async fn accept_connection(
+    stream: impl Transport,
+    #[cfg(feature = "tls")] acceptor: Option<TlsAcceptor>,
+    shared: Shared,
+    endpoint: Endpoint,
+    peer: String,
 ) -> Result<()> {
-    let controller = WindowController::new();
+    #[cfg(feature = "tls")]
+    if let Some(acceptor) = acceptor {
+        let stream = tls::accept(&acceptor, stream).await?;
+        return serve_endpoint(stream, shared, endpoint, peer).await;
+    }
+    serve_endpoint(stream, shared, endpoint, peer).await
+}
 
-    loop {
-        // Read message header (8 bytes: version u32 + length u32)
-        let mut header_buf = [0u8; 8];
-        match stream.read_exact(&mut header_buf).await {
-            Ok(_) => {}
-            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+async fn serve_endpoint(
+    stream: impl Transport,
+    shared: Shared,
+    endpoint: Endpoint,
+    peer: String,
+) -> Result<()> {
+    match endpoint {
+        Endpoint::Framed => {
+            let session = shared.open_session(peer, "pdb");
+            handle_connection(stream, shared, session).await
+        }
+        #[cfg(feature = "websocket")]
+        Endpoint::WebSocket => {
+            let session = shared.open_session(peer, "websocket");
+            websocket::handle_connection(stream, shared, session).await
+        }
+        #[cfg(feature = "http")]
+        Endpoint::Http(gateway) => http::handle_connection(stream, shared, gateway).await,
+    }
+}
+
+/// Progress of a connection through authentication
+pub(crate) enum Auth {
+    /// Commands are accepted; `key` is the index of the key the client
+    /// authenticated with, if the server has keys
+    Done { key: Option<usize> },
+    /// Waiting for `Hello`
+    Required,
+    /// Challenge sent, capabilities held back until it is answered
+    Challenged { challenge: Vec<u8>, capabilities: Response },
+}
+
+impl Auth {
+    pub(crate) fn new(keys: &[Vec<u8>]) -> Self {
+        if keys.is_empty() {
+            Auth::Done { key: None }
+        } else {
+            Auth::Required
+        }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self, Auth::Done { .. })
+    }
+
+    /// Index of the key the client authenticated with
+    pub(crate) fn key(&self) -> Option<usize> {
+        match self {
+            Auth::Done { key } => *key,
+            _ => None,
+        }
+    }
+
+    /// Answer a command received before authentication completed
+    ///
+    /// `Hello` gets a challenge, a correct `Authenticate` the held back
+    /// capabilities. Anything else fails with the reason to send before
+    /// closing the connection.
+    pub(crate) fn handle(
+        &mut self,
+        command: Command,
+        keys: &[Vec<u8>],
+        controller: &WindowController,
+    ) -> std::result::Result<Response, String> {
+        match (command, std::mem::replace(self, Auth::Required)) {
+            (Command::Hello(h), _) => match hello(&h, controller) {
+                capabilities @ Response::Hello(_) => {
+                    let challenge = auth::challenge().map_err(|e| e.to_string())?;
+                    *self = Auth::Challenged { challenge: challenge.clone(), capabilities };
+                    Ok(Response::Challenge(challenge))
+                }
+                other => Ok(other),
+            },
+            (Command::Authenticate { proof }, Auth::Challenged { challenge, capabilities }) => {
+                match auth::find_key(keys, &challenge, &proof) {
+                    Some(key) => {
+                        *self = Auth::Done { key: Some(key) };
+                        Ok(capabilities)
+                    }
+                    None => Err("wrong key".to_string()),
+                }
+            }
+            (Command::Authenticate { .. }, _) => Err("wrong key".to_string()),
+            _ => Err("authentication required".to_string()),
+        }
+    }
+}
+
+/// Handle a single client connection
+///
+/// When the server has keys, nothing but the `Hello`/`Authenticate`
+/// exchange is accepted until the client answered the challenge; any other
+/// command or a wrong proof gets an error and the connection is closed.
+///
+/// Up to protocol 2, commands are answered one at a time in order. From
+/// `REQUEST_ID_VERSION` on, each window gets its own lane: commands for one
+/// window run in order, different windows run concurrently, and responses
+/// are matched to requests by id.
+///
+/// The connection's devices are released when it closes.
+async fn handle_connection<S>(stream: S, shared: Shared, session: Session) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (mut reader, writer) = tokio::io::split(stream);
+    let (outgoing, queue) = mpsc::channel(OUTGOING_DEPTH);
+    let writer = tokio::spawn(write_messages(writer, queue));
+    let connection = Arc::new(Connection {
+        controller: shared.controller(),
+        session,
+        encoders: Encoders::default(),
+        outgoing,
+    });
+    // Switched by the Hello exchange
+    let mut encoding = Encoding::Json;
+    let mut auth = Auth::new(&shared.keys);
+    // Command queues by window (`None` for commands without one)
+    let mut lanes: HashMap<Option<usize>, Lane> = HashMap::new();
+    // Running subscriptions by request id
+    let mut subscriptions: HashMap<u32, JoinHandle<()>> = HashMap::new();
+
+    let result = loop {
+        let (header, body_buf) = match framing::read_frame(&mut reader, &shared.limits).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
                 info!("Client disconnected");
-                return Ok(());
+                break Ok(());
+            }
+            Err(e @ (PdbError::VersionMismatch(_) | PdbError::FrameTooLarge { .. })) => {
+                let response = Response::Error(e.to_string().into());
+                // Answer in the header layout every version understands
+                let reply_header = MessageHeader::with_version(HANDSHAKE_VERSION, 0);
+                connection.reply(&reply_header, encoding, &response).await?;
+                break Err(e);
             }
-            Err(e) => return Err(e.into()),
+            Err(e) => break Err(e),
+        };
+
+        // Parse command; one this server cannot read is answered, not fatal,
+        // unless the client still has to authenticate
+        let command = match Command::decode(encoding, &body_buf) {
+            Ok(command) => command,
+            Err(e) => {
+                connection.reply(&header, encoding, &Response::Error(e.into())).await?;
+                if auth.is_done() {
+                    continue;
+                }
+                break Err(PdbError::AuthenticationFailed("authentication required".into()));
+            }
+        };
+
+        if !auth.is_done() {
+            let response = match auth.handle(command, &shared.keys, &connection.controller) {
+                Ok(response) => response,
+                Err(reason) => {
+                    let response = Response::Error(reason.clone().into());
+                    connection.reply(&header, encoding, &response).await?;
+                    break Err(PdbError::AuthenticationFailed(reason));
+                }
+            };
+            connection.reply(&header, encoding, &response).await?;
+            if auth.is_done() {
+                connection.session.set_policy(shared.policy(auth.key()));
+            }
+            // The negotiated encoding applies once the handshake is complete
+            if let Response::Hello(caps) = &response {
+                encoding = Encoding::from_name(&caps.encoding).unwrap_or_default();
+            }
+            continue;
         }
 
-        let header: MessageHeader = {
-            let version = u32::from_le_bytes(header_buf[0..4].try_into().unwrap());
-            let length = u32::from_le_bytes(header_buf[4..8].try_into().unwrap());
-            MessageHeader { version, length }
+        // Older protocols (and the handshake) are answered in order
+        if !header.has_id() || matches!(command, Command::Hello(_)) {
+            let response = connection.handle(command).await;
+            // Send response in the version the client speaks
+            connection.reply(&header, encoding, &response).await?;
+
+            // The negotiated encoding applies from the next message on
+            if let Response::Hello(caps) = &response {
+                encoding = Encoding::from_name(&caps.encoding).unwrap_or_default();
+            }
+            continue;
+        }
+
+        if let Err(e) = connection.session.policy().check(&command) {
+            connection.reply(&header, encoding, &Response::Error(e.into())).await?;
+            continue;
+        }
+        match command {
+            Command::Subscribe { hwnd, fps, format, scale, delta } => {
+                subscriptions.retain(|_, task| !task.is_finished());
+                // A reused id replaces the subscription it named
+                if let Some(task) = subscriptions.remove(&header.id) {
+                    task.abort();
+                }
+                let device = connection.session.device(hwnd).await;
+                let response = match device {
+                    _ if subscriptions.len() >= MAX_SUBSCRIPTIONS => Response::Error(
+                        PdbError::ProtocolError(format!(
+                            "At most {} subscriptions per connection",
+                            MAX_SUBSCRIPTIONS
+                        ))
+                        .into(),
+                    ),
+                    Some(device) if (1..=MAX_FPS).contains(&fps) => {
+                        let subscription = Subscription { device, fps, format, scale, delta };
+                        connection.reply(&header, encoding, &Response::Ok).await?;
+                        let task = tokio::spawn(stream_frames(
+                            connection.clone(),
+                            header.clone(),
+                            encoding,
+                            subscription,
+                        ));
+                        subscriptions.insert(header.id, task);
+                        continue;
+                    }
+                    Some(_) => Response::Error(
+                        PdbError::ProtocolError(format!("fps must be between 1 and {}", MAX_FPS))
+                            .into(),
+                    ),
+                    None => Response::Error(PdbError::DeviceNotConnected(hwnd).into()),
+                };
+                connection.reply(&header, encoding, &response).await?;
+            }
+
+            // Streams of the released devices end with them
+            Command::Disconnect => {
+                for (_, task) in subscriptions.drain() {
+                    task.abort();
+                }
+                let response = connection.handle(Command::Disconnect).await;
+                connection.reply(&header, encoding, &response).await?;
+            }
+
+            Command::Unsubscribe { id } => {
+                let response = match subscriptions.remove(&id) {
+                    Some(task) => {
+                        task.abort();
+                        Response::Ok
+                    }
+                    None => Response::Error(
+                        PdbError::ProtocolError(format!("No subscription with id {}", id)).into(),
+                    ),
+                };
+                connection.reply(&header, encoding, &response).await?;
+            }
+
+            command => {
+                let hwnd = command.hwnd();
+                if !lanes.contains_key(&hwnd) {
+                    // Lanes of windows no longer in use would pile up otherwise
+                    lanes.retain(|_, lane| !lane.is_idle());
+                }
+                let lane = lanes.entry(hwnd).or_insert_with(|| Lane::spawn(connection.clone()));
+                let job = Job { header, encoding, command };
+                if lane.send(job).await.is_err() {
+                    break Ok(());
+                }
+            }
+        }
+    };
+
+    // Let queued commands finish and their responses go out before closing
+    for task in subscriptions.values() {
+        task.abort();
+    }
+    drop(lanes);
+    drop(connection);
+    let _ = writer.await;
+    result
+}
+
+/// Task answering the commands of one window in order
+struct Lane {
+    sender: mpsc::Sender<Job>,
+    /// Commands sent and not yet answered
+    pending: Arc<AtomicUsize>,
+}
+
+impl Lane {
+    fn spawn(connection: Arc<Connection>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<Job>(LANE_DEPTH);
+        let pending = Arc::new(AtomicUsize::new(0));
+        let answered = pending.clone();
+        tokio::spawn(async move {
+            while let Some(job) = receiver.recv().await {
+                let response = connection.handle(job.command).await;
+                let sent = connection.reply(&job.header, job.encoding, &response).await;
+                answered.fetch_sub(1, Ordering::Release);
+                if let Err(e) = sent {
+                    error!("Connection error: {}", e);
+                    return;
+                }
+            }
+        });
+        Self { sender, pending }
+    }
+
+    async fn send(&self, job: Job) -> std::result::Result<(), mpsc::error::SendError<Job>> {
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        self.sender.send(job).await
+    }
+
+    /// Whether dropping the lane loses nothing: only the connection's read
+    /// loop sends, so no command can arrive in between
+    fn is_idle(&self) -> bool {
+        self.pending.load(Ordering::Acquire) == 0
+    }
+}
+
+/// Frame a response, echoing the request's version and id
+fn encode_response(
+    request: &MessageHeader,
+    encoding: Encoding,
+    response: &Response,
+) -> Result<Vec<u8>> {
+    let downgraded = response.for_version(request.version);
+    let response_body = encoding.encode(downgraded.as_ref().unwrap_or(response))?;
+    framing::encode_frame(request.version, request.id, &response_body)
+}
+
+/// Parameters of an accepted `Subscribe` command
+struct Subscription {
+    device: Device,
+    fps: u32,
+    format: ScreenshotFormat,
+    scale: Option<f32>,
+    delta: bool,
+}
+
+/// Push frames to a subscriber until the task is aborted
+///
+/// Frames are captured on a fixed schedule; ticks that pass while a frame is
+/// still being captured or written are skipped, so a slow subscriber gets
+/// fewer frames instead of a growing backlog.
+async fn stream_frames(
+    connection: Arc<Connection>,
+    header: MessageHeader,
+    encoding: Encoding,
+    subscription: Subscription,
+) {
+    let Subscription { device, fps, format, scale, delta } = subscription;
+    info!("Streaming {} at {} fps", device.hwnd(), fps);
+
+    let mut ticker = tokio::time::interval(Duration::from_secs(1) / fps);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    // Shared with the capture task; frames are encoded one at a time
+    let encoder = Arc::new(std::sync::Mutex::new(DeltaEncoder::new()));
+
+    loop {
+        ticker.tick().await;
+
+        let device = device.clone();
+        let encoder = encoder.clone();
+        let captured = tokio::task::spawn_blocking(move || {
+            let mut encoder = encoder.lock().unwrap();
+            // The subscriber holds every frame sent so far
+            let base = delta.then(|| encoder.seq());
+            capture(&device, scale).and_then(|screenshot| encoder.encode(screenshot, format, base))
+        })
+        .await;
+        let response = match captured {
+            Ok(Ok(frame)) => Response::Frame(frame),
+            Ok(Err(e)) => Response::Error(e.into()),
+            Err(e) => Response::Error(PdbError::CaptureError(e.to_string()).into()),
         };
+        let failed = matches!(response, Response::Error(_));
+        if connection.reply(&header, encoding, &response).await.is_err() || failed {
+            break;
+        }
+    }
 
-        // Read message body
-        let mut body_buf = vec![0u8; header.length as usize];
-        stream.read_exact(&mut body_buf).await?;
+    info!("Subscription {} ended", header.id);
+}
 
-        // Parse command
-        let command: Command = serde_json::from_slice(&body_buf)?;
-        
-        // Handle command
-        let response = handle_command(command, &controller, &devices).await;
+/// Negotiate the protocol version and describe this server
+fn hello(hello: &Hello, controller: &WindowController) -> Response {
+    let version = hello.version.min(PROTOCOL_VERSION);
+    if version < hello.min_version.max(MIN_PROTOCOL_VERSION) {
+        return Response::Error(
+            format!(
+                "client speaks protocol {}..={}, server speaks {}..={}",
+                hello.min_version, hello.version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            )
+            .into(),
+        );
+    }
 
-        // Send response
-        let response_json = serde_json::to_vec(&response)?;
-        let resp_header = MessageHeader::new(response_json.len() as u32);
-        
-        let mut resp_header_buf = [0u8; 8];
-        resp_header_buf[0..4].copy_from_slice(&resp_header.version.to_le_bytes());
-        resp_header_buf[4..8].copy_from_slice(&resp_header.length.to_le_bytes());
-        
-        stream.write_all(&resp_header_buf).await?;
-        stream.write_all(&response_json).await?;
-        stream.flush().await?;
+    // First encoding the client prefers that we support
+    let encoding = hello
+        .encodings
+        .iter()
+        .find_map(|name| Encoding::from_name(name))
+        .unwrap_or_default();
+
+    let backend = controller.backend();
+    Response::Hello(Capabilities {
+        version,
+        agent: AGENT.to_string(),
+        commands: Command::NAMES.iter().map(|c| c.to_string()).collect(),
+        encodings: Encoding::ALL.iter().map(|e| e.name().to_string()).collect(),
+        encoding: encoding.name().to_string(),
+        backend: backend.name().to_string(),
+        os: std::env::consts::OS.to_string(),
+        screen_size: backend.screen_size().ok(),
+    })
+}
+
+/// Capture and optionally downscale a screenshot
+fn capture(device: &Device, scale: Option<f32>) -> Result<Screenshot> {
+    let screenshot = device.screenshot()?;
+    match scale {
+        Some(factor) => screenshot.scaled(factor),
+        None => Ok(screenshot),
     }
 }
 
+/// Capture, scale and encode a screenshot
+fn screenshot(device: &Device, format: ScreenshotFormat, scale: Option<f32>) -> Result<Response> {
+    let screenshot = capture(device, scale)?;
+    Ok(match format {
+        ScreenshotFormat::Raw => Response::Screenshot(screenshot),
+        format => Response::Image(screenshot.encode(format)?),
+    })
+}
+
+/// Run a blocking call on a device of the session off the async runtime
+async fn with_device<F>(session: &Session, hwnd: usize, call: F) -> Result<Response>
+where
+    F: FnOnce(&Device) -> Result<Response> + Send + 'static,
+{
+    let device = session.device(hwnd).await;
+    let Some(device) = device else {
+        return Err(PdbError::DeviceNotConnected(hwnd));
+    };
+    tokio::task::spawn_blocking(move || call(&device))
+        .await
+        .map_err(|e| PdbError::ConnectionError(e.to_string()))?
+}
+
 /// Handle a command and return response
-async fn handle_command(
+pub(crate) async fn handle_command(
     command: Command,
     controller: &WindowController,
-    devices: &Arc<Mutex<HashMap<usize, Device>>>,
+    session: &Session,
+    encoders: &Encoders,
 ) -> Response {
+    execute(command, controller, session, encoders)
+        .await
+        .unwrap_or_else(|e| Response::Error(e.into()))
+}
+
+/// Run a command, keeping the error for callers that report it differently
+pub(crate) async fn execute(
+    command: Command,
+    controller: &WindowController,
+    session: &Session,
+    encoders: &Encoders,
+) -> Result<Response> {
+    let policy = session.policy();
+    policy.check(&command)?;
+    if let (true, Some(hwnd)) = (command.is_input(), command.hwnd()) {
+        session.check_lease(hwnd)?;
+    }
     match command {
-        Command::Ping => Response::Pong,
+        Command::Hello(h) => Ok(hello(&h, controller)),
         
-        Command::Disconnect => Response::Ok,
+        Command::Authenticate { .. } => {
+            Err(PdbError::ProtocolError("No authentication pending".to_string()))
+        }
         
-        Command::ListWindows => {
-            match controller.list_windows() {
-                Ok(windows) => Response::Windows(windows),
-                Err(e) => Response::Error(e.to_string()),
+        Command::Ping => Ok(Response::Pong),
+        
+        Command::Disconnect => {
+            session.release().await;
+            encoders.lock().unwrap().clear();
+            Ok(Response::Ok)
+        }
+        
+        Command::AcquireLease { hwnd, duration_ms, wait_ms } => {
+            let duration = lease_duration(duration_ms)?;
+            let wait = lease_time(wait_ms)?;
+            if session.device(hwnd).await.is_none() {
+                return Err(PdbError::DeviceNotConnected(hwnd));
             }
+            session.acquire_lease(hwnd, duration, wait).await?;
+            Ok(Response::Ok)
         }
         
-        Command::Connect { title } => {
-            match controller.find_window(&title) {
-                Ok(info) => {
-                    let device = Device::new(info.clone());
-                    devices.lock().await.insert(info.hwnd, device);
-                    Response::Window(info)
-                }
-                Err(e) => Response::Error(e.to_string()),
+        Command::RenewLease { hwnd, duration_ms } => {
+            session.renew_lease(hwnd, lease_duration(duration_ms)?)?;
+            Ok(Response::Ok)
+        }
+        
+        Command::ReleaseLease { hwnd } => {
+            session.release_lease(hwnd)?;
+            Ok(Response::Ok)
+        }
+        
+        Command::ListSessions => {
+            let mut sessions = session.sessions().list().await;
+            // Windows this client may not see stay hidden here as well
+            let allowed = |hwnd: &usize| {
+                controller
+                    .get_window_by_hwnd(*hwnd)
+                    .is_ok_and(|window| policy.allows_window(&window))
+            };
+            for info in &mut sessions {
+                info.devices.retain(allowed);
+                info.leases.retain(allowed);
             }
+            Ok(Response::Sessions(sessions))
+        }
+        
+        Command::ListWindows => {
+            let mut windows = controller.list_windows()?;
+            windows.retain(|window| policy.allows_window(window));
+            Ok(Response::Windows(windows))
+        }
+        
+        Command::Connect { title } => {
+            let info = find_window(controller, &policy, &title)?;
+            session.attach(controller.device(info.clone())).await;
+            Ok(Response::Window(info))
         }
         
         Command::ConnectByHwnd { hwnd } => {
-            match controller.get_window_by_hwnd(hwnd) {
-                Ok(info) => {
-                    let device = Device::new(info.clone());
-                    devices.lock().await.insert(info.hwnd, device);
-                    Response::Window(info)
-                }
-                Err(e) => Response::Error(e.to_string()),
-            }
+            let info = controller.get_window_by_hwnd(hwnd)?;
+            policy.check_window(&info)?;
+            session.attach(controller.device(info.clone())).await;
+            Ok(Response::Window(info))
         }
         
         Command::Click { hwnd, x, y } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.click(x, y) {
-                    Ok(_) => Response::Ok,
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+            with_device(session, hwnd, move |device| {
+                device.click(x, y).map(|_| Response::Ok)
+            })
+            .await
         }
         
         Command::Swipe { hwnd, x1, y1, x2, y2, duration_ms } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.swipe(x1, y1, x2, y2, duration_ms) {
-                    Ok(_) => Response::Ok,
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+            with_device(session, hwnd, move |device| {
+                device.swipe(x1, y1, x2, y2, duration_ms).map(|_| Response::Ok)
+            })
+            .await
         }
         
-        Command::Screenshot { hwnd } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.screenshot() {
-                    Ok(screenshot) => Response::Screenshot(screenshot),
-                    Err(e) => Response::Error(e.to_string()),
+        Command::Screenshot { hwnd, format, scale, base } => {
+            let encoders = encoders.clone();
+            with_device(session, hwnd, move |device| match base {
+                Some(base) => {
+                    let screenshot = capture(device, scale)?;
+                    let mut encoders = encoders.lock().unwrap();
+                    let encoder = encoders.entry(hwnd).or_default();
+                    encoder.encode(screenshot, format, Some(base)).map(Response::Frame)
                 }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+                None => screenshot(device, format, scale),
+            })
+            .await
+        }
+        
+        Command::Subscribe { .. } | Command::Unsubscribe { .. } => {
+            Err(PdbError::Unsupported(format!(
+                "Subscriptions need a protocol {} connection",
+                REQUEST_ID_VERSION
+            )))
         }
         
         Command::InputText { hwnd, text } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.input_text(&text) {
-                    Ok(_) => Response::Ok,
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+            with_device(session, hwnd, move |device| {
+                device.input_text(&text).map(|_| Response::Ok)
+            })
+            .await
         }
         
         Command::KeyEvent { hwnd, key } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.key_event(key) {
-                    Ok(_) => Response::Ok,
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+            with_device(session, hwnd, move |device| {
+                device.key_event(key).map(|_| Response::Ok)
+            })
+            .await
         }
         
         Command::GetSize { hwnd } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.get_size() {
-                    Ok((width, height)) => Response::Size { width, height },
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
-            }
+            with_device(session, hwnd, move |device| {
+                device.get_size().map(|(width, height)| Response::Size { width, height })
+            })
+            .await
         }
         
         Command::Focus { hwnd } => {
-            let devices = devices.lock().await;
-            if let Some(device) = devices.get(&hwnd) {
-                match device.focus() {
-                    Ok(_) => Response::Ok,
-                    Err(e) => Response::Error(e.to_string()),
-                }
-            } else {
-                Response::Error("Device not connected".to_string())
+            with_device(session, hwnd, move |device| device.focus().map(|_| Response::Ok)).await
+        }
+        
+        Command::Batch { steps, stop_on_error } => {
+            batch(steps, stop_on_error, controller, session, encoders).await
+        }
+    }
+}
+
+/// First window with `title` in its title that `policy` allows
+fn find_window(controller: &WindowController, policy: &Policy, title: &str) -> Result<WindowInfo> {
+    let mut matching: Vec<WindowInfo> = controller
+        .list_windows()?
+        .into_iter()
+        .filter(|window| window.title.contains(title))
+        .collect();
+    match matching.iter().position(|window| policy.allows_window(window)) {
+        Some(index) => Ok(matching.swap_remove(index)),
+        None => {
+            // Windows that match but are not allowed are denied, not missing
+            if let Some(window) = matching.first() {
+                policy.check_window(window)?;
             }
+            Err(PdbError::WindowNotFound(title.to_string()))
+        }
+    }
+}
+
+/// Duration of a lease or of the wait for one
+fn lease_time(ms: u32) -> Result<Duration> {
+    if ms > MAX_LEASE_MS {
+        return Err(PdbError::ProtocolError(format!(
+            "Lease time of {} ms, at most {} allowed",
+            ms, MAX_LEASE_MS
+        )));
+    }
+    Ok(Duration::from_millis(ms.into()))
+}
+
+/// Duration of a lease, which cannot be 0
+fn lease_duration(ms: u32) -> Result<Duration> {
+    if ms == 0 {
+        return Err(PdbError::ProtocolError("Lease duration must not be 0".into()));
+    }
+    lease_time(ms)
+}
+
+/// Run the steps of a `Batch` in order
+///
+/// The whole batch is checked before the first step runs, so a malformed
+/// batch has no effect.
+async fn batch(
+    steps: Vec<BatchStep>,
+    stop_on_error: bool,
+    controller: &WindowController,
+    session: &Session,
+    encoders: &Encoders,
+) -> Result<Response> {
+    if steps.len() > MAX_BATCH_STEPS {
+        return Err(PdbError::ProtocolError(format!(
+            "Batch of {} steps, at most {} allowed",
+            steps.len(),
+            MAX_BATCH_STEPS
+        )));
+    }
+    for step in &steps {
+        match step.command {
+            Command::Hello(_)
+            | Command::Authenticate { .. }
+            | Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Batch { .. }
+            | Command::Disconnect => {
+                return Err(PdbError::ProtocolError(format!(
+                    "{} cannot be part of a batch",
+                    step.command.name()
+                )));
+            }
+            _ if step.delay_ms > MAX_BATCH_DELAY_MS => {
+                return Err(PdbError::ProtocolError(format!(
+                    "Batch delay of {} ms, at most {} allowed",
+                    step.delay_ms, MAX_BATCH_DELAY_MS
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    let mut responses = Vec::with_capacity(steps.len());
+    for BatchStep { delay_ms, command } in steps {
+        if delay_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(delay_ms.into())).await;
+        }
+        // Boxed because batches are themselves run by `execute`
+        let response = Box::pin(handle_command(command, controller, session, encoders)).await;
+        let failed = matches!(response, Response::Error(_));
+        responses.push(response);
+        if failed && stop_on_error {
+            break;
         }
     }
+    Ok(Response::Batch(responses))
 }
@@ -0,0 +1,206 @@
+//! Per-connection sessions
+//!
+//! Every connection to a server runs in a `Session` that owns the devices
+//! it attached with `Connect`/`ConnectByHwnd`: commands only reach devices
+//! of their own session, and a session's devices are released on
+//! `Command::Disconnect` and when its connection closes. The HTTP gateway
+//! runs all its requests in one session (one per key with its own policy),
+//! as REST requests do not share a connection. `Server::sessions` and
+//! `Command::ListSessions` describe the sessions currently open.
+//!
+//! A session can also lease windows for itself alone
+//! (`Command::AcquireLease`); its leases end with it as well.
+//!
+//! What a session may do is up to its `Policy`, the server's unless the
+//! client authenticated with a key that has its own.
+
+use crate::device::Device;
+use crate::error::Result;
+use crate::lease::Leases;
+use crate::policy::Policy;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// Attached devices (hwnd -> Device)
+type Devices = Arc<Mutex<HashMap<usize, Device>>>;
+
+/// Description of an open session, as listed by `Command::ListSessions`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    /// Number of the session, unique for the server's lifetime
+    pub id: u64,
+    /// Address of the client (the gateway's listen address for HTTP)
+    pub peer: String,
+    /// How the client is connected: "pdb", "websocket" or "http"
+    pub endpoint: String,
+    /// When the session was opened, in seconds since the Unix epoch
+    pub opened_at: u64,
+    /// Windows the session has attached
+    pub devices: Vec<usize>,
+    /// Windows the session holds a lease on
+    #[serde(default)]
+    pub leases: Vec<usize>,
+}
+
+/// Sessions open on a server
+#[derive(Clone, Default)]
+pub(crate) struct Sessions {
+    registry: Arc<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Last id handed out
+    last_id: AtomicU64,
+    open: std::sync::Mutex<BTreeMap<u64, Entry>>,
+    leases: Leases,
+}
+
+struct Entry {
+    peer: String,
+    endpoint: &'static str,
+    opened_at: u64,
+    devices: Devices,
+}
+
+impl Sessions {
+    /// Open a listed session for a client at `peer`
+    pub(crate) fn open(&self, peer: impl Into<String>, endpoint: &'static str) -> Session {
+        let session = self.detached();
+        let peer = peer.into();
+        info!("Session {} opened for {} ({})", session.id, peer, endpoint);
+        let opened_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let entry = Entry {
+            peer,
+            endpoint,
+            opened_at,
+            devices: session.devices.clone(),
+        };
+        self.registry.open.lock().unwrap().insert(session.id, entry);
+        session
+    }
+
+    /// A session that is not listed, for commands run in-process
+    pub(crate) fn detached(&self) -> Session {
+        Session {
+            id: self.registry.last_id.fetch_add(1, Ordering::Relaxed) + 1,
+            devices: Devices::default(),
+            policy: std::sync::Mutex::default(),
+            sessions: self.clone(),
+        }
+    }
+
+    /// Describe the open sessions, oldest first
+    pub(crate) async fn list(&self) -> Vec<SessionInfo> {
+        let open: Vec<_> = {
+            let open = self.registry.open.lock().unwrap();
+            open.iter()
+                .map(|(id, entry)| {
+                    let info = SessionInfo {
+                        id: *id,
+                        peer: entry.peer.clone(),
+                        endpoint: entry.endpoint.to_string(),
+                        opened_at: entry.opened_at,
+                        devices: Vec::new(),
+                        leases: self.registry.leases.held_by(*id),
+                    };
+                    (info, entry.devices.clone())
+                })
+                .collect()
+        };
+        let mut sessions = Vec::with_capacity(open.len());
+        for (mut info, devices) in open {
+            info.devices = devices.lock().await.keys().copied().collect();
+            info.devices.sort_unstable();
+            sessions.push(info);
+        }
+        sessions
+    }
+}
+
+/// Devices attached by one client; released when it is dropped
+pub(crate) struct Session {
+    id: u64,
+    devices: Devices,
+    policy: std::sync::Mutex<Arc<Policy>>,
+    sessions: Sessions,
+}
+
+impl Session {
+    /// All sessions of the server this one belongs to
+    pub(crate) fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    /// What the session may do (by default anything)
+    pub(crate) fn policy(&self) -> Arc<Policy> {
+        self.policy.lock().unwrap().clone()
+    }
+
+    /// Replace the session's policy, e.g. once its client authenticated
+    pub(crate) fn set_policy(&self, policy: Arc<Policy>) {
+        *self.policy.lock().unwrap() = policy;
+    }
+
+    /// Attach a device, replacing an earlier one for the same window
+    pub(crate) async fn attach(&self, device: Device) {
+        self.devices.lock().await.insert(device.hwnd(), device);
+    }
+
+    /// The attached device for `hwnd`
+    pub(crate) async fn device(&self, hwnd: usize) -> Option<Device> {
+        self.devices.lock().await.get(&hwnd).cloned()
+    }
+
+    /// Release all attached devices and leases
+    pub(crate) async fn release(&self) {
+        self.sessions.registry.leases.release_all(self.id);
+        let released = std::mem::take(&mut *self.devices.lock().await);
+        if !released.is_empty() {
+            info!("Session {} released {} device(s)", self.id, released.len());
+        }
+    }
+
+    /// Lease `hwnd` for `duration`, waiting up to `wait` for it to be free
+    pub(crate) async fn acquire_lease(
+        &self,
+        hwnd: usize,
+        duration: Duration,
+        wait: Duration,
+    ) -> Result<()> {
+        let leases = &self.sessions.registry.leases;
+        leases.acquire(hwnd, self.id, duration, wait).await?;
+        info!("Session {} leased 0x{:X} for {:?}", self.id, hwnd, duration);
+        Ok(())
+    }
+
+    pub(crate) fn renew_lease(&self, hwnd: usize, duration: Duration) -> Result<()> {
+        self.sessions.registry.leases.renew(hwnd, self.id, duration)
+    }
+
+    pub(crate) fn release_lease(&self, hwnd: usize) -> Result<()> {
+        self.sessions.registry.leases.release(hwnd, self.id)
+    }
+
+    /// Fail with `PdbError::DeviceLeased` if another session leased `hwnd`
+    pub(crate) fn check_lease(&self, hwnd: usize) -> Result<()> {
+        self.sessions.registry.leases.check(hwnd, self.id)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let registry = &self.sessions.registry;
+        registry.leases.release_all(self.id);
+        if registry.open.lock().unwrap().remove(&self.id).is_some() {
+            info!("Session {} closed", self.id);
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! TLS for client and server connections
+//!
+//! The server presents a certificate chain and may require client
+//! certificates signed by a given CA (mutual TLS). The client either checks
+//! the server certificate against CA roots, or pins it by SHA-256
+//! fingerprint, which works with self-signed certificates and ignores names
+//! and expiry. The PDB protocol runs unchanged inside the TLS session.
+
+use crate::error::{PdbError, Result};
+use crate::transport::Transport;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::client::TlsStream as ClientTlsStream;
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::crypto::{self, CryptoProvider};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+};
+use tokio_rustls::server::TlsStream as ServerTlsStream;
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+/// Time a peer gets to complete the TLS handshake
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(crypto::ring::default_provider())
+}
+
+/// SHA-256 fingerprint of a DER certificate, as colon-separated hex
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Fingerprint of the first certificate in a PEM file, as printed by
+/// `pdb-server` and accepted by `TlsClientConfig::pinned`
+pub fn fingerprint_pem(pem: &[u8]) -> Result<String> {
+    let chain = certificates(pem)?;
+    Ok(fingerprint(&chain[0]))
+}
+
+/// Parse a hex fingerprint, with or without colons
+fn parse_fingerprint(hex: &str) -> Result<[u8; 32]> {
+    let digits: String = hex.chars().filter(|c| *c != ':').collect();
+    let invalid = || PdbError::TlsError(format!("invalid SHA-256 fingerprint: {}", hex));
+    if digits.len() != 64 || !digits.is_ascii() {
+        return Err(invalid());
+    }
+    let mut fingerprint = [0u8; 32];
+    for (i, byte) in fingerprint.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(fingerprint)
+}
+
+fn certificates(pem: &[u8]) -> Result<Vec<CertificateDer<'static>>> {
+    let chain = CertificateDer::pem_slice_iter(pem)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| PdbError::TlsError(format!("bad certificate PEM: {}", e)))?;
+    if chain.is_empty() {
+        return Err(PdbError::TlsError("no certificate in PEM".into()));
+    }
+    Ok(chain)
+}
+
+fn private_key(pem: &[u8]) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_slice(pem)
+        .map_err(|e| PdbError::TlsError(format!("bad private key PEM: {}", e)))
+}
+
+fn roots(pem: &[u8]) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in certificates(pem)? {
+        roots.add(cert)?;
+    }
+    Ok(roots)
+}
+
+fn read(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    std::fs::read(path).map_err(|e| PdbError::TlsError(format!("{}: {}", path.display(), e)))
+}
+
+/// Server side TLS settings, see `Server::with_tls`
+#[derive(Debug)]
+pub struct TlsServerConfig {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    /// CA that client certificates must chain to (mutual TLS)
+    client_ca: Option<Arc<RootCertStore>>,
+}
+
+impl Clone for TlsServerConfig {
+    fn clone(&self) -> Self {
+        Self {
+            cert_chain: self.cert_chain.clone(),
+            key: self.key.clone_key(),
+            client_ca: self.client_ca.clone(),
+        }
+    }
+}
+
+impl TlsServerConfig {
+    /// Present this certificate chain and private key (both PEM)
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self> {
+        Ok(Self {
+            cert_chain: certificates(cert_chain)?,
+            key: private_key(key)?,
+            client_ca: None,
+        })
+    }
+
+    /// Read the certificate chain and private key from PEM files
+    pub fn from_pem_files(cert_chain: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Self> {
+        Self::from_pem(&read(cert_chain)?, &read(key)?)
+    }
+
+    /// Require clients to present a certificate signed by this CA (PEM)
+    pub fn with_client_ca_pem(mut self, ca: &[u8]) -> Result<Self> {
+        self.client_ca = Some(Arc::new(roots(ca)?));
+        Ok(self)
+    }
+
+    /// Fingerprint of the server certificate, for clients to pin
+    pub fn fingerprint(&self) -> String {
+        fingerprint(&self.cert_chain[0])
+    }
+
+    /// Build the acceptor used for every connection
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.client_ca {
+            Some(ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(ca.clone(), provider())
+                    .build()
+                    .map_err(|e| PdbError::TlsError(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(self.cert_chain.clone(), self.key.clone_key())?;
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// How the client decides to trust the server certificate
+#[derive(Debug, Clone)]
+enum Trust {
+    /// Chain to one of these roots and match the server name
+    Roots(Arc<RootCertStore>),
+    /// Be exactly one of these certificates (SHA-256 of the DER)
+    Pinned(Vec<[u8; 32]>),
+}
+
+/// Client side TLS settings, see `ClientOptions::with_tls`
+#[derive(Debug)]
+pub struct TlsClientConfig {
+    trust: Trust,
+    /// Certificate chain and key presented for mutual TLS
+    identity: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+    /// Name to verify the server certificate against (default: the host
+    /// part of the address connected to, "localhost" for Unix sockets)
+    server_name: Option<String>,
+}
+
+impl Clone for TlsClientConfig {
+    fn clone(&self) -> Self {
+        Self {
+            trust: self.trust.clone(),
+            identity: self
+                .identity
+                .as_ref()
+                .map(|(chain, key)| (chain.clone(), key.clone_key())),
+            server_name: self.server_name.clone(),
+        }
+    }
+}
+
+impl TlsClientConfig {
+    /// Trust servers whose certificate chains to one of these CAs (PEM)
+    pub fn with_ca_pem(ca: &[u8]) -> Result<Self> {
+        Ok(Self {
+            trust: Trust::Roots(Arc::new(roots(ca)?)),
+            identity: None,
+            server_name: None,
+        })
+    }
+
+    /// Trust exactly the server certificate with this SHA-256 fingerprint
+    /// (hex, colons optional), e.g. a self-signed one
+    pub fn pinned(fingerprint: &str) -> Result<Self> {
+        Ok(Self {
+            trust: Trust::Pinned(vec![parse_fingerprint(fingerprint)?]),
+            identity: None,
+            server_name: None,
+        })
+    }
+
+    /// Also trust another pinned certificate (e.g. while rotating)
+    pub fn with_pin(mut self, fingerprint: &str) -> Result<Self> {
+        let pin = parse_fingerprint(fingerprint)?;
+        match &mut self.trust {
+            Trust::Pinned(pins) => pins.push(pin),
+            Trust::Roots(_) => self.trust = Trust::Pinned(vec![pin]),
+        }
+        Ok(self)
+    }
+
+    /// Present this certificate chain and private key (both PEM) to servers
+    /// that require client certificates
+    pub fn with_client_cert_pem(mut self, cert_chain: &[u8], key: &[u8]) -> Result<Self> {
+        self.identity = Some((certificates(cert_chain)?, private_key(key)?));
+        Ok(self)
+    }
+
+    /// Verify the server certificate against this name instead of the host
+    /// connected to
+    pub fn with_server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Run the TLS handshake on a connected stream to `host`
+    pub(crate) async fn connect<S: Transport>(
+        &self,
+        host: Option<&str>,
+        stream: S,
+    ) -> Result<ClientTlsStream<S>> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match &self.trust {
+            Trust::Roots(roots) => builder.with_root_certificates(roots.clone()),
+            Trust::Pinned(pins) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                    pins: pins.clone(),
+                    provider: provider(),
+                })),
+        };
+        let config = match &self.identity {
+            Some((chain, key)) => builder.with_client_auth_cert(chain.clone(), key.clone_key())?,
+            None => builder.with_no_client_auth(),
+        };
+
+        let name = match (&self.server_name, host) {
+            (Some(name), _) => name.clone(),
+            (None, Some(host)) => host.to_string(),
+            (None, None) => "localhost".to_string(),
+        };
+        let name = ServerName::try_from(name)
+            .map_err(|e| PdbError::TlsError(format!("invalid server name: {}", e)))?;
+        let connector = TlsConnector::from(Arc::new(config));
+        timeout(connector.connect(name, stream)).await
+    }
+}
+
+/// Run the server side of the TLS handshake on an accepted stream
+pub(crate) async fn accept<S: Transport>(
+    acceptor: &TlsAcceptor,
+    stream: S,
+) -> Result<ServerTlsStream<S>> {
+    timeout(acceptor.accept(stream)).await
+}
+
+async fn timeout<T>(handshake: impl std::future::Future<Output = std::io::Result<T>>) -> Result<T> {
+    match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => Err(PdbError::TlsError(e.to_string())),
+        Err(_) => Err(PdbError::TlsError("handshake timed out".into())),
+    }
+}
+
+/// Accepts exactly the pinned certificates, whatever their names and dates
+#[derive(Debug)]
+struct PinnedVerifier {
+    pins: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        let digest: [u8; 32] = Sha256::digest(end_entity).into();
+        if self.pins.contains(&digest) {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(tokio_rustls::rustls::Error::General(format!(
+                "server certificate {} is not pinned",
+                fingerprint(end_entity)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,149 @@
+//! Byte streams the protocol runs over
+//!
+//! The framed protocol only needs a reliable, ordered byte stream. Addresses
+//! select TCP (`host:port`) or, on Unix, a Unix domain socket
+//! (`unix:/path/to.sock`), which keeps same-host automation off the network
+//! entirely. `Server::serve_connection` and `Client::connect_stream` accept
+//! any other `AsyncRead + AsyncWrite` stream.
+
+use crate::error::{PdbError, Result};
+use std::fmt;
+#[cfg(unix)]
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Prefix of Unix domain socket addresses
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Stream a connection can run over
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Transport for T {}
+
+/// Any transport, as used by `Client` and `Server`
+pub type BoxedTransport = Box<dyn Transport>;
+
+/// Where a server listens or a client connects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// TCP `host:port`
+    Tcp(String),
+    /// Unix domain socket path
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// Parse `host:port` or `unix:/path/to.sock`
+    pub fn parse(addr: &str) -> Result<Self> {
+        match addr.strip_prefix(UNIX_PREFIX) {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Ok(Address::Unix(PathBuf::from(path))),
+            #[cfg(unix)]
+            Some(_) => Err(PdbError::ConnectionError(format!(
+                "no socket path in {}",
+                addr
+            ))),
+            #[cfg(not(unix))]
+            Some(_) => Err(PdbError::Unsupported(
+                "Unix domain sockets are not available on this platform".into(),
+            )),
+            None => Ok(Address::Tcp(addr.to_string())),
+        }
+    }
+
+    /// Host name or IP of a TCP address
+    pub fn host(&self) -> Option<&str> {
+        match self {
+            Address::Tcp(addr) => {
+                let host = addr
+                    .rsplit_once(':')
+                    .map_or(addr.as_str(), |(host, _)| host);
+                Some(host.trim_start_matches('[').trim_end_matches(']'))
+            }
+            #[cfg(unix)]
+            Address::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Address::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            Address::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Open a connection to an address
+pub async fn connect(addr: &Address) -> Result<BoxedTransport> {
+    match addr {
+        Address::Tcp(addr) => Ok(Box::new(TcpStream::connect(addr).await?)),
+        #[cfg(unix)]
+        Address::Unix(path) => Ok(Box::new(UnixStream::connect(path).await?)),
+    }
+}
+
+/// Listening socket of a server
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listen on an address
+    ///
+    /// A Unix socket file left behind by a server that is no longer running
+    /// is replaced; the new socket is only accessible to the current user.
+    pub async fn bind(addr: &Address) -> Result<Self> {
+        match addr {
+            Address::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                use std::os::unix::fs::PermissionsExt;
+
+                if path.exists() && UnixStream::connect(path).await.is_err() {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path)?;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+                Ok(Listener::Unix(listener))
+            }
+        }
+    }
+
+    /// Accept the next connection, with a description of the peer
+    pub async fn accept(&self) -> Result<(BoxedTransport, String)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, addr) = listener.accept().await?;
+                Ok((Box::new(stream), addr.to_string()))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener) => {
+                let (stream, _) = listener.accept().await?;
+                Ok((Box::new(stream), "local socket".to_string()))
+            }
+        }
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixListener> for Listener {
+    fn from(listener: UnixListener) -> Self {
+        Listener::Unix(listener)
+    }
+}
@@ -55,6 +55,7 @@ impl Rect {
 
 /// Key codes similar to Android KeyEvent
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "mcp", derive(schemars::JsonSchema))]
 #[repr(u16)]
 pub enum KeyCode {
     // Numbers
@@ -143,6 +144,88 @@ impl KeyCode {
     pub fn vk_code(&self) -> u16 {
         *self as u16
     }
+
+    /// Get X11 keysym (also used by RFB key events)
+    pub fn keysym(&self) -> u32 {
+        let vk = self.vk_code() as u32;
+        match self {
+            // Digits map to their ASCII keysyms, letters to lowercase
+            KeyCode::Num0 | KeyCode::Num1 | KeyCode::Num2 | KeyCode::Num3 | KeyCode::Num4
+            | KeyCode::Num5 | KeyCode::Num6 | KeyCode::Num7 | KeyCode::Num8 | KeyCode::Num9 => vk,
+            KeyCode::F1 | KeyCode::F2 | KeyCode::F3 | KeyCode::F4 | KeyCode::F5 | KeyCode::F6
+            | KeyCode::F7 | KeyCode::F8 | KeyCode::F9 | KeyCode::F10 | KeyCode::F11
+            | KeyCode::F12 => 0xFFBE + (vk - KeyCode::F1 as u32),
+            KeyCode::Backspace => 0xFF08,
+            KeyCode::Tab => 0xFF09,
+            KeyCode::Enter => 0xFF0D,
+            KeyCode::Shift => 0xFFE1,
+            KeyCode::Ctrl => 0xFFE3,
+            KeyCode::Alt => 0xFFE9,
+            KeyCode::Pause => 0xFF13,
+            KeyCode::CapsLock => 0xFFE5,
+            KeyCode::Escape => 0xFF1B,
+            KeyCode::Space => 0x20,
+            KeyCode::PageUp => 0xFF55,
+            KeyCode::PageDown => 0xFF56,
+            KeyCode::End => 0xFF57,
+            KeyCode::Home => 0xFF50,
+            KeyCode::Left => 0xFF51,
+            KeyCode::Up => 0xFF52,
+            KeyCode::Right => 0xFF53,
+            KeyCode::Down => 0xFF54,
+            KeyCode::Insert => 0xFF63,
+            KeyCode::Delete => 0xFFFF,
+            KeyCode::LWin => 0xFFEB,
+            KeyCode::RWin => 0xFFEC,
+            // Letters
+            _ => vk + 0x20,
+        }
+    }
+
+    /// Get the key for a non-character X11 keysym (function, editing and
+    /// modifier keys); printable characters are not mapped
+    pub fn from_keysym(keysym: u32) -> Option<KeyCode> {
+        const FUNCTION_KEYS: [KeyCode; 12] = [
+            KeyCode::F1,
+            KeyCode::F2,
+            KeyCode::F3,
+            KeyCode::F4,
+            KeyCode::F5,
+            KeyCode::F6,
+            KeyCode::F7,
+            KeyCode::F8,
+            KeyCode::F9,
+            KeyCode::F10,
+            KeyCode::F11,
+            KeyCode::F12,
+        ];
+        let key = match keysym {
+            0xFFBE..=0xFFC9 => FUNCTION_KEYS[(keysym - 0xFFBE) as usize],
+            0xFF08 => KeyCode::Backspace,
+            0xFF09 => KeyCode::Tab,
+            0xFF0D | 0xFF8D => KeyCode::Enter,
+            0xFFE1 | 0xFFE2 => KeyCode::Shift,
+            0xFFE3 | 0xFFE4 => KeyCode::Ctrl,
+            0xFFE9 | 0xFFEA => KeyCode::Alt,
+            0xFF13 => KeyCode::Pause,
+            0xFFE5 => KeyCode::CapsLock,
+            0xFF1B => KeyCode::Escape,
+            0xFF55 => KeyCode::PageUp,
+            0xFF56 => KeyCode::PageDown,
+            0xFF57 => KeyCode::End,
+            0xFF50 => KeyCode::Home,
+            0xFF51 => KeyCode::Left,
+            0xFF52 => KeyCode::Up,
+            0xFF53 => KeyCode::Right,
+            0xFF54 => KeyCode::Down,
+            0xFF63 => KeyCode::Insert,
+            0xFFFF => KeyCode::Delete,
+            0xFFEB => KeyCode::LWin,
+            0xFFEC => KeyCode::RWin,
+            _ => return None,
+        };
+        Some(key)
+    }
 }
 
 /// Screenshot data
@@ -152,7 +235,8 @@ pub struct Screenshot {
     pub width: u32,
     /// Height in pixels
     pub height: u32,
-    /// Raw RGBA pixel data (base64 encoded for network transfer)
+    /// Raw RGBA pixel data (a binary blob in MessagePack, a number array in JSON)
+    #[serde(with = "serde_bytes")]
     pub data: Vec<u8>,
 }
 
@@ -175,4 +259,141 @@ impl Screenshot {
     pub fn rgba_data(&self) -> &[u8] {
         &self.data
     }
+
+    fn to_image(&self) -> crate::error::Result<image::RgbaImage> {
+        image::RgbaImage::from_raw(self.width, self.height, self.data.clone()).ok_or_else(|| {
+            crate::error::PdbError::CaptureError(format!(
+                "{} bytes of pixel data do not match {}x{}",
+                self.data.len(),
+                self.width,
+                self.height
+            ))
+        })
+    }
+
+    /// Downscale by a factor in (0, 1]
+    pub fn scaled(&self, factor: f32) -> crate::error::Result<Screenshot> {
+        if !(factor > 0.0 && factor <= 1.0) {
+            return Err(crate::error::PdbError::CaptureError(format!(
+                "Scale factor {} is not in (0, 1]",
+                factor
+            )));
+        }
+        if factor == 1.0 {
+            return Ok(self.clone());
+        }
+        let width = ((self.width as f32 * factor).round() as u32).max(1);
+        let height = ((self.height as f32 * factor).round() as u32).max(1);
+        let img = image::imageops::resize(
+            &self.to_image()?,
+            width,
+            height,
+            image::imageops::FilterType::Triangle,
+        );
+        Ok(Screenshot {
+            width,
+            height,
+            data: img.into_raw(),
+        })
+    }
+
+    /// Compress into an image file format
+    pub fn encode(&self, format: ScreenshotFormat) -> crate::error::Result<EncodedImage> {
+        use image::codecs::jpeg::JpegEncoder;
+        use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+        use image::codecs::webp::WebPEncoder;
+        use image::{ExtendedColorType, ImageEncoder};
+
+        let mut data = Vec::new();
+        match format {
+            ScreenshotFormat::Raw => data.clone_from(&self.data),
+            ScreenshotFormat::Png => {
+                // Favour speed over size: screenshots are usually sent once
+                PngEncoder::new_with_quality(&mut data, CompressionType::Fast, FilterType::Adaptive)
+                    .write_image(&self.data, self.width, self.height, ExtendedColorType::Rgba8)?;
+            }
+            ScreenshotFormat::Jpeg { quality } => {
+                let rgb = image::DynamicImage::ImageRgba8(self.to_image()?).into_rgb8();
+                JpegEncoder::new_with_quality(&mut data, quality.clamp(1, 100))
+                    .write_image(&rgb, self.width, self.height, ExtendedColorType::Rgb8)?;
+            }
+            ScreenshotFormat::WebP => {
+                WebPEncoder::new_lossless(&mut data).write_image(
+                    &self.data,
+                    self.width,
+                    self.height,
+                    ExtendedColorType::Rgba8,
+                )?;
+            }
+        }
+        Ok(EncodedImage {
+            width: self.width,
+            height: self.height,
+            format,
+            data,
+        })
+    }
+}
+
+/// Image format for transferring screenshots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "mcp", derive(schemars::JsonSchema))]
+pub enum ScreenshotFormat {
+    /// Uncompressed RGBA
+    #[default]
+    Raw,
+    /// Lossless PNG
+    Png,
+    /// Lossy JPEG with quality 1-100 (alpha is dropped)
+    Jpeg { quality: u8 },
+    /// Lossless WebP
+    WebP,
+}
+
+/// Screenshot compressed into a `ScreenshotFormat`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncodedImage {
+    /// Width in pixels
+    pub width: u32,
+    /// Height in pixels
+    pub height: u32,
+    /// Format of `data`
+    pub format: ScreenshotFormat,
+    /// Encoded image bytes
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+impl EncodedImage {
+    /// Decode back into RGBA pixels
+    pub fn decode(&self) -> crate::error::Result<Screenshot> {
+        let format = match self.format {
+            ScreenshotFormat::Raw => {
+                let expected = self.width as u64 * self.height as u64 * 4;
+                if self.data.len() as u64 != expected {
+                    return Err(crate::error::PdbError::ProtocolError(format!(
+                        "{}x{} raw image of {} bytes, expected {}",
+                        self.width,
+                        self.height,
+                        self.data.len(),
+                        expected
+                    )));
+                }
+                return Ok(Screenshot {
+                    width: self.width,
+                    height: self.height,
+                    data: self.data.clone(),
+                })
+            }
+            ScreenshotFormat::Png => image::ImageFormat::Png,
+            ScreenshotFormat::Jpeg { .. } => image::ImageFormat::Jpeg,
+            ScreenshotFormat::WebP => image::ImageFormat::WebP,
+        };
+        let img = image::load_from_memory_with_format(&self.data, format)?.into_rgba8();
+        Ok(Screenshot {
+            width: img.width(),
+            height: img.height(),
+            data: img.into_raw(),
+        })
+    }
 }
@@ -0,0 +1,122 @@
+//! WebSocket endpoint for browser-based clients
+//!
+//! Browsers cannot open raw TCP sockets, so the server can also accept
+//! WebSockets (`Server::serve_websocket`). Instead of the framed header,
+//! every WebSocket message carries exactly one message body: a text message
+//! holds a JSON `Command` and is answered with a JSON `Response` text
+//! message, a binary message does the same in MessagePack. Commands on a
+//! connection are answered in order, and a server with keys expects the
+//! same `Hello`/`Authenticate` exchange first. Errors carry their kind
+//! once a `Hello` agreed on `ERROR_KIND_VERSION` or later.
+//!
+//! Browsers let any page open a WebSocket to `localhost`, so handshakes
+//! with an `Origin` header are refused unless the origin was allowed
+//! (`Server::with_websocket_origin`). Messages are limited to the server's
+//! frame size (`Server::with_max_frame`).
+
+use crate::error::{PdbError, Result};
+use crate::protocol::{Command, Encoding, Response, MIN_PROTOCOL_VERSION};
+use crate::server::{self, Auth, Encoders, Shared};
+use crate::session::Session;
+use crate::transport::Transport;
+use futures_util::SinkExt;
+use log::info;
+use tokio_stream::StreamExt;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request};
+use tokio_tungstenite::tungstenite::http::{header, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Serve one WebSocket connection until it closes
+pub(crate) async fn handle_connection(
+    stream: impl Transport,
+    shared: Shared,
+    session: Session,
+) -> Result<()> {
+    let origins = shared.origins.clone();
+    // The error type is dictated by tungstenite's handshake callback
+    #[allow(clippy::result_large_err)]
+    let check_origin = move |request: &Request, response| {
+        let origin = request
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|origin| origin.to_str().ok());
+        match origin {
+            // Non-browser clients send no origin; pages need an allowed one
+            Some(origin) if !origins.iter().any(|o| o == origin) => {
+                let mut error = ErrorResponse::new(Some(format!("origin {} not allowed", origin)));
+                *error.status_mut() = StatusCode::FORBIDDEN;
+                Err(error)
+            }
+            _ => Ok(response),
+        }
+    };
+    let max_frame = Some(shared.limits.max_frame as usize);
+    let config = WebSocketConfig::default()
+        .max_message_size(max_frame)
+        .max_frame_size(max_frame);
+    let mut socket =
+        tokio_tungstenite::accept_hdr_async_with_config(stream, check_origin, Some(config)).await?;
+
+    let controller = shared.controller();
+    let encoders = Encoders::default();
+    let mut auth = Auth::new(&shared.keys);
+    // Raised by the Hello exchange
+    let mut version = MIN_PROTOCOL_VERSION;
+
+    while let Some(message) = socket.next().await {
+        let (encoding, body) = match message? {
+            Message::Text(text) => (Encoding::Json, text.as_bytes().to_vec()),
+            Message::Binary(data) => (Encoding::MessagePack, data.to_vec()),
+            Message::Close(_) => break,
+            // Pings are answered by the library
+            _ => continue,
+        };
+        // A command this server cannot read is answered, not fatal, unless
+        // the client still has to authenticate
+        let (response, result) = match Command::decode(encoding, &body) {
+            Ok(command) if auth.is_done() => {
+                let response =
+                    server::handle_command(command, &controller, &session, &encoders).await;
+                (response, Ok(()))
+            }
+            Ok(command) => match auth.handle(command, &shared.keys, &controller) {
+                Ok(response) => {
+                    if auth.is_done() {
+                        session.set_policy(shared.policy(auth.key()));
+                    }
+                    (response, Ok(()))
+                }
+                Err(reason) => (
+                    Response::Error(reason.clone().into()),
+                    Err(PdbError::AuthenticationFailed(reason)),
+                ),
+            },
+            Err(e) if auth.is_done() => (Response::Error(e.into()), Ok(())),
+            Err(e) => (
+                Response::Error(e.into()),
+                Err(PdbError::AuthenticationFailed(
+                    "authentication required".into(),
+                )),
+            ),
+        };
+
+        if let Response::Hello(caps) = &response {
+            version = caps.version;
+        }
+        let downgraded = response.for_version(version);
+        let body = encoding.encode(downgraded.as_ref().unwrap_or(&response))?;
+        let message = match encoding {
+            Encoding::Json => Message::text(String::from_utf8(body).expect("JSON is UTF-8")),
+            Encoding::MessagePack => Message::binary(body),
+        };
+        socket.send(message).await?;
+        if result.is_err() {
+            let _ = socket.close(None).await;
+            return result;
+        }
+    }
+
+    info!("WebSocket client disconnected");
+    Ok(())
+}
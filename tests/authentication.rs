@@ -0,0 +1,112 @@
+//! Pre-shared key challenge-response authentication
+
+mod common;
+
+use pdb::auth;
+use pdb::{Client, ClientOptions, Command, PdbError, Response, Server};
+use tokio::net::TcpStream;
+
+async fn start_server(keys: &[&str]) -> String {
+    let configure = |mut server: Server| {
+        for key in keys {
+            server = server.with_key(*key);
+        }
+        server
+    };
+    common::start_server(configure).await.0
+}
+
+async fn connect(addr: &str, key: Option<&str>) -> pdb::Result<Client> {
+    let mut options = ClientOptions::default();
+    if let Some(key) = key {
+        options = options.with_key(key);
+    }
+    Client::connect_with(addr, options).await
+}
+
+/// Send a JSON command on a protocol 2 connection and read the reply
+async fn request(stream: &mut TcpStream, command: &Command) -> std::io::Result<Response> {
+    common::request(stream, 2, command).await
+}
+
+#[tokio::test]
+async fn client_with_key_is_accepted() {
+    let addr = start_server(&["old secret", "new secret"]).await;
+    for key in ["old secret", "new secret"] {
+        let client = connect(&addr, Some(key)).await.unwrap();
+        assert!(client.capabilities().supports("Authenticate"));
+        assert_eq!(client.list_windows().await.unwrap().len(), 1);
+    }
+
+    // A key is harmless when the server does not ask for one
+    let open = start_server(&[]).await;
+    let client = connect(&open, Some("old secret")).await.unwrap();
+    assert!(client.ping().await.unwrap());
+}
+
+#[tokio::test]
+async fn missing_or_wrong_key_is_rejected() {
+    let addr = start_server(&["secret"]).await;
+    for key in [None, Some("guess"), Some("")] {
+        match connect(&addr, key).await {
+            Err(PdbError::AuthenticationFailed(_)) => {}
+            other => panic!(
+                "{:?} accepted: {:?}",
+                key,
+                other.map(|c| c.protocol_version())
+            ),
+        }
+    }
+}
+
+#[tokio::test]
+async fn commands_before_authentication_close_the_connection() {
+    let addr = start_server(&["secret"]).await;
+
+    // A protocol 1 style client skipping the handshake
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let response = request(&mut stream, &Command::ListWindows).await.unwrap();
+    assert!(matches!(&response, Response::Error(e) if e.message.contains("authentication")));
+    assert!(request(&mut stream, &Command::Ping).await.is_err());
+
+    // Having seen the challenge is not enough
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let hello = Command::Hello(Default::default());
+    let challenge = match request(&mut stream, &hello).await.unwrap() {
+        Response::Challenge(challenge) => challenge,
+        other => panic!("unexpected {:?}", other),
+    };
+    assert_eq!(challenge.len(), auth::CHALLENGE_LEN);
+    let response = request(&mut stream, &Command::ListWindows).await.unwrap();
+    assert!(matches!(response, Response::Error(_)));
+    assert!(request(&mut stream, &Command::Ping).await.is_err());
+}
+
+#[tokio::test]
+async fn proofs_do_not_carry_over_to_other_connections() {
+    let addr = start_server(&["secret"]).await;
+    let hello = Command::Hello(Default::default());
+
+    let mut first = TcpStream::connect(&addr).await.unwrap();
+    let Response::Challenge(challenge) = request(&mut first, &hello).await.unwrap() else {
+        panic!("no challenge");
+    };
+    let proof = auth::sign(b"secret", &challenge);
+    assert!(auth::verify(&[b"secret".to_vec()], &challenge, &proof));
+
+    // Replaying the proof against a fresh challenge fails
+    let mut second = TcpStream::connect(&addr).await.unwrap();
+    let Response::Challenge(other) = request(&mut second, &hello).await.unwrap() else {
+        panic!("no challenge");
+    };
+    assert_ne!(challenge, other);
+    let replay = Command::Authenticate {
+        proof: proof.clone(),
+    };
+    let response = request(&mut second, &replay).await.unwrap();
+    assert!(matches!(response, Response::Error(_)));
+
+    let answer = Command::Authenticate { proof };
+    let response = request(&mut first, &answer).await.unwrap();
+    assert!(matches!(response, Response::Hello(_)), "{:?}", response);
+}
@@ -0,0 +1,142 @@
+//! Batched commands in one round trip
+
+mod common;
+
+use common::{start_server, HWND};
+use pdb::backend::mock::MockEvent;
+use pdb::protocol::BatchStep;
+use pdb::{Client, Command, KeyCode, Response, ScreenshotFormat};
+use std::time::{Duration, Instant};
+
+const MISSING: usize = 0x999;
+
+#[tokio::test]
+async fn steps_run_in_order_with_delays() {
+    let (addr, backend) = start_server(|server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    assert!(client.capabilities().supports("Batch"));
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    let started = Instant::now();
+    let responses = device
+        .batch()
+        .click(10, 20)
+        .wait(Duration::from_millis(150))
+        .input_text("hello")
+        .key_event(KeyCode::Enter)
+        .screenshot(ScreenshotFormat::Png, None)
+        .send()
+        .await
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(150));
+
+    assert_eq!(responses.len(), 4);
+    assert!(responses[..3].iter().all(|r| matches!(r, Response::Ok)));
+    assert!(
+        matches!(&responses[3], Response::Image(image) if image.format == ScreenshotFormat::Png)
+    );
+    assert_eq!(
+        backend.events(),
+        vec![
+            MockEvent::Click {
+                hwnd: HWND,
+                x: 10,
+                y: 20
+            },
+            MockEvent::InputText {
+                hwnd: HWND,
+                text: "hello".into()
+            },
+            MockEvent::KeyEvent {
+                hwnd: HWND,
+                key: KeyCode::Enter
+            },
+        ]
+    );
+}
+
+#[tokio::test]
+async fn failed_step_stops_the_batch_only_when_asked() {
+    let (addr, backend) = start_server(|server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    let failing = Command::Click {
+        hwnd: MISSING,
+        x: 1,
+        y: 1,
+    };
+
+    let responses = device
+        .batch()
+        .click(1, 1)
+        .command(failing.clone())
+        .input_text("after")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(responses.len(), 3);
+    assert!(matches!(&responses[1], Response::Error(e) if e.message.contains("0x999")));
+    assert!(matches!(responses[2], Response::Ok));
+    assert_eq!(backend.take_events().len(), 2);
+
+    let responses = device
+        .batch()
+        .click(1, 1)
+        .command(failing)
+        .input_text("after")
+        .stop_on_error(true)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(responses.len(), 2);
+    assert!(matches!(responses[1], Response::Error(_)));
+    assert_eq!(
+        backend.take_events(),
+        vec![MockEvent::Click {
+            hwnd: HWND,
+            x: 1,
+            y: 1
+        }]
+    );
+}
+
+#[tokio::test]
+async fn invalid_batch_runs_nothing() {
+    let (addr, backend) = start_server(|server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    let click = BatchStep::from(Command::Click {
+        hwnd: HWND,
+        x: 1,
+        y: 1,
+    });
+    let nested = Command::Batch {
+        steps: vec![click.clone()],
+        stop_on_error: false,
+    };
+    let invalid = [
+        vec![click.clone(), BatchStep::from(nested)],
+        vec![
+            click.clone(),
+            BatchStep::from(Command::Unsubscribe { id: 1 }),
+        ],
+        vec![
+            click.clone(),
+            BatchStep {
+                delay_ms: 3_600_000,
+                command: Command::Ping,
+            },
+        ],
+        vec![click; 1000],
+    ];
+    for steps in invalid {
+        let batch = Command::Batch {
+            steps,
+            stop_on_error: false,
+        };
+        let response = client.send_command(batch).await.unwrap();
+        assert!(matches!(response, Response::Error(_)), "{:?}", response);
+    }
+    assert!(backend.events().is_empty());
+}
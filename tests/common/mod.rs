@@ -0,0 +1,100 @@
+//! Helpers shared by the integration tests: a mock server and raw framing
+
+// Every test crate compiles this module but uses only part of it
+#![allow(dead_code)]
+
+use pdb::backend::mock::{MockBackend, MockWindow};
+use pdb::protocol::{MessageHeader, PROTOCOL_VERSION};
+use pdb::{Command, Rect, Response, Server};
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Window of `notepad`
+pub const HWND: usize = 0x100;
+
+/// Backend with a single 320x240 Notepad window, `HWND`
+pub fn notepad() -> Arc<MockBackend> {
+    Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Notepad",
+        "Edit",
+        Rect::new(0, 0, 320, 240),
+    )]))
+}
+
+/// Listener on a free local port and its address
+pub async fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    (listener, addr)
+}
+
+/// Serve `backend` with a server set up by `configure` and return its address
+pub async fn serve(backend: Arc<MockBackend>, configure: impl FnOnce(Server) -> Server) -> String {
+    let (listener, addr) = listen().await;
+    let server = configure(Server::with_backend(&addr, backend));
+    tokio::spawn(async move { server.serve(listener).await });
+    addr
+}
+
+/// Serve a `notepad` backend with a server set up by `configure`
+pub async fn start_server(configure: impl FnOnce(Server) -> Server) -> (String, Arc<MockBackend>) {
+    let backend = notepad();
+    let addr = serve(backend.clone(), configure).await;
+    (addr, backend)
+}
+
+/// Write one message with a header of `version`
+pub async fn write_message(
+    stream: &mut TcpStream,
+    version: u32,
+    id: u32,
+    body: &[u8],
+) -> io::Result<()> {
+    let header = MessageHeader::with_version(version, body.len() as u32).with_id(id);
+    stream.write_all(&header.to_bytes()).await?;
+    stream.write_all(body).await
+}
+
+/// Read one message, whatever the version of its header
+pub async fn read_message(stream: &mut TcpStream) -> io::Result<(MessageHeader, Vec<u8>)> {
+    let mut bytes = [0u8; 8];
+    stream.read_exact(&mut bytes).await?;
+    let version = u32::from_le_bytes(bytes[0..4].try_into().unwrap());
+    let length = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    let mut header = MessageHeader::with_version(version, length);
+    if header.has_id() {
+        header.id = stream.read_u32_le().await?;
+    }
+    let mut body = vec![0u8; length as usize];
+    stream.read_exact(&mut body).await?;
+    Ok((header, body))
+}
+
+/// Send a JSON command with a header of `version` and read the reply
+pub async fn request(
+    stream: &mut TcpStream,
+    version: u32,
+    command: &Command,
+) -> io::Result<Response> {
+    let body = serde_json::to_vec(command).unwrap();
+    write_message(stream, version, 1, &body).await?;
+    let (_, body) = read_message(stream).await?;
+    Ok(serde_json::from_slice(&body).unwrap())
+}
+
+/// Send a request on the current protocol without waiting for the reply
+pub async fn send(stream: &mut TcpStream, id: u32, command: &Command) {
+    let body = serde_json::to_vec(command).unwrap();
+    write_message(stream, PROTOCOL_VERSION, id, &body)
+        .await
+        .unwrap();
+}
+
+/// Read a response on the current protocol as (id, response)
+pub async fn receive(stream: &mut TcpStream) -> (u32, Response) {
+    let (header, body) = read_message(stream).await.unwrap();
+    (header.id, serde_json::from_slice(&body).unwrap())
+}
@@ -0,0 +1,215 @@
+//! Delta frame encoding, locally and for repeated remote screenshots
+
+mod common;
+
+use common::{serve, HWND};
+use pdb::backend::mock::{solid_frame, MockBackend, MockWindow};
+use pdb::delta::{DeltaDecoder, DeltaEncoder};
+use pdb::protocol::{Frame, FrameData, Tile};
+use pdb::{Client, Command, EncodedImage, PdbError, Rect, Response, Screenshot, ScreenshotFormat};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+
+const GREY: [u8; 4] = [128, 128, 128, 255];
+
+fn set_pixel(frame: &mut Screenshot, x: usize, y: usize, color: [u8; 4]) {
+    let i = (y * frame.width as usize + x) * 4;
+    frame.data[i..i + 4].copy_from_slice(&color);
+}
+
+fn tiles(frame: &Frame) -> Option<Vec<(u32, u32, u32, u32)>> {
+    match &frame.data {
+        FrameData::Key(_) => None,
+        FrameData::Delta { tiles, .. } => Some(
+            tiles
+                .iter()
+                .map(|t| (t.x, t.y, t.image.width, t.image.height))
+                .collect(),
+        ),
+    }
+}
+
+#[test]
+fn deltas_carry_only_changed_tiles() {
+    let mut encoder = DeltaEncoder::new();
+    let mut decoder = DeltaDecoder::new();
+    let mut frame = solid_frame(200, 100, GREY);
+
+    let key = encoder
+        .encode(frame.clone(), ScreenshotFormat::Raw, Some(decoder.seq()))
+        .unwrap();
+    assert_eq!(key.seq, 1);
+    assert!(tiles(&key).is_none());
+    assert_eq!(decoder.apply(key).unwrap().data, frame.data);
+
+    let unchanged = encoder
+        .encode(frame.clone(), ScreenshotFormat::Raw, Some(1))
+        .unwrap();
+    assert_eq!(tiles(&unchanged), Some(vec![]));
+    decoder.apply(unchanged).unwrap();
+
+    // Two neighbouring tiles in the first row merge; one in the second row
+    set_pixel(&mut frame, 10, 10, [255, 0, 0, 255]);
+    set_pixel(&mut frame, 70, 10, [0, 255, 0, 255]);
+    set_pixel(&mut frame, 199, 99, [0, 0, 255, 255]);
+    let delta = encoder
+        .encode(frame.clone(), ScreenshotFormat::Png, Some(2))
+        .unwrap();
+    // A format change forces a keyframe
+    assert!(tiles(&delta).is_none());
+    decoder.apply(delta).unwrap();
+
+    set_pixel(&mut frame, 10, 10, GREY);
+    set_pixel(&mut frame, 70, 10, GREY);
+    set_pixel(&mut frame, 199, 99, GREY);
+    let delta = encoder
+        .encode(frame.clone(), ScreenshotFormat::Png, Some(3))
+        .unwrap();
+    assert_eq!(tiles(&delta), Some(vec![(0, 0, 128, 64), (192, 64, 8, 36)]));
+    assert_eq!(decoder.apply(delta).unwrap().data, frame.data);
+}
+
+#[test]
+fn keyframes_on_resize_interval_and_unknown_base() {
+    let mut encoder = DeltaEncoder::new().with_keyframe_interval(3);
+    let mut is_key = |size: u32, base: Option<u64>| {
+        let frame = solid_frame(size, size, GREY);
+        tiles(&encoder.encode(frame, ScreenshotFormat::Raw, base).unwrap()).is_none()
+    };
+
+    assert!(is_key(64, Some(0)));
+    assert!(!is_key(64, Some(1)));
+    assert!(!is_key(64, Some(2)));
+    // Interval reached
+    assert!(is_key(64, Some(3)));
+    // Receiver holds an older frame, or none at all
+    assert!(is_key(64, Some(2)));
+    assert!(is_key(64, None));
+    // Resized
+    assert!(!is_key(64, Some(6)));
+    assert!(is_key(32, Some(7)));
+
+    // A delta against a frame the decoder does not hold is refused
+    let mut encoder = DeltaEncoder::new();
+    encoder
+        .encode(solid_frame(8, 8, GREY), ScreenshotFormat::Raw, None)
+        .unwrap();
+    let delta = encoder
+        .encode(solid_frame(8, 8, GREY), ScreenshotFormat::Raw, Some(1))
+        .unwrap();
+    assert!(DeltaDecoder::new().apply(delta).is_err());
+}
+
+#[test]
+fn malformed_tiles_are_refused() {
+    let raw = |width: u32, height: u32, len: usize| EncodedImage {
+        width,
+        height,
+        format: ScreenshotFormat::Raw,
+        data: vec![0; len],
+    };
+    let apply = |x: u32, image: EncodedImage| {
+        let mut decoder = DeltaDecoder::new();
+        let key = Frame {
+            seq: 1,
+            data: FrameData::Key(raw(8, 8, 8 * 8 * 4)),
+        };
+        decoder.apply(key).unwrap();
+        let tile = Tile { x, y: 0, image };
+        let delta = Frame {
+            seq: 2,
+            data: FrameData::Delta {
+                base: 1,
+                tiles: vec![tile],
+            },
+        };
+        decoder.apply(delta)
+    };
+
+    let refused =
+        |result: pdb::Result<Screenshot>| matches!(result, Err(PdbError::ProtocolError(_)));
+    assert!(refused(apply(0, raw(0, 4, 0))));
+    // More pixels than the tile holds would run past the frame
+    assert!(refused(apply(0, raw(2, 2, 8 * 8 * 4))));
+    assert!(refused(apply(u32::MAX, raw(2, 2, 16))));
+    assert!(refused(raw(8, 8, 3).decode()));
+    assert!(apply(6, raw(2, 2, 16)).is_ok());
+}
+
+fn mock() -> Arc<MockBackend> {
+    Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Editor",
+        "Edit",
+        Rect::new(0, 0, 128, 128),
+    )
+    .with_color(GREY)]))
+}
+
+/// Send a JSON command on a protocol 2 connection and read the reply
+async fn request(stream: &mut TcpStream, command: &Command) -> Response {
+    common::request(stream, 2, command).await.unwrap()
+}
+
+#[tokio::test]
+async fn server_remembers_last_frame_per_connection() {
+    let backend = mock();
+    let addr = serve(backend.clone(), |server| server).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    request(&mut stream, &Command::ConnectByHwnd { hwnd: HWND }).await;
+
+    let screenshot = |base| Command::Screenshot {
+        hwnd: HWND,
+        format: ScreenshotFormat::Raw,
+        scale: None,
+        base: Some(base),
+    };
+    let frame = |response| match response {
+        Response::Frame(frame) => frame,
+        other => panic!("unexpected {:?}", other),
+    };
+
+    let key = frame(request(&mut stream, &screenshot(0)).await);
+    assert!(tiles(&key).is_none());
+    let same = frame(request(&mut stream, &screenshot(key.seq)).await);
+    assert_eq!(tiles(&same), Some(vec![]));
+
+    let mut changed = solid_frame(128, 128, GREY);
+    set_pixel(&mut changed, 100, 100, [1, 2, 3, 255]);
+    backend.set_frame(HWND, changed).unwrap();
+    let delta = frame(request(&mut stream, &screenshot(same.seq)).await);
+    assert_eq!(tiles(&delta), Some(vec![(64, 64, 64, 64)]));
+
+    // Another connection starts from a keyframe
+    let mut other = TcpStream::connect(&addr).await.unwrap();
+    request(&mut other, &Command::ConnectByHwnd { hwnd: HWND }).await;
+    let key = frame(request(&mut other, &screenshot(delta.seq)).await);
+    assert!(tiles(&key).is_none());
+}
+
+#[tokio::test]
+async fn remote_screenshots_are_rebuilt_from_deltas() {
+    let backend = mock();
+    let addr = serve(backend.clone(), |server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    let mut expected = solid_frame(128, 128, GREY);
+    for i in 0..4 {
+        set_pixel(&mut expected, i * 30, i * 30, [i as u8, 0, 0, 255]);
+        backend.set_frame(HWND, expected.clone()).unwrap();
+        let shot = device
+            .screenshot_with(ScreenshotFormat::Png, None)
+            .await
+            .unwrap();
+        assert_eq!(shot.data, expected.data);
+    }
+
+    // A downscaled screenshot is a different size and starts over
+    let small = device
+        .screenshot_with(ScreenshotFormat::Raw, Some(0.5))
+        .await
+        .unwrap();
+    assert_eq!((small.width, small.height), (64, 64));
+    assert_eq!(device.screenshot().await.unwrap().data, expected.data);
+}
@@ -0,0 +1,107 @@
+//! Typed errors across the wire
+
+mod common;
+
+use common::{read_message, start_server, write_message, HWND};
+use pdb::protocol::{Encoding, ErrorInfo, ERROR_KIND_VERSION};
+use pdb::{Client, Command, Device, ErrorKind, PdbError, Response};
+use tokio::net::TcpStream;
+
+const MISSING: usize = 0x999;
+
+/// Send a JSON command with a header of `version` and read the raw reply body
+async fn raw_request(stream: &mut TcpStream, version: u32, command: &Command) -> serde_json::Value {
+    let body = serde_json::to_vec(command).unwrap();
+    write_message(stream, version, 1, &body).await.unwrap();
+    let (_, body) = read_message(stream).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn remote_errors_match_local_ones() {
+    let (addr, backend) = start_server(|server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+
+    let missing = client.connect_window("Calculator").await;
+    assert!(matches!(missing, Err(PdbError::WindowNotFound(_))));
+
+    let click = Command::Click {
+        hwnd: MISSING,
+        x: 1,
+        y: 1,
+    };
+    match client.send_command(click).await.unwrap() {
+        Response::Error(error) => {
+            assert_eq!(error.kind, ErrorKind::DeviceNotConnected);
+            assert_eq!(error.details, Some(serde_json::json!({ "hwnd": MISSING })));
+            let error = error.into_error(PdbError::InputError);
+            assert!(matches!(error, PdbError::DeviceNotConnected(MISSING)));
+            assert_eq!(error.to_string(), "Device not connected: 0x999");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // A window that went away fails the same way for both
+    let remote = client.connect_window_by_hwnd(HWND).await.unwrap();
+    let local = Device::with_backend(remote.info().clone(), backend.clone());
+    backend.remove_window(HWND);
+    let local_error = local.screenshot().unwrap_err();
+    let remote_error = remote.screenshot().await.unwrap_err();
+    assert_eq!(remote_error.kind(), local_error.kind());
+    assert_eq!(remote_error.to_string(), local_error.to_string());
+}
+
+#[tokio::test]
+async fn older_peers_get_plain_messages() {
+    let (addr, _backend) = start_server(|server| server).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    let click = Command::Click {
+        hwnd: MISSING,
+        x: 1,
+        y: 1,
+    };
+
+    let reply = raw_request(&mut stream, ERROR_KIND_VERSION - 1, &click).await;
+    assert_eq!(
+        reply,
+        serde_json::json!({ "Error": "Device not connected: 0x999" })
+    );
+
+    let reply = raw_request(&mut stream, ERROR_KIND_VERSION, &click).await;
+    assert_eq!(
+        reply,
+        serde_json::json!({ "Error": {
+            "kind": "DeviceNotConnected",
+            "message": "0x999",
+            "details": { "hwnd": MISSING },
+        } })
+    );
+}
+
+#[test]
+fn error_payloads_decode_in_both_encodings() {
+    let error = ErrorInfo::from(PdbError::CaptureError("no frame".into()));
+    for encoding in [Encoding::Json, Encoding::MessagePack] {
+        let response = Response::Error(error.clone());
+        let decoded: Response = encoding
+            .decode(&encoding.encode(&response).unwrap())
+            .unwrap();
+        match decoded {
+            Response::Error(decoded) => assert_eq!(decoded, error),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // Plain messages of older servers, and kinds of newer ones
+    let old: Response = serde_json::from_str(r#"{"Error": "boom"}"#).unwrap();
+    let new: Response =
+        serde_json::from_str(r#"{"Error": {"kind": "Overheated", "message": "boom"}}"#).unwrap();
+    for response in [old, new] {
+        let Response::Error(error) = response else {
+            panic!("not an error");
+        };
+        assert_eq!(error.kind, ErrorKind::Unknown);
+        let error = error.into_error(PdbError::InputError);
+        assert_eq!(error.to_string(), "Input simulation failed: boom");
+    }
+}
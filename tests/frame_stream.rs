@@ -0,0 +1,103 @@
+//! Live frame subscriptions over a real socket
+
+mod common;
+
+use common::{serve, HWND};
+use pdb::backend::mock::{solid_frame, MockBackend, MockWindow};
+use pdb::{Client, Rect, ScreenshotFormat};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_stream::StreamExt;
+
+fn mock() -> Arc<MockBackend> {
+    Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Player",
+        "Video",
+        Rect::new(0, 0, 64, 48),
+    )
+    .with_color([10, 20, 30, 255])]))
+}
+
+#[tokio::test]
+async fn frames_follow_the_window() {
+    let backend = mock();
+    let addr = serve(backend.clone(), |server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    let mut frames = device
+        .subscribe(30, ScreenshotFormat::Png, None)
+        .await
+        .unwrap();
+    let frame = frames.next().await.unwrap().unwrap();
+    assert_eq!((frame.width, frame.height), (64, 48));
+    assert_eq!(&frame.data[..4], &[10, 20, 30, 255]);
+
+    backend
+        .set_frame(HWND, solid_frame(64, 48, [200, 0, 0, 255]))
+        .unwrap();
+    let changed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let frame = frames.next().await.unwrap().unwrap();
+            if frame.data[..4] == [200, 0, 0, 255] {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(changed.is_ok(), "stream never showed the new frame");
+
+    // The device keeps working while the stream is open
+    assert_eq!(device.get_size().await.unwrap(), (64, 48));
+}
+
+#[tokio::test]
+async fn slow_consumer_drops_frames() {
+    let addr = serve(mock(), |server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    let mut frames = device
+        .subscribe(60, ScreenshotFormat::Raw, Some(0.5))
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert!(frames.dropped() > 0);
+
+    // Only the buffered frame is waiting, then new ones keep coming
+    let frame = frames.next().await.unwrap().unwrap();
+    assert_eq!((frame.width, frame.height), (32, 24));
+    assert!(frames.next().await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn subscription_errors() {
+    let backend = mock();
+    let addr = serve(backend.clone(), |server| server).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    assert!(device
+        .subscribe(0, ScreenshotFormat::Raw, None)
+        .await
+        .is_err());
+
+    // A window that goes away ends the stream with its capture error
+    let mut frames = device
+        .subscribe(20, ScreenshotFormat::Raw, None)
+        .await
+        .unwrap();
+    frames.next().await.unwrap().unwrap();
+    backend.remove_window(HWND);
+    let last = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(frame) = frames.next().await {
+            if frame.is_err() {
+                return frames.next().await.is_none();
+            }
+        }
+        false
+    })
+    .await;
+    assert_eq!(last.ok(), Some(true));
+}
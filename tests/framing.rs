@@ -0,0 +1,152 @@
+//! Frame size limits and incomplete frames
+
+mod common;
+
+use common::{start_server, HWND};
+use pdb::framing::{self, FrameLimits};
+use pdb::protocol::{MessageHeader, Response, HANDSHAKE_VERSION, PROTOCOL_VERSION};
+use pdb::{Client, ClientOptions, PdbError};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn oversized_frame_is_refused() {
+    let (addr, _backend) = start_server(|server| server.with_max_frame(1024)).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    // Claims 4 GiB; the server must answer without waiting for (or
+    // allocating) the body
+    let header = MessageHeader::with_version(PROTOCOL_VERSION, u32::MAX).with_id(1);
+    stream.write_all(&header.to_bytes()).await.unwrap();
+
+    let mut reply = [0u8; 8];
+    stream.read_exact(&mut reply).await.unwrap();
+    assert_eq!(
+        u32::from_le_bytes(reply[0..4].try_into().unwrap()),
+        HANDSHAKE_VERSION
+    );
+    let length = u32::from_le_bytes(reply[4..8].try_into().unwrap());
+    let mut body = vec![0u8; length as usize];
+    stream.read_exact(&mut body).await.unwrap();
+    match serde_json::from_slice(&body).unwrap() {
+        Response::Error(error) => assert!(error.message.contains("Frame too large"), "{}", error),
+        other => panic!("unexpected {:?}", other),
+    }
+    let mut buf = [0u8; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn stalled_frame_is_dropped() {
+    let (addr, _backend) =
+        start_server(|server| server.with_read_timeout(Some(Duration::from_millis(200)))).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    // Half a header, then nothing
+    stream
+        .write_all(&[PROTOCOL_VERSION as u8, 0])
+        .await
+        .unwrap();
+    let started = Instant::now();
+    let mut buf = [0u8; 1];
+    let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+        .await
+        .expect("server kept the connection open");
+    assert!(matches!(read, Ok(0) | Err(_)));
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn incomplete_frames_are_reported() {
+    let limits = FrameLimits {
+        max_frame: 64,
+        read_timeout: Some(Duration::from_millis(100)),
+    };
+
+    // Clean end between frames
+    let (mut reader, writer) = tokio::io::duplex(1024);
+    drop(writer);
+    assert!(framing::read_frame(&mut reader, &limits)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Stream ends inside the header, inside the body, or stops arriving
+    let frame = framing::encode_frame(PROTOCOL_VERSION, 3, b"0123456789").unwrap();
+    for cut in [5, frame.len() - 4] {
+        let (mut reader, mut writer) = tokio::io::duplex(1024);
+        writer.write_all(&frame[..cut]).await.unwrap();
+        drop(writer);
+        let result = framing::read_frame(&mut reader, &limits).await;
+        assert!(
+            matches!(result, Err(PdbError::TruncatedFrame(_))),
+            "{:?}",
+            result
+        );
+    }
+    let (mut reader, mut writer) = tokio::io::duplex(1024);
+    writer.write_all(&frame[..frame.len() - 4]).await.unwrap();
+    let result = framing::read_frame(&mut reader, &limits).await;
+    assert!(
+        matches!(result, Err(PdbError::TruncatedFrame(_))),
+        "{:?}",
+        result
+    );
+    drop(writer);
+}
+
+#[tokio::test]
+async fn frames_are_checked_before_their_body() {
+    let limits = FrameLimits {
+        max_frame: 64,
+        read_timeout: Some(Duration::from_millis(100)),
+    };
+    let (mut reader, mut writer) = tokio::io::duplex(1024);
+
+    let big = framing::encode_frame(PROTOCOL_VERSION, 1, &[0u8; 65]).unwrap();
+    writer.write_all(&big[..12]).await.unwrap();
+    match framing::read_frame(&mut reader, &limits).await {
+        Err(PdbError::FrameTooLarge { length, max }) => assert_eq!((length, max), (65, 64)),
+        other => panic!("unexpected {:?}", other.map(|f| f.map(|(h, _)| h))),
+    }
+
+    // An unknown version is skipped whole, so the next frame still reads
+    let (mut reader, mut writer) = tokio::io::duplex(1024);
+    let future = MessageHeader::with_version(PROTOCOL_VERSION + 1, 4);
+    writer.write_all(&future.to_bytes()[..8]).await.unwrap();
+    writer.write_all(b"body").await.unwrap();
+    let next = framing::encode_frame(PROTOCOL_VERSION, 2, b"next").unwrap();
+    writer.write_all(&next).await.unwrap();
+    let result = framing::read_frame(&mut reader, &limits).await;
+    assert!(
+        matches!(result, Err(PdbError::VersionMismatch(_))),
+        "{:?}",
+        result.map(|_| ())
+    );
+    let (header, body) = framing::read_frame(&mut reader, &limits)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((header.id, body.as_slice()), (2, &b"next"[..]));
+}
+
+#[tokio::test]
+async fn client_limits_responses() {
+    let (addr, _backend) = start_server(|server| server).await;
+
+    // A raw 320x240 screenshot is 300 KiB
+    let options = ClientOptions::default().with_max_frame(64 << 10);
+    let client = Client::connect_with(&addr, options).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    match device.screenshot().await {
+        Err(PdbError::ConnectionError(message)) => {
+            assert!(message.contains("Frame too large"), "{}", message)
+        }
+        other => panic!("unexpected {:?}", other.map(|s| s.width)),
+    }
+
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    assert_eq!(device.screenshot().await.unwrap().width, 320);
+}
@@ -0,0 +1,121 @@
+//! Hello handshake and protocol version checks over a real socket
+
+mod common;
+
+use common::{notepad, read_message, serve};
+use pdb::backend::mock::MockBackend;
+use pdb::protocol::{Hello, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION};
+use pdb::{Client, Command, PdbError, Response, Server};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn start_server() -> String {
+    let backend = notepad();
+    backend.set_screen_size(1920, 1080);
+    serve(backend, |server| server).await
+}
+
+/// Send one frame with an 8-byte header, whatever `version` says, and read
+/// the reply as (header version, response)
+async fn raw_request(stream: &mut TcpStream, version: u32, body: &[u8]) -> (u32, Response) {
+    stream.write_all(&version.to_le_bytes()).await.unwrap();
+    stream
+        .write_all(&(body.len() as u32).to_le_bytes())
+        .await
+        .unwrap();
+    stream.write_all(body).await.unwrap();
+    let (header, body) = read_message(stream).await.unwrap();
+    (header.version, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn client_receives_capabilities() {
+    let addr = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+
+    let caps = client.capabilities();
+    assert_eq!(caps.version, PROTOCOL_VERSION);
+    assert_eq!(caps.backend, "mock");
+    assert_eq!(caps.os, std::env::consts::OS);
+    assert_eq!(caps.screen_size, Some((1920, 1080)));
+    assert!(caps.supports("Screenshot"));
+    assert!(caps.encodings.contains(&"json".to_string()));
+
+    assert_eq!(client.list_windows().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn incompatible_hello_is_rejected() {
+    let server = Server::with_backend("127.0.0.1:0", Arc::new(MockBackend::new()));
+    let response = server
+        .handle_command(Command::Hello(Hello {
+            version: PROTOCOL_VERSION + 5,
+            min_version: PROTOCOL_VERSION + 1,
+            agent: "future".into(),
+            ..Hello::default()
+        }))
+        .await;
+    assert!(matches!(response, Response::Error(_)), "{:?}", response);
+
+    // A newer client is talked down to our version
+    let response = server
+        .handle_command(Command::Hello(Hello {
+            version: PROTOCOL_VERSION + 5,
+            min_version: MIN_PROTOCOL_VERSION,
+            agent: "future".into(),
+            ..Hello::default()
+        }))
+        .await;
+    match response {
+        Response::Hello(caps) => assert_eq!(caps.version, PROTOCOL_VERSION),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unknown_header_version_gets_clear_error() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    let (_, response) = raw_request(&mut stream, 99, b"{\"Future\":{}}").await;
+    match response {
+        Response::Error(e) => assert!(e.message.contains("version mismatch"), "{}", e),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // The server hangs up afterwards
+    let mut buf = [0u8; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn protocol_1_clients_still_work() {
+    let addr = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    let (version, response) = raw_request(&mut stream, 1, b"\"Ping\"").await;
+    assert_eq!(version, 1);
+    assert!(matches!(response, Response::Pong));
+}
+
+#[tokio::test]
+async fn pre_handshake_server_is_reported_as_version_mismatch() {
+    // Stand-in for an old server: it cannot parse `Hello` and hangs up
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).await.unwrap();
+        let length = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let mut body = vec![0u8; length as usize];
+        stream.read_exact(&mut body).await.unwrap();
+    });
+
+    match Client::connect(&addr).await {
+        Err(PdbError::VersionMismatch(_)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("handshake with an old server succeeded"),
+    }
+}
@@ -0,0 +1,200 @@
+//! HTTP REST gateway
+
+#![cfg(feature = "http")]
+
+mod common;
+
+use common::{listen, notepad, HWND};
+use pdb::backend::mock::{MockBackend, MockEvent};
+use pdb::Server;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+async fn start_server(configure: impl FnOnce(Server) -> Server) -> (String, Arc<MockBackend>) {
+    let backend = notepad();
+    let (listener, addr) = listen().await;
+    let server = configure(Server::with_backend("unused", backend.clone()));
+    tokio::spawn(async move { server.serve_http(listener).await });
+    (addr, backend)
+}
+
+struct Reply {
+    status: u16,
+    headers: String,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+}
+
+/// Send one HTTP/1.1 request and read the whole reply
+async fn request(addr: &str, method: &str, path: &str, extra: &str, body: &str) -> Reply {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: pdb\r\nConnection: close\r\nContent-Length: {}\r\n{}\r\n{}",
+        method,
+        path,
+        body.len(),
+        extra,
+        body
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await.unwrap();
+
+    let split = reply.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+    let headers = String::from_utf8(reply[..split].to_vec()).unwrap();
+    let status = headers[9..12].parse().unwrap();
+    Reply {
+        status,
+        headers: headers.to_lowercase(),
+        body: reply[split + 4..].to_vec(),
+    }
+}
+
+#[tokio::test]
+async fn routes_run_commands() {
+    let (addr, backend) = start_server(|server| server.with_policy(pdb::Policy::admin())).await;
+
+    let windows = request(&addr, "GET", "/windows", "", "").await;
+    assert_eq!(windows.status, 200);
+    assert!(windows.headers.contains("content-type: application/json"));
+    assert_eq!(windows.json()[0]["title"], "Notepad");
+
+    let connected = request(&addr, "POST", "/devices/0x100", "", "").await;
+    assert_eq!(connected.status, 200);
+    assert_eq!(connected.json()["hwnd"], HWND);
+
+    let click = request(
+        &addr,
+        "POST",
+        "/devices/256/click",
+        "",
+        r#"{"x": 5, "y": 6}"#,
+    )
+    .await;
+    assert_eq!(click.status, 204);
+    let key = request(&addr, "POST", "/devices/256/key", "", r#"{"key": "Enter"}"#).await;
+    assert_eq!(key.status, 204);
+    let events = backend.events();
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, MockEvent::Click { x: 5, y: 6, .. })));
+
+    // Requests on separate connections share the gateway's session
+    let sessions = request(&addr, "GET", "/sessions", "", "").await;
+    assert_eq!(sessions.json()[0]["endpoint"], "http");
+    assert_eq!(sessions.json()[0]["devices"], serde_json::json!([HWND]));
+
+    let size = request(&addr, "GET", "/devices/256/size", "", "").await;
+    assert_eq!(
+        size.json(),
+        serde_json::json!({ "width": 320, "height": 240 })
+    );
+
+    let png = request(
+        &addr,
+        "GET",
+        "/devices/256/screenshot.png?scale=0.5",
+        "",
+        "",
+    )
+    .await;
+    assert_eq!(png.status, 200);
+    assert!(png.headers.contains("content-type: image/png"));
+    let image = image::load_from_memory(&png.body).unwrap();
+    assert_eq!((image.width(), image.height()), (160, 120));
+
+    let jpeg = request(
+        &addr,
+        "GET",
+        "/devices/256/screenshot.jpg?quality=50",
+        "",
+        "",
+    )
+    .await;
+    assert!(jpeg.headers.contains("content-type: image/jpeg"));
+}
+
+#[tokio::test]
+async fn errors_have_status_codes() {
+    let (addr, _backend) = start_server(|server| server).await;
+
+    let cases = [
+        ("GET", "/nowhere", "", 404),
+        ("GET", "/devices/256/click", "", 405),
+        ("POST", "/devices/256/click", r#"{"x": 1, "y": 2}"#, 404),
+        ("POST", "/devices/0x999", "", 404),
+        ("POST", "/devices/abc", "", 400),
+        ("POST", "/devices/256", "", 200),
+        ("POST", "/devices/256/click", r#"{"x": "left"}"#, 400),
+        ("POST", "/devices/256/click", "not json", 400),
+        ("GET", "/devices/256/screenshot.bmp", "", 404),
+        ("GET", "/devices/256/screenshot.png?scale=big", "", 400),
+    ];
+    for (method, path, body, status) in cases {
+        let reply = request(&addr, method, path, "", body).await;
+        assert_eq!(reply.status, status, "{} {}", method, path);
+        if status >= 400 {
+            let error = reply.json()["error"].as_str().unwrap().to_string();
+            assert!(!error.is_empty());
+        }
+    }
+
+    let missing = request(&addr, "GET", "/devices/512/size", "", "").await;
+    assert!(missing.json()["error"]
+        .as_str()
+        .unwrap()
+        .contains("not connected"));
+    assert_eq!(missing.json()["kind"], "DeviceNotConnected");
+}
+
+#[tokio::test]
+async fn keys_are_bearer_tokens() {
+    let (addr, _backend) = start_server(|server| server.with_key("secret")).await;
+
+    let anonymous = request(&addr, "GET", "/windows", "", "").await;
+    assert_eq!(anonymous.status, 401);
+    assert!(anonymous.headers.contains("www-authenticate: bearer"));
+    let wrong = request(
+        &addr,
+        "GET",
+        "/windows",
+        "Authorization: Bearer secret2\r\n",
+        "",
+    )
+    .await;
+    assert_eq!(wrong.status, 401);
+    let right = request(
+        &addr,
+        "GET",
+        "/windows",
+        "Authorization: Bearer secret\r\n",
+        "",
+    )
+    .await;
+    assert_eq!(right.status, 200);
+}
+
+#[tokio::test]
+async fn denied_commands_are_forbidden() {
+    let (addr, backend) = start_server(|server| server.with_policy(pdb::Policy::read_only())).await;
+
+    let connect = request(&addr, "POST", "/devices/0x100", "", "").await;
+    assert_eq!(connect.status, 200);
+    let click = request(
+        &addr,
+        "POST",
+        "/devices/0x100/click",
+        "",
+        r#"{"x":1,"y":1}"#,
+    )
+    .await;
+    assert_eq!(click.status, 403);
+    assert_eq!(click.json()["kind"], "PermissionDenied");
+    assert!(backend.take_events().is_empty());
+}
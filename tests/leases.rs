@@ -0,0 +1,134 @@
+//! Exclusive leases on devices shared by several clients
+
+mod common;
+
+use common::HWND;
+use pdb::backend::mock::{MockBackend, MockEvent};
+use pdb::{Client, PdbError, Policy, RemoteDevice};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+async fn start_server() -> (String, Arc<MockBackend>) {
+    common::start_server(|server| server.with_policy(Policy::admin())).await
+}
+
+async fn attach(addr: &str) -> (Client, RemoteDevice) {
+    let client = Client::connect(addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    (client, device)
+}
+
+fn is_leased<T>(result: pdb::Result<T>) -> bool {
+    matches!(result, Err(PdbError::DeviceLeased(HWND)))
+}
+
+#[tokio::test]
+async fn only_the_holder_sends_input() {
+    let (addr, backend) = start_server().await;
+    let (owner, mine) = attach(&addr).await;
+    let (_other, theirs) = attach(&addr).await;
+
+    mine.acquire_lease(Duration::from_secs(30), Duration::ZERO)
+        .await
+        .unwrap();
+    assert!(is_leased(theirs.click(1, 1).await));
+    assert!(is_leased(theirs.input_text("x").await));
+    assert!(is_leased(theirs.focus().await));
+    assert!(is_leased(theirs.renew_lease(Duration::from_secs(1)).await));
+    assert!(is_leased(
+        theirs
+            .acquire_lease(Duration::from_secs(1), Duration::ZERO)
+            .await
+    ));
+    // Observing stays shared
+    assert_eq!(theirs.screenshot().await.unwrap().width, 320);
+    assert_eq!(theirs.get_size().await.unwrap(), (320, 240));
+
+    mine.click(5, 5).await.unwrap();
+    assert_eq!(
+        backend.take_events(),
+        vec![MockEvent::Click {
+            hwnd: HWND,
+            x: 5,
+            y: 5
+        }]
+    );
+    let sessions = owner.list_sessions().await.unwrap();
+    assert_eq!(sessions[0].leases, vec![HWND]);
+    assert!(sessions[1].leases.is_empty());
+
+    mine.release_lease().await.unwrap();
+    theirs.click(1, 1).await.unwrap();
+}
+
+#[tokio::test]
+async fn acquiring_waits_for_release() {
+    let (addr, _backend) = start_server().await;
+    let (_owner, mine) = attach(&addr).await;
+    let (_other, theirs) = attach(&addr).await;
+    mine.acquire_lease(Duration::from_secs(30), Duration::ZERO)
+        .await
+        .unwrap();
+
+    let started = Instant::now();
+    let waiting = tokio::spawn(async move {
+        theirs
+            .acquire_lease(Duration::from_secs(30), Duration::from_secs(5))
+            .await
+            .map(|_| theirs)
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    mine.release_lease().await.unwrap();
+    let theirs = waiting.await.unwrap().unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+
+    theirs.click(1, 1).await.unwrap();
+    assert!(is_leased(mine.click(1, 1).await));
+}
+
+#[tokio::test]
+async fn leases_expire_unless_renewed() {
+    let (addr, _backend) = start_server().await;
+    let (_owner, mine) = attach(&addr).await;
+    let (_other, theirs) = attach(&addr).await;
+
+    mine.acquire_lease(Duration::from_millis(200), Duration::ZERO)
+        .await
+        .unwrap();
+    mine.renew_lease(Duration::from_millis(400)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(250)).await;
+    assert!(is_leased(theirs.click(1, 1).await));
+
+    // The waiter gets the lease once it runs out
+    theirs
+        .acquire_lease(Duration::from_secs(30), Duration::from_secs(5))
+        .await
+        .unwrap();
+    assert!(matches!(
+        mine.renew_lease(Duration::from_secs(1)).await,
+        Err(PdbError::DeviceLeased(HWND))
+    ));
+}
+
+#[tokio::test]
+async fn leases_end_with_their_session() {
+    let (addr, _backend) = start_server().await;
+    let (_other, theirs) = attach(&addr).await;
+
+    let (owner, mine) = attach(&addr).await;
+    mine.acquire_lease(Duration::from_secs(30), Duration::ZERO)
+        .await
+        .unwrap();
+    owner.disconnect().await.unwrap();
+    theirs.click(1, 1).await.unwrap();
+
+    let (owner, mine) = attach(&addr).await;
+    mine.acquire_lease(Duration::from_secs(30), Duration::ZERO)
+        .await
+        .unwrap();
+    drop((owner, mine));
+    theirs
+        .acquire_lease(Duration::from_secs(30), Duration::from_secs(5))
+        .await
+        .unwrap();
+}
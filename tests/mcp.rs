@@ -0,0 +1,141 @@
+//! MCP server exposing windows as agent tools
+
+#![cfg(feature = "mcp")]
+
+use base64::Engine;
+use pdb::backend::mock::{MockBackend, MockEvent, MockWindow};
+use pdb::mcp::McpServer;
+use pdb::{Rect, WindowController};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+const HWND: usize = 0x100;
+
+fn start() -> (McpServer, Arc<MockBackend>) {
+    let backend = Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Notepad",
+        "Edit",
+        Rect::new(0, 0, 64, 48),
+    )]));
+    let mcp = McpServer::local(WindowController::with_backend(backend.clone()));
+    (mcp, backend)
+}
+
+async fn request(mcp: &McpServer, method: &str, params: Value) -> Value {
+    let message = json!({ "jsonrpc": "2.0", "id": 7, "method": method, "params": params });
+    let reply = mcp.handle_message(&message.to_string()).await.unwrap();
+    assert_eq!(reply["id"], 7);
+    reply
+}
+
+async fn call_tool(mcp: &McpServer, name: &str, arguments: Value) -> Value {
+    let reply = request(
+        mcp,
+        "tools/call",
+        json!({ "name": name, "arguments": arguments }),
+    )
+    .await;
+    reply["result"].clone()
+}
+
+#[tokio::test]
+async fn handshake_and_tool_list() {
+    let (mcp, _backend) = start();
+    let init = request(
+        &mcp,
+        "initialize",
+        json!({ "protocolVersion": "2025-03-26", "capabilities": {}, "clientInfo": { "name": "test" } }),
+    )
+    .await;
+    assert_eq!(init["result"]["protocolVersion"], "2025-03-26");
+    assert!(init["result"]["capabilities"]["tools"].is_object());
+    let notification = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    assert!(mcp
+        .handle_message(&notification.to_string())
+        .await
+        .is_none());
+
+    let list = request(&mcp, "tools/list", json!({})).await;
+    let tools = list["result"]["tools"].as_array().unwrap();
+    let tool = |name: &str| tools.iter().find(|t| t["name"] == name).unwrap().clone();
+    for name in [
+        "list_windows",
+        "screenshot",
+        "click",
+        "swipe",
+        "type_text",
+        "key",
+    ] {
+        assert_eq!(tool(name)["inputSchema"]["type"], "object", "{}", name);
+    }
+    // Schemas come from the command definitions
+    assert_eq!(
+        tool("click")["inputSchema"]["required"],
+        json!(["hwnd", "x", "y"])
+    );
+    assert_eq!(
+        tool("type_text")["inputSchema"]["required"],
+        json!(["hwnd", "text"])
+    );
+    let key = tool("key")["inputSchema"]["properties"]["key"].to_string();
+    assert!(key.contains("Enter"), "{}", key);
+    let screenshot = &tool("screenshot")["inputSchema"]["properties"];
+    assert!(screenshot.get("base").is_none());
+    assert!(screenshot.get("scale").is_some());
+}
+
+#[tokio::test]
+async fn tools_drive_the_window() {
+    let (mcp, backend) = start();
+
+    let windows = call_tool(&mcp, "list_windows", json!({})).await;
+    assert_eq!(windows["isError"], false);
+    assert!(windows["content"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("Notepad"));
+
+    let click = call_tool(&mcp, "click", json!({ "hwnd": HWND, "x": 1, "y": 2 })).await;
+    assert_eq!(click["isError"], false);
+    call_tool(&mcp, "type_text", json!({ "hwnd": HWND, "text": "hi" })).await;
+    call_tool(&mcp, "key", json!({ "hwnd": HWND, "key": "Enter" })).await;
+    let events = backend.events();
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, MockEvent::Click { x: 1, y: 2, .. })));
+    assert!(events
+        .iter()
+        .any(|e| matches!(e, MockEvent::InputText { text, .. } if text == "hi")));
+
+    let shot = call_tool(&mcp, "screenshot", json!({ "hwnd": HWND, "scale": 0.5 })).await;
+    let image = &shot["content"][0];
+    assert_eq!(image["type"], "image");
+    assert_eq!(image["mimeType"], "image/png");
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(image["data"].as_str().unwrap())
+        .unwrap();
+    let decoded = image::load_from_memory(&data).unwrap();
+    assert_eq!((decoded.width(), decoded.height()), (32, 24));
+}
+
+#[tokio::test]
+async fn failures_are_tool_errors() {
+    let (mcp, _backend) = start();
+
+    let missing = call_tool(&mcp, "click", json!({ "hwnd": 0x999, "x": 1, "y": 2 })).await;
+    assert_eq!(missing["isError"], true);
+    assert!(missing["content"][0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("not found"));
+    let invalid = call_tool(&mcp, "swipe", json!({ "hwnd": HWND })).await;
+    assert_eq!(invalid["isError"], true);
+    let raw = call_tool(&mcp, "screenshot", json!({ "hwnd": HWND, "format": "Raw" })).await;
+    assert_eq!(raw["isError"], true);
+
+    let unknown = request(&mcp, "tools/call", json!({ "name": "format_disk" })).await;
+    assert_eq!(unknown["error"]["code"], pdb::rpc::INVALID_PARAMS);
+    let method = request(&mcp, "resources/list", json!({})).await;
+    assert_eq!(method["error"]["code"], pdb::rpc::METHOD_NOT_FOUND);
+}
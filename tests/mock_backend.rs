@@ -0,0 +1,109 @@
+//! Device and server behaviour against the in-memory mock backend
+
+use pdb::backend::mock::{solid_frame, MockBackend, MockEvent, MockWindow};
+use pdb::{Command, KeyCode, PdbError, Rect, Response, Server, WindowController};
+use std::sync::Arc;
+
+fn desktop() -> Arc<MockBackend> {
+    Arc::new(MockBackend::with_windows([
+        MockWindow::new(0x100, "Notepad", "Edit", Rect::new(100, 50, 420, 290)),
+        MockWindow::new(0x200, "Calculator", "CalcFrame", Rect::new(0, 0, 200, 300))
+            .with_color([255, 0, 0, 255])
+            .minimized(),
+    ]))
+}
+
+#[test]
+fn lists_and_finds_windows() {
+    let controller = WindowController::with_backend(desktop());
+
+    let windows = controller.list_windows().unwrap();
+    assert_eq!(windows.len(), 2);
+
+    let info = controller.find_window_by_class("Calc").unwrap();
+    assert_eq!(info.hwnd, 0x200);
+
+    assert!(matches!(
+        controller.find_window("Paint"),
+        Err(PdbError::WindowNotFound(_))
+    ));
+}
+
+#[test]
+fn records_input_in_client_coordinates() {
+    let backend = desktop();
+    let controller = WindowController::with_backend(backend.clone());
+    let device = controller.device(controller.find_window("Notepad").unwrap());
+
+    device.click(10, 20).unwrap();
+    device.swipe(0, 0, 30, 40, 200).unwrap();
+    device.input_text("hello").unwrap();
+    device.key_event(KeyCode::Enter).unwrap();
+
+    assert_eq!(
+        backend.take_events(),
+        vec![
+            MockEvent::Click { hwnd: 0x100, x: 10, y: 20 },
+            MockEvent::Swipe { hwnd: 0x100, x1: 0, y1: 0, x2: 30, y2: 40, duration_ms: 200 },
+            MockEvent::InputText { hwnd: 0x100, text: "hello".into() },
+            MockEvent::KeyEvent { hwnd: 0x100, key: KeyCode::Enter },
+        ]
+    );
+    assert_eq!(device.get_cursor_pos().unwrap(), (30, 40));
+    assert!(backend.events().is_empty());
+}
+
+#[test]
+fn minimized_window_is_restored_and_reminimized() {
+    let backend = desktop();
+    let controller = WindowController::with_backend(backend.clone());
+    let device = controller.device(controller.get_window_by_hwnd(0x200).unwrap());
+
+    assert!(device.is_minimized());
+    device.click(1, 1).unwrap();
+    assert!(device.is_minimized());
+    assert_eq!(backend.focused(), 0x200);
+}
+
+#[test]
+fn serves_configured_frames() {
+    let backend = desktop();
+    let controller = WindowController::with_backend(backend.clone());
+    let device = controller.device(controller.get_window_by_hwnd(0x200).unwrap());
+
+    let shot = device.screenshot().unwrap();
+    assert_eq!((shot.width, shot.height), (200, 300));
+    assert_eq!(&shot.data[..4], &[255, 0, 0, 255]);
+
+    backend.set_frame(0x200, solid_frame(2, 2, [1, 1, 1, 255])).unwrap();
+    backend.push_frame(0x200, solid_frame(2, 2, [2, 2, 2, 255])).unwrap();
+    assert_eq!(device.screenshot().unwrap().data[0], 1);
+    assert_eq!(device.screenshot().unwrap().data[0], 2);
+    assert_eq!(device.screenshot().unwrap().data[0], 2);
+}
+
+#[tokio::test]
+async fn server_dispatches_to_backend() {
+    let backend = desktop();
+    let server = Server::with_backend("127.0.0.1:0", backend.clone());
+
+    match server.handle_command(Command::Click { hwnd: 0x100, x: 1, y: 2 }).await {
+        Response::Error(_) => {}
+        other => panic!("expected error for unconnected device, got {:?}", other),
+    }
+
+    match server.handle_command(Command::Connect { title: "Notepad".into() }).await {
+        Response::Window(info) => assert_eq!(info.hwnd, 0x100),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    assert!(matches!(
+        server.handle_command(Command::Click { hwnd: 0x100, x: 1, y: 2 }).await,
+        Response::Ok
+    ));
+    assert!(matches!(
+        server.handle_command(Command::GetSize { hwnd: 0x100 }).await,
+        Response::Size { width: 320, height: 240 }
+    ));
+    assert_eq!(backend.events(), vec![MockEvent::Click { hwnd: 0x100, x: 1, y: 2 }]);
+}
@@ -0,0 +1,249 @@
+//! Request ids and concurrent commands on one connection
+
+mod common;
+
+use common::{receive, send};
+use pdb::backend::mock::{MockBackend, MockWindow};
+use pdb::protocol::{Capabilities, MessageHeader, HANDSHAKE_VERSION, MAX_SUBSCRIPTIONS};
+use pdb::{Client, Command, PdbError, Rect, Response, ScreenshotFormat};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const LEFT: usize = 0x100;
+const RIGHT: usize = 0x200;
+
+async fn start_server() -> (String, Arc<MockBackend>) {
+    let backend = Arc::new(MockBackend::with_windows([
+        MockWindow::new(LEFT, "Left", "Pane", Rect::new(0, 0, 100, 100)),
+        MockWindow::new(RIGHT, "Right", "Pane", Rect::new(100, 0, 200, 50)),
+    ]));
+    backend.set_realtime(true);
+    let addr = common::serve(backend.clone(), |server| server).await;
+    (addr, backend)
+}
+
+#[tokio::test]
+async fn long_swipe_does_not_block_other_windows() {
+    let (addr, _backend) = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+    let left = client.connect_window_by_hwnd(LEFT).await.unwrap();
+    let right = client.connect_window_by_hwnd(RIGHT).await.unwrap();
+
+    let started = Instant::now();
+    let swipe = left.swipe(10, 10, 90, 90, 1000);
+    let others = async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let shot = right.screenshot().await.unwrap();
+        assert_eq!((shot.width, shot.height), (100, 50));
+        assert!(client.ping().await.unwrap());
+        started.elapsed()
+    };
+    let (swiped, elapsed) = tokio::join!(swipe, others);
+    swiped.unwrap();
+    assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+    assert!(started.elapsed() >= Duration::from_millis(1000));
+}
+
+#[tokio::test]
+async fn responses_are_matched_by_id() {
+    let (addr, _backend) = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    for (id, hwnd) in [(1, LEFT), (2, RIGHT)] {
+        send(&mut stream, id, &Command::ConnectByHwnd { hwnd }).await;
+        assert_eq!(receive(&mut stream).await.0, id);
+    }
+
+    // Pipelined: the swipe finishes last, commands on one window stay in order
+    let swipe = Command::Swipe {
+        hwnd: LEFT,
+        x1: 0,
+        y1: 0,
+        x2: 50,
+        y2: 50,
+        duration_ms: 300,
+    };
+    send(&mut stream, 10, &swipe).await;
+    send(&mut stream, 11, &Command::GetSize { hwnd: LEFT }).await;
+    send(&mut stream, 12, &Command::GetSize { hwnd: RIGHT }).await;
+    send(&mut stream, 13, &Command::Ping).await;
+
+    let mut ids = Vec::new();
+    for _ in 0..4 {
+        let (id, response) = receive(&mut stream).await;
+        match id {
+            10 => assert!(matches!(response, Response::Ok)),
+            11 => assert!(matches!(
+                response,
+                Response::Size {
+                    width: 100,
+                    height: 100
+                }
+            )),
+            12 => assert!(matches!(
+                response,
+                Response::Size {
+                    width: 100,
+                    height: 50
+                }
+            )),
+            13 => assert!(matches!(response, Response::Pong)),
+            other => panic!("unexpected id {}", other),
+        }
+        ids.push(id);
+    }
+    assert_eq!(&ids[2..], &[10, 11]);
+}
+
+#[tokio::test]
+async fn subscription_shares_the_connection() {
+    let (addr, _backend) = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    send(&mut stream, 1, &Command::ConnectByHwnd { hwnd: LEFT }).await;
+    receive(&mut stream).await;
+
+    let subscribe = Command::Subscribe {
+        hwnd: LEFT,
+        fps: 50,
+        format: ScreenshotFormat::Raw,
+        scale: None,
+        delta: false,
+    };
+    send(&mut stream, 5, &subscribe).await;
+    assert!(matches!(receive(&mut stream).await, (5, Response::Ok)));
+    assert!(matches!(
+        receive(&mut stream).await,
+        (5, Response::Frame(_))
+    ));
+
+    // Other commands are answered in between frames
+    send(&mut stream, 6, &Command::Ping).await;
+    loop {
+        match receive(&mut stream).await {
+            (5, Response::Frame(_)) => continue,
+            (6, Response::Pong) => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    send(&mut stream, 7, &Command::Unsubscribe { id: 5 }).await;
+    loop {
+        match receive(&mut stream).await {
+            (5, Response::Frame(_)) => continue,
+            (7, Response::Ok) => break,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    send(&mut stream, 8, &Command::Ping).await;
+    assert!(matches!(receive(&mut stream).await, (8, Response::Pong)));
+}
+
+/// Next response other than a frame
+async fn receive_reply(stream: &mut TcpStream) -> (u32, Response) {
+    loop {
+        match receive(stream).await {
+            (_, Response::Frame(_)) => continue,
+            reply => return reply,
+        }
+    }
+}
+
+#[tokio::test]
+async fn subscriptions_are_replaced_and_capped() {
+    let (addr, _backend) = start_server().await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+    send(&mut stream, 1, &Command::ConnectByHwnd { hwnd: LEFT }).await;
+    receive(&mut stream).await;
+    let subscribe = Command::Subscribe {
+        hwnd: LEFT,
+        fps: 20,
+        format: ScreenshotFormat::Raw,
+        scale: None,
+        delta: false,
+    };
+
+    // Reusing an id replaces the subscription, so one unsubscribe ends both
+    send(&mut stream, 5, &subscribe).await;
+    assert!(matches!(
+        receive_reply(&mut stream).await,
+        (5, Response::Ok)
+    ));
+    send(&mut stream, 5, &subscribe).await;
+    assert!(matches!(
+        receive_reply(&mut stream).await,
+        (5, Response::Ok)
+    ));
+    send(&mut stream, 6, &Command::Unsubscribe { id: 5 }).await;
+    assert!(matches!(
+        receive_reply(&mut stream).await,
+        (6, Response::Ok)
+    ));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    send(&mut stream, 7, &Command::Ping).await;
+    assert!(matches!(receive(&mut stream).await, (7, Response::Pong)));
+
+    for id in 0..MAX_SUBSCRIPTIONS as u32 {
+        send(&mut stream, 100 + id, &subscribe).await;
+        assert!(matches!(
+            receive_reply(&mut stream).await,
+            (_, Response::Ok)
+        ));
+    }
+    send(&mut stream, 200, &subscribe).await;
+    match receive_reply(&mut stream).await {
+        (200, Response::Error(error)) => assert!(error.message.contains("subscriptions")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn client_falls_back_to_ordered_responses() {
+    // Stand-in for a protocol 2 server: no request ids, answers in order
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        loop {
+            let mut header = [0u8; 8];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            assert_eq!(
+                u32::from_le_bytes(header[0..4].try_into().unwrap()),
+                HANDSHAKE_VERSION
+            );
+            let length = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            let mut body = vec![0u8; length as usize];
+            stream.read_exact(&mut body).await.unwrap();
+            let response = match serde_json::from_slice(&body).unwrap() {
+                Command::Hello(_) => Response::Hello(Capabilities {
+                    version: 2,
+                    agent: "pdb/old".into(),
+                    commands: vec!["Ping".into()],
+                    encodings: vec!["json".into()],
+                    encoding: "json".into(),
+                    backend: "mock".into(),
+                    os: "linux".into(),
+                    screen_size: None,
+                }),
+                Command::Ping => Response::Pong,
+                other => Response::Error(format!("unknown {}", other.name()).into()),
+            };
+            let body = serde_json::to_vec(&response).unwrap();
+            let header = MessageHeader::with_version(2, body.len() as u32);
+            stream.write_all(&header.to_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+        }
+    });
+
+    let client = Client::connect(&addr).await.unwrap();
+    assert_eq!(client.protocol_version(), 2);
+    let (a, b) = tokio::join!(client.ping(), client.ping());
+    assert!(a.unwrap() && b.unwrap());
+    match client.list_windows().await {
+        Err(PdbError::ConnectionError(e)) => assert!(e.contains("unknown ListWindows"), "{}", e),
+        other => panic!("unexpected {:?}", other.map(|w| w.len())),
+    }
+}
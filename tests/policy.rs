@@ -0,0 +1,154 @@
+//! Authorization policies: read-only mode, command lists and window patterns
+
+mod common;
+
+use pdb::backend::mock::{MockBackend, MockWindow};
+use pdb::policy::PolicyFile;
+use pdb::{Client, ClientOptions, Command, ErrorKind, PdbError, Policy, Rect, Response, Server};
+use std::sync::Arc;
+
+const NOTEPAD: usize = 0x100;
+const TERMINAL: usize = 0x200;
+
+async fn start_server(configure: impl FnOnce(Server) -> Server) -> (String, Arc<MockBackend>) {
+    let backend = Arc::new(MockBackend::with_windows([
+        MockWindow::new(
+            NOTEPAD,
+            "notes.txt - Notepad",
+            "Edit",
+            Rect::new(0, 0, 320, 240),
+        ),
+        MockWindow::new(TERMINAL, "Terminal", "Console", Rect::new(0, 0, 640, 480)),
+    ]));
+    let addr = common::serve(backend.clone(), configure).await;
+    (addr, backend)
+}
+
+fn is_denied<T>(result: pdb::Result<T>) -> bool {
+    matches!(result, Err(PdbError::PermissionDenied(_)))
+}
+
+#[tokio::test]
+async fn read_only_clients_only_observe() {
+    let (addr, backend) = start_server(|server| server.with_policy(Policy::read_only())).await;
+    let client = Client::connect(&addr).await.unwrap();
+    assert_eq!(client.list_windows().await.unwrap().len(), 2);
+    let device = client.connect_window_by_hwnd(NOTEPAD).await.unwrap();
+    assert_eq!(device.screenshot().await.unwrap().width, 320);
+    assert_eq!(device.get_size().await.unwrap(), (320, 240));
+
+    assert!(is_denied(device.click(1, 1).await));
+    assert!(is_denied(device.input_text("x").await));
+    assert!(is_denied(device.focus().await));
+    assert!(is_denied(
+        device
+            .acquire_lease(std::time::Duration::from_secs(1), Default::default())
+            .await
+    ));
+    // A batch is refused as a whole
+    let batch = device
+        .batch()
+        .screenshot(Default::default(), None)
+        .click(1, 1);
+    assert!(is_denied(batch.send().await));
+    assert!(backend.take_events().is_empty());
+
+    match client
+        .send_command(Command::Focus { hwnd: NOTEPAD })
+        .await
+        .unwrap()
+    {
+        Response::Error(error) => assert_eq!(error.kind, ErrorKind::PermissionDenied),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn commands_can_be_allowed_or_denied_by_name() {
+    let policy = Policy {
+        allow: vec!["ConnectByHwnd".into(), "Click".into(), "Screenshot".into()],
+        deny: vec!["Screenshot".into()],
+        ..Policy::default()
+    };
+    let (addr, _backend) = start_server(|server| server.with_policy(policy)).await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(NOTEPAD).await.unwrap();
+
+    device.click(1, 1).await.unwrap();
+    // Denied wins over allowed
+    assert!(is_denied(device.screenshot().await));
+    assert!(is_denied(device.get_size().await));
+    assert!(is_denied(client.list_windows().await));
+    client.ping().await.unwrap();
+}
+
+#[tokio::test]
+async fn window_patterns_hide_other_windows() {
+    let policy = Policy {
+        titles: vec!["* - Notepad".into()],
+        ..Policy::default()
+    };
+    let (addr, _backend) = start_server(|server| server.with_policy(policy)).await;
+    let client = Client::connect(&addr).await.unwrap();
+
+    let windows = client.list_windows().await.unwrap();
+    assert_eq!(windows.len(), 1);
+    assert_eq!(windows[0].hwnd, NOTEPAD);
+    assert!(is_denied(client.connect_window_by_hwnd(TERMINAL).await));
+    assert!(is_denied(client.connect_window("Terminal").await));
+    let device = client.connect_window("Notepad").await.unwrap();
+    device.click(1, 1).await.unwrap();
+}
+
+#[tokio::test]
+async fn keys_grant_their_own_policy() {
+    let (addr, _backend) = start_server(|server| {
+        server
+            .with_key("admin")
+            .with_key_policy("viewer", Policy::read_only())
+    })
+    .await;
+    let connect = |key: &'static str| {
+        let addr = addr.clone();
+        async move {
+            let options = ClientOptions::default().with_key(key);
+            let client = Client::connect_with(&addr, options).await.unwrap();
+            let device = client.connect_window_by_hwnd(NOTEPAD).await.unwrap();
+            (client, device)
+        }
+    };
+
+    let (_viewer, watching) = connect("viewer").await;
+    assert!(is_denied(watching.click(1, 1).await));
+    watching.screenshot().await.unwrap();
+    let (_admin, driving) = connect("admin").await;
+    driving.click(1, 1).await.unwrap();
+}
+
+#[tokio::test]
+async fn policy_files_name_known_commands() {
+    let dir = std::env::temp_dir().join(format!("pdb-policy-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let key_file = dir.join("viewer.key");
+    std::fs::write(&key_file, "viewer\n").unwrap();
+    let path = dir.join("policy.json");
+    let contents = serde_json::json!({
+        "deny": ["Swipe"],
+        "titles": ["* - Notepad"],
+        "keys": [{ "key_file": key_file, "read_only": true }],
+    });
+    std::fs::write(&path, contents.to_string()).unwrap();
+
+    let file = PolicyFile::load(&path).unwrap();
+    assert_eq!(file.policy.deny, vec!["Swipe"]);
+    assert!(!file.policy.read_only);
+    assert!(file.keys[0].policy.read_only);
+    assert_eq!(file.keys[0].key().unwrap(), b"viewer");
+
+    std::fs::write(&path, r#"{"deny": ["Swype"]}"#).unwrap();
+    assert!(matches!(
+        PolicyFile::load(&path),
+        Err(PdbError::ProtocolError(_))
+    ));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,338 @@
+//! RFB server serving a mock window to the VNC client backend and a raw viewer
+
+#![cfg(feature = "vnc")]
+
+use pdb::backend::mock::{solid_frame, MockBackend, MockEvent, MockWindow};
+use pdb::backend::vnc::{VncBackend, VNC_HWND};
+use pdb::rfb::{self, PixelFormat};
+use pdb::{Client, Device, KeyCode, PdbError, Policy, Rect, RfbServer, Server, WindowController};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HWND: usize = 0x42;
+const GREY: [u8; 4] = [128, 128, 128, 255];
+
+fn mock() -> Arc<MockBackend> {
+    let window =
+        MockWindow::new(HWND, "Target", "TargetClass", Rect::new(0, 0, 160, 100)).with_color(GREY);
+    Arc::new(MockBackend::with_windows(vec![window]))
+}
+
+/// Serve the mock window and return the RFB address
+fn serve(backend: Arc<MockBackend>, password: Option<&str>) -> String {
+    let controller = WindowController::with_backend(backend);
+    let device: Device = controller.device(controller.get_window_by_hwnd(HWND).unwrap());
+    let mut server = RfbServer::new(device).with_poll_interval(Duration::from_millis(10));
+    if let Some(password) = password {
+        server = server.with_password(password);
+    }
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || server.serve(listener));
+    addr
+}
+
+fn wait_for_events(backend: &MockBackend, count: usize) -> Vec<MockEvent> {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let events = backend.events();
+        if events.len() >= count || Instant::now() > deadline {
+            return events;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn vnc_client_sees_frames_and_drives_device() {
+    let backend = mock();
+    let addr = serve(backend.clone(), Some("pw"));
+
+    let vnc = VncBackend::connect(&addr, Some("pw")).unwrap();
+    assert_eq!(vnc.desktop_name(), "Target");
+    let controller = WindowController::with_backend(Arc::new(vnc));
+    let remote = controller.device(controller.get_window_by_hwnd(VNC_HWND).unwrap());
+
+    let shot = remote.screenshot().unwrap();
+    assert_eq!((shot.width, shot.height), (160, 100));
+    assert_eq!(&shot.data[..4], &GREY);
+
+    remote.click(20, 30).unwrap();
+    remote.swipe(10, 10, 100, 80, 100).unwrap();
+    remote.key_event(KeyCode::Enter).unwrap();
+    remote.key_event(KeyCode::Shift).unwrap();
+    remote.input_text("Hé").unwrap();
+
+    let events = wait_for_events(&backend, 5);
+    assert_eq!(
+        events[0],
+        MockEvent::Click {
+            hwnd: HWND,
+            x: 20,
+            y: 30
+        }
+    );
+    match &events[1] {
+        MockEvent::Swipe { x1, y1, x2, y2, .. } => {
+            assert_eq!((*x1, *y1, *x2, *y2), (10, 10, 100, 80))
+        }
+        other => panic!("expected swipe, got {:?}", other),
+    }
+    assert_eq!(
+        &events[2..],
+        &[
+            MockEvent::KeyEvent {
+                hwnd: HWND,
+                key: KeyCode::Enter
+            },
+            MockEvent::InputText {
+                hwnd: HWND,
+                text: "H".into()
+            },
+            MockEvent::InputText {
+                hwnd: HWND,
+                text: "é".into()
+            },
+        ]
+    );
+
+    // A changed window shows up in the next update
+    backend
+        .set_frame(HWND, solid_frame(160, 100, [0, 0, 255, 255]))
+        .unwrap();
+    let shot = remote.screenshot().unwrap();
+    assert_eq!(&shot.data[..4], &[0, 0, 255, 255]);
+}
+
+/// Serve the mock window to VNC viewers under `server`'s rules
+fn serve_for(server: &Server) -> pdb::Result<String> {
+    let rfb = server
+        .rfb_server_for_title("Target")?
+        .with_poll_interval(Duration::from_millis(10));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || rfb.serve(listener));
+    Ok(addr)
+}
+
+fn vnc_device(addr: &str) -> Device {
+    let controller =
+        WindowController::with_backend(Arc::new(VncBackend::connect(addr, None).unwrap()));
+    controller.device(controller.get_window_by_hwnd(VNC_HWND).unwrap())
+}
+
+#[test]
+fn server_policy_makes_viewers_watch_only() {
+    let backend = mock();
+    let server = Server::with_backend("unused", backend.clone()).with_policy(Policy::read_only());
+    let remote = vnc_device(&serve_for(&server).unwrap());
+
+    remote.click(20, 30).unwrap();
+    remote.key_event(KeyCode::Enter).unwrap();
+    assert_eq!(remote.screenshot().unwrap().width, 160);
+    thread::sleep(Duration::from_millis(100));
+    assert!(backend.events().is_empty());
+
+    // Windows the policy hides are not served at all
+    let hidden = Policy {
+        titles: vec!["Other".into()],
+        ..Policy::default()
+    };
+    let server = Server::with_backend("unused", backend.clone()).with_policy(hidden);
+    assert!(matches!(
+        serve_for(&server),
+        Err(PdbError::PermissionDenied(_))
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn viewer_input_respects_leases() {
+    let backend = mock();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Arc::new(Server::with_backend(&addr, backend.clone()));
+    let vnc_addr = serve_for(&server).unwrap();
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve(listener).await });
+
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    device
+        .acquire_lease(Duration::from_secs(30), Duration::ZERO)
+        .await
+        .unwrap();
+    let click = |x| {
+        let vnc_addr = vnc_addr.clone();
+        tokio::task::spawn_blocking(move || {
+            let remote = vnc_device(&vnc_addr);
+            remote.click(x, 1).unwrap();
+            // A screenshot round trip makes sure the click was read
+            remote.screenshot().unwrap();
+        })
+    };
+    click(5).await.unwrap();
+    assert!(backend.take_events().is_empty());
+
+    device.release_lease().await.unwrap();
+    click(6).await.unwrap();
+    let events = tokio::task::spawn_blocking({
+        let backend = backend.clone();
+        move || wait_for_events(&backend, 1)
+    })
+    .await
+    .unwrap();
+    assert_eq!(
+        events,
+        vec![MockEvent::Click {
+            hwnd: HWND,
+            x: 6,
+            y: 1
+        }]
+    );
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let addr = serve(mock(), Some("pw"));
+    let err = VncBackend::connect(&addr, Some("nope")).unwrap_err();
+    assert!(err.to_string().contains("authentication failed"), "{}", err);
+}
+
+/// Rectangle position, size and encoding
+type RectHeader = (u16, u16, u16, u16, i32);
+
+/// Minimal viewer speaking RFB 3.8 without authentication
+struct Viewer {
+    stream: TcpStream,
+}
+
+impl Viewer {
+    fn connect(addr: &str) -> (Self, u16, u16) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut version = [0u8; 12];
+        stream.read_exact(&mut version).unwrap();
+        stream.write_all(rfb::RFB_VERSION).unwrap();
+        let mut security = [0u8; 2];
+        stream.read_exact(&mut security).unwrap();
+        assert_eq!(security, [1, rfb::SECURITY_NONE]);
+        stream.write_all(&[rfb::SECURITY_NONE]).unwrap();
+        assert_eq!(rfb::read_u32(&mut stream).unwrap(), 0);
+
+        stream.write_all(&[1]).unwrap();
+        let width = rfb::read_u16(&mut stream).unwrap();
+        let height = rfb::read_u16(&mut stream).unwrap();
+        PixelFormat::read_from(&mut stream).unwrap();
+        let name_len = rfb::read_u32(&mut stream).unwrap();
+        rfb::skip(&mut stream, name_len as u64).unwrap();
+        (Self { stream }, width, height)
+    }
+
+    fn set_pixel_format(&mut self, format: PixelFormat) {
+        let mut msg = vec![rfb::CLIENT_SET_PIXEL_FORMAT, 0, 0, 0];
+        msg.extend_from_slice(&format.to_bytes());
+        self.stream.write_all(&msg).unwrap();
+    }
+
+    fn set_encodings(&mut self, encodings: &[i32]) {
+        let mut msg = vec![rfb::CLIENT_SET_ENCODINGS, 0];
+        msg.extend_from_slice(&(encodings.len() as u16).to_be_bytes());
+        for encoding in encodings {
+            msg.extend_from_slice(&encoding.to_be_bytes());
+        }
+        self.stream.write_all(&msg).unwrap();
+    }
+
+    /// Request an update and return its rectangles as ((x, y, w, h, encoding), data)
+    fn update(&mut self, incremental: bool, bytes_per_pixel: usize) -> Vec<(RectHeader, Vec<u8>)> {
+        rfb::write_update_request(&mut self.stream, incremental, 0, 0).unwrap();
+        let s = &mut self.stream;
+        assert_eq!(rfb::read_u8(s).unwrap(), rfb::SERVER_FRAMEBUFFER_UPDATE);
+        rfb::skip(s, 1).unwrap();
+        let count = rfb::read_u16(s).unwrap();
+        (0..count)
+            .map(|_| {
+                let x = rfb::read_u16(s).unwrap();
+                let y = rfb::read_u16(s).unwrap();
+                let w = rfb::read_u16(s).unwrap();
+                let h = rfb::read_u16(s).unwrap();
+                let encoding = rfb::read_i32(s).unwrap();
+                let len = if encoding == rfb::ENCODING_RAW {
+                    w as usize * h as usize * bytes_per_pixel
+                } else {
+                    0
+                };
+                let data = rfb::read_bytes(s, len, len).unwrap();
+                ((x, y, w, h, encoding), data)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn incremental_updates_only_carry_dirty_tiles() {
+    let backend = mock();
+    let addr = serve(backend.clone(), None);
+    let (mut viewer, width, height) = Viewer::connect(&addr);
+    assert_eq!((width, height), (160, 100));
+
+    let full = viewer.update(false, 4);
+    assert_eq!(full.len(), 1);
+    assert_eq!(full[0].0, (0, 0, 160, 100, rfb::ENCODING_RAW));
+    assert_eq!(&full[0].1[..4], &[128, 128, 128, 0]);
+
+    // Change two pixels in the second tile column of the second tile row
+    let mut frame = solid_frame(160, 100, GREY);
+    for (x, y) in [(70usize, 70usize), (100, 90)] {
+        let i = (y * 160 + x) * 4;
+        frame.data[i..i + 4].copy_from_slice(&[255, 0, 0, 255]);
+    }
+    backend.set_frame(HWND, frame).unwrap();
+
+    let update = viewer.update(true, 4);
+    assert_eq!(update.len(), 1);
+    assert_eq!(update[0].0, (64, 64, 64, 36, rfb::ENCODING_RAW));
+    let i = ((70 - 64) * 64 + (70 - 64)) * 4;
+    assert_eq!(&update[0].1[i..i + 4], &[255, 0, 0, 0]);
+}
+
+#[test]
+fn converts_pixel_format_and_announces_resize() {
+    let backend = mock();
+    let addr = serve(backend.clone(), None);
+    let (mut viewer, _, _) = Viewer::connect(&addr);
+
+    // 16-bit big-endian RGB565
+    viewer.set_pixel_format(PixelFormat {
+        bits_per_pixel: 16,
+        depth: 16,
+        big_endian: true,
+        true_colour: true,
+        red_max: 31,
+        green_max: 63,
+        blue_max: 31,
+        red_shift: 11,
+        green_shift: 5,
+        blue_shift: 0,
+    });
+    viewer.set_encodings(&[rfb::ENCODING_RAW, rfb::ENCODING_DESKTOP_SIZE]);
+    backend
+        .set_frame(HWND, solid_frame(160, 100, [255, 0, 0, 255]))
+        .unwrap();
+    let full = viewer.update(false, 2);
+    assert_eq!(&full[0].1[..2], &[0xF8, 0x00]);
+
+    backend
+        .set_frame(HWND, solid_frame(200, 120, [0, 255, 0, 255]))
+        .unwrap();
+    let update = viewer.update(true, 2);
+    assert_eq!(update.len(), 2);
+    assert_eq!(update[0].0, (0, 0, 200, 120, rfb::ENCODING_DESKTOP_SIZE));
+    assert_eq!(update[1].0, (0, 0, 200, 120, rfb::ENCODING_RAW));
+    assert_eq!(&update[1].1[..2], &[0x07, 0xE0]);
+}
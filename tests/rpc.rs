@@ -0,0 +1,218 @@
+//! JSON-RPC 2.0 interface
+
+mod common;
+
+use base64::Engine;
+use common::{read_message, write_message};
+use pdb::backend::mock::{MockBackend, MockEvent, MockWindow};
+use pdb::protocol::{Capabilities, HANDSHAKE_VERSION, PROTOCOL_VERSION};
+use pdb::rpc::{self, RpcServer};
+use pdb::{Client, Rect, Response, Server, WindowController};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+
+const HWND: usize = 0x100;
+
+fn mock() -> Arc<MockBackend> {
+    Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Notepad",
+        "Edit",
+        Rect::new(0, 0, 64, 48),
+    )]))
+}
+
+async fn call(rpc: &RpcServer, id: u32, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    let reply = rpc.handle_message(&request.to_string()).await.unwrap();
+    assert_eq!(reply["id"], id);
+    reply
+}
+
+fn decode_png(result: &Value) -> image::DynamicImage {
+    assert_eq!(result["format"], "png");
+    let data = base64::engine::general_purpose::STANDARD
+        .decode(result["data"].as_str().unwrap())
+        .unwrap();
+    image::load_from_memory(&data).unwrap()
+}
+
+#[tokio::test]
+async fn local_methods() {
+    let backend = mock();
+    let rpc = RpcServer::local(WindowController::with_backend(backend.clone()));
+
+    let windows = call(&rpc, 1, "list_windows", json!({})).await;
+    assert_eq!(windows["result"][0]["title"], "Notepad");
+
+    // Windows are connected on first use
+    let click = call(&rpc, 2, "click", json!({ "hwnd": HWND, "x": 3, "y": 4 })).await;
+    assert_eq!(click["result"], Value::Null);
+    assert!(backend
+        .events()
+        .iter()
+        .any(|e| matches!(e, MockEvent::Click { x: 3, y: 4, .. })));
+
+    let size = call(&rpc, 3, "get_size", json!({ "hwnd": HWND })).await;
+    assert_eq!(size["result"], json!({ "width": 64, "height": 48 }));
+
+    let shot = call(&rpc, 4, "screenshot", json!({ "hwnd": HWND, "scale": 0.5 })).await;
+    let image = decode_png(&shot["result"]);
+    assert_eq!((image.width(), image.height()), (32, 24));
+    // Delta frames have no JSON form, so a base is ignored
+    let full = call(&rpc, 6, "screenshot", json!({ "hwnd": HWND, "base": 0 })).await;
+    assert_eq!(decode_png(&full["result"]).width(), 64);
+
+    let key = call(
+        &rpc,
+        5,
+        "key_event",
+        json!({ "hwnd": HWND, "key": "Enter" }),
+    )
+    .await;
+    assert_eq!(key["result"], Value::Null);
+}
+
+#[tokio::test]
+async fn errors_have_codes() {
+    let rpc = RpcServer::local(WindowController::with_backend(mock()));
+
+    let cases = [
+        ("launch", json!({}), rpc::METHOD_NOT_FOUND),
+        (
+            "click",
+            json!({ "hwnd": HWND, "x": "left" }),
+            rpc::INVALID_PARAMS,
+        ),
+        ("click", json!([HWND, 1, 2]), rpc::INVALID_PARAMS),
+        (
+            "click",
+            json!({ "hwnd": 0x999, "x": 1, "y": 2 }),
+            rpc::WINDOW_NOT_FOUND,
+        ),
+        (
+            "acquire_lease",
+            json!({ "hwnd": HWND, "duration_ms": 0 }),
+            rpc::INVALID_PARAMS,
+        ),
+    ];
+    for (id, (method, params, code)) in cases.into_iter().enumerate() {
+        let reply = call(&rpc, id as u32, method, params).await;
+        assert_eq!(reply["error"]["code"], code, "{}", reply);
+    }
+
+    let garbage = rpc.handle_message("{not json").await.unwrap();
+    assert_eq!(garbage["error"]["code"], rpc::PARSE_ERROR);
+    assert_eq!(garbage["id"], Value::Null);
+    let old = rpc
+        .handle_message(r#"{"id": 1, "method": "ping"}"#)
+        .await
+        .unwrap();
+    assert_eq!(old["error"]["code"], rpc::INVALID_REQUEST);
+
+    // Notifications run but are not answered, also inside a batch
+    assert!(rpc
+        .handle_message(r#"{"jsonrpc": "2.0", "method": "ping"}"#)
+        .await
+        .is_none());
+    let batch = rpc
+        .handle_message(
+            r#"[{"jsonrpc": "2.0", "id": 1, "method": "ping"},
+                {"jsonrpc": "2.0", "method": "ping"},
+                {"jsonrpc": "2.0", "id": 2, "method": "nope"}]"#,
+        )
+        .await
+        .unwrap();
+    assert_eq!(batch[0]["result"], true);
+    assert_eq!(batch[1]["error"]["code"], rpc::METHOD_NOT_FOUND);
+    assert_eq!(batch.as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn remote_over_lines() {
+    let backend = mock();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = Server::with_backend(&addr, backend.clone());
+    tokio::spawn(async move { server.serve(listener).await });
+    let rpc = RpcServer::remote(Client::connect(&addr).await.unwrap());
+
+    // Stand-ins for stdin and stdout
+    let (mut input, input_end) = tokio::io::duplex(4096);
+    let (output_end, output) = tokio::io::duplex(1 << 20);
+    let serving = tokio::spawn(async move { rpc.serve(input_end, output_end).await });
+    let mut output = BufReader::new(output).lines();
+
+    input
+        .write_all(
+            br#"{"jsonrpc": "2.0", "id": 1, "method": "input_text", "params": {"hwnd": 256, "text": "hi"}}
+
+{"jsonrpc": "2.0", "id": 2, "method": "screenshot", "params": {"hwnd": 256}}
+{"jsonrpc": "2.0", "id": 3, "method": "connect", "params": {"title": "Calculator"}}
+"#,
+        )
+        .await
+        .unwrap();
+    drop(input);
+
+    let mut replies = Vec::new();
+    while let Some(line) = output.next_line().await.unwrap() {
+        replies.push(serde_json::from_str::<Value>(&line).unwrap());
+    }
+    serving.await.unwrap().unwrap();
+
+    assert_eq!(replies.len(), 3);
+    assert_eq!(replies[0]["result"], Value::Null);
+    assert!(backend
+        .events()
+        .iter()
+        .any(|e| matches!(e, MockEvent::InputText { text, .. } if text == "hi")));
+    assert_eq!(decode_png(&replies[1]["result"]).width(), 64);
+    // The server's error keeps its kind
+    assert_eq!(replies[2]["error"]["code"], rpc::WINDOW_NOT_FOUND);
+    assert_eq!(replies[2]["error"]["data"]["kind"], "WindowNotFound");
+    assert!(replies[2]["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("Calculator"));
+}
+
+#[tokio::test]
+async fn unreadable_remote_reply_is_no_parameter_error() {
+    // A newer server answering with a response this version does not know
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read_message(&mut stream).await.unwrap();
+        let hello = Response::Hello(Capabilities {
+            version: PROTOCOL_VERSION,
+            agent: "pdb/new".into(),
+            commands: vec!["ListWindows".into()],
+            encodings: vec!["json".into()],
+            encoding: "json".into(),
+            backend: "mock".into(),
+            os: "linux".into(),
+            screen_size: None,
+        });
+        let body = serde_json::to_vec(&hello).unwrap();
+        write_message(&mut stream, HANDSHAKE_VERSION, 0, &body)
+            .await
+            .unwrap();
+        let (header, _) = read_message(&mut stream).await.unwrap();
+        write_message(
+            &mut stream,
+            PROTOCOL_VERSION,
+            header.id,
+            br#"{"Hologram": [1, 2]}"#,
+        )
+        .await
+        .unwrap();
+    });
+    let rpc = RpcServer::remote(Client::connect(&addr).await.unwrap());
+
+    let reply = call(&rpc, 1, "list_windows", json!({})).await;
+    assert_eq!(reply["error"]["code"], rpc::SERVER_ERROR, "{}", reply);
+}
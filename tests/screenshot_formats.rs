@@ -0,0 +1,108 @@
+//! Compressed and scaled screenshot transfer
+
+mod common;
+
+use common::serve;
+use pdb::backend::mock::{solid_frame, MockBackend, MockWindow};
+use pdb::{Client, PdbError, Rect, RemoteDevice, Screenshot, ScreenshotFormat};
+use std::sync::Arc;
+
+const HWND: usize = 0x100;
+
+/// Smooth test image: red rises left to right, green top to bottom
+fn gradient(width: u32, height: u32) -> Screenshot {
+    let mut frame = solid_frame(width, height, [0, 0, 128, 255]);
+    for (i, px) in frame.data.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        px[0] = (x * 255 / width) as u8;
+        px[1] = (y * 255 / height) as u8;
+    }
+    frame
+}
+
+async fn connect(frame: Screenshot) -> RemoteDevice {
+    let backend = Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Game",
+        "GameClass",
+        Rect::new(0, 0, frame.width as i32, frame.height as i32),
+    )
+    .with_frame(frame)]));
+    let addr = serve(backend, |server| server).await;
+
+    let client = Client::connect(&addr).await.unwrap();
+    client.connect_window_by_hwnd(HWND).await.unwrap()
+}
+
+#[tokio::test]
+async fn lossless_formats_round_trip_exactly() {
+    let frame = gradient(320, 200);
+    let device = connect(frame.clone()).await;
+
+    for format in [
+        ScreenshotFormat::Raw,
+        ScreenshotFormat::Png,
+        ScreenshotFormat::WebP,
+    ] {
+        let shot = device.screenshot_with(format, None).await.unwrap();
+        assert_eq!((shot.width, shot.height), (320, 200), "{:?}", format);
+        assert!(shot.data == frame.data, "{:?} changed pixels", format);
+    }
+}
+
+#[tokio::test]
+async fn jpeg_is_close_and_opaque() {
+    let frame = gradient(320, 200);
+    let device = connect(frame.clone()).await;
+
+    let shot = device
+        .screenshot_with(ScreenshotFormat::Jpeg { quality: 85 }, None)
+        .await
+        .unwrap();
+    assert_eq!((shot.width, shot.height), (320, 200));
+    let max_error = shot
+        .data
+        .iter()
+        .zip(&frame.data)
+        .map(|(a, b)| a.abs_diff(*b))
+        .max()
+        .unwrap();
+    assert!(max_error < 16, "max channel error {}", max_error);
+    assert!(shot.data.chunks_exact(4).all(|px| px[3] == 255));
+}
+
+#[tokio::test]
+async fn scale_is_applied_before_encoding() {
+    let device = connect(gradient(320, 200)).await;
+
+    let shot = device
+        .screenshot_with(ScreenshotFormat::Png, Some(0.5))
+        .await
+        .unwrap();
+    assert_eq!((shot.width, shot.height), (160, 100));
+    assert_eq!(shot.data.len(), 160 * 100 * 4);
+
+    let err = device
+        .screenshot_with(ScreenshotFormat::Raw, Some(2.0))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, PdbError::CaptureError(_)), "{}", err);
+}
+
+#[test]
+fn compressed_formats_are_smaller_than_raw() {
+    let frame = gradient(640, 480);
+    for format in [
+        ScreenshotFormat::Png,
+        ScreenshotFormat::Jpeg { quality: 75 },
+        ScreenshotFormat::WebP,
+    ] {
+        let encoded = frame.encode(format).unwrap();
+        assert!(
+            encoded.data.len() < frame.data.len() / 4,
+            "{:?}: {} bytes",
+            format,
+            encoded.data.len()
+        );
+    }
+}
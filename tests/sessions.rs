@@ -0,0 +1,128 @@
+//! Sessions owning the devices of their connection
+
+mod common;
+
+use common::{listen, notepad, HWND};
+use pdb::{Client, ClientOptions, Command, ErrorKind, PdbError, Policy, Response, Server};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn start_server() -> (String, Arc<Server>) {
+    start_server_with(|server| server.with_policy(Policy::admin())).await
+}
+
+async fn start_server_with(configure: impl FnOnce(Server) -> Server) -> (String, Arc<Server>) {
+    let (listener, addr) = listen().await;
+    let server = Arc::new(configure(Server::with_backend(&addr, notepad())));
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve(listener).await });
+    (addr, server)
+}
+
+/// Wait for the server to notice connections that came or went
+async fn settle(server: &Server, count: usize) {
+    for _ in 0..100 {
+        if server.sessions().await.len() == count {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("expected {} sessions", count);
+}
+
+#[tokio::test]
+async fn devices_belong_to_their_connection() {
+    let (addr, server) = start_server().await;
+    let owner = Client::connect(&addr).await.unwrap();
+    let other = Client::connect(&addr).await.unwrap();
+    let device = owner.connect_window_by_hwnd(HWND).await.unwrap();
+    device.click(1, 1).await.unwrap();
+
+    let click = Command::Click {
+        hwnd: HWND,
+        x: 1,
+        y: 1,
+    };
+    match other.send_command(click).await.unwrap() {
+        Response::Error(error) => assert_eq!(error.kind, ErrorKind::DeviceNotConnected),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let sessions = owner.list_sessions().await.unwrap();
+    assert_eq!(sessions, server.sessions().await);
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].devices, vec![HWND]);
+    assert!(sessions[1].devices.is_empty());
+    assert!(sessions.iter().all(|s| s.endpoint == "pdb"));
+    assert!(sessions[0].id < sessions[1].id);
+}
+
+#[tokio::test]
+async fn disconnect_releases_devices() {
+    let (addr, server) = start_server().await;
+    let client = Client::connect(&addr).await.unwrap();
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+
+    client.disconnect().await.unwrap();
+    assert!(matches!(
+        device.click(1, 1).await,
+        Err(PdbError::DeviceNotConnected(HWND))
+    ));
+    assert!(server.sessions().await[0].devices.is_empty());
+
+    // The connection itself stays usable
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    device.click(1, 1).await.unwrap();
+}
+
+#[tokio::test]
+async fn closed_connections_end_their_session() {
+    let (addr, server) = start_server().await;
+    let staying = Client::connect(&addr).await.unwrap();
+    let leaving = Client::connect(&addr).await.unwrap();
+    leaving.connect_window_by_hwnd(HWND).await.unwrap();
+    settle(&server, 2).await;
+
+    drop(leaving);
+    settle(&server, 1).await;
+    assert!(staying.list_sessions().await.unwrap()[0].devices.is_empty());
+
+    // In-process commands run in a session of their own, which is not listed
+    let connect = server
+        .handle_command(Command::ConnectByHwnd { hwnd: HWND })
+        .await;
+    assert!(matches!(connect, Response::Window(_)));
+    assert_eq!(server.sessions().await.len(), 1);
+}
+
+#[tokio::test]
+async fn only_admins_list_sessions() {
+    let admin = Policy {
+        titles: vec!["Terminal".into()],
+        ..Policy::admin()
+    };
+    let (addr, _server) = start_server_with(|server| {
+        server
+            .with_key_policy("user", Policy::default())
+            .with_key_policy("admin", admin)
+    })
+    .await;
+    let connect = |key: &str| Client::connect_with(&addr, ClientOptions::default().with_key(key));
+
+    let user = connect("user").await.unwrap();
+    user.connect_window_by_hwnd(HWND).await.unwrap();
+    assert!(matches!(
+        user.list_sessions().await,
+        Err(PdbError::PermissionDenied(_))
+    ));
+
+    // Admins do not see windows their policy hides either
+    let sessions = connect("admin")
+        .await
+        .unwrap()
+        .list_sessions()
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert!(sessions[0].devices.is_empty());
+}
@@ -0,0 +1,154 @@
+//! TLS connections with self-signed certificates
+
+#![cfg(feature = "tls")]
+
+mod common;
+
+use common::HWND;
+use pdb::{Client, ClientOptions, PdbError, TlsClientConfig, TlsServerConfig};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertifiedKey, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair,
+};
+
+/// Certificate authority for the tests
+struct Ca {
+    cert: Certificate,
+    key: KeyPair,
+}
+
+impl Ca {
+    fn new() -> Self {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Self { cert, key }
+    }
+
+    /// Issue a certificate as (cert PEM, key PEM)
+    fn issue(&self, name: &str, usage: ExtendedKeyUsagePurpose) -> (String, String) {
+        let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+        params.extended_key_usages = vec![usage];
+        let key = KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        (cert.pem(), key.serialize_pem())
+    }
+
+    fn pem(&self) -> String {
+        self.cert.pem()
+    }
+}
+
+fn self_signed(name: &str) -> (String, String) {
+    let CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+    (cert.pem(), key_pair.serialize_pem())
+}
+
+async fn start_server(tls: TlsServerConfig) -> String {
+    common::start_server(|server| server.with_tls(tls)).await.0
+}
+
+async fn connect(addr: &str, tls: TlsClientConfig) -> pdb::Result<Client> {
+    Client::connect_with(addr, ClientOptions::default().with_tls(tls)).await
+}
+
+#[tokio::test]
+async fn pinned_self_signed_certificate() {
+    let (cert, key) = self_signed("pdb.invalid");
+    let server_tls = TlsServerConfig::from_pem(cert.as_bytes(), key.as_bytes()).unwrap();
+    let fingerprint = server_tls.fingerprint();
+    assert_eq!(
+        fingerprint,
+        pdb::tls::fingerprint_pem(cert.as_bytes()).unwrap()
+    );
+    let addr = start_server(server_tls).await;
+
+    // The pin is all that counts: the name does not match 127.0.0.1
+    let client = connect(&addr, TlsClientConfig::pinned(&fingerprint).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(client.list_windows().await.unwrap().len(), 1);
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    assert_eq!(device.screenshot().await.unwrap().width, 320);
+
+    // Colons are optional, and any of several pins may match
+    let (other, _) = self_signed("other.invalid");
+    let other = pdb::tls::fingerprint_pem(other.as_bytes()).unwrap();
+    let tls = TlsClientConfig::pinned(&other)
+        .unwrap()
+        .with_pin(&fingerprint.replace(':', "").to_lowercase())
+        .unwrap();
+    assert!(connect(&addr, tls).await.unwrap().ping().await.unwrap());
+
+    // Another certificate is rejected
+    let tls = TlsClientConfig::pinned(&other).unwrap();
+    assert!(matches!(
+        connect(&addr, tls).await,
+        Err(PdbError::TlsError(_))
+    ));
+    assert!(TlsClientConfig::pinned("12:34").is_err());
+}
+
+#[tokio::test]
+async fn ca_verified_certificate() {
+    let ca = Ca::new();
+    let (cert, key) = ca.issue("pdb.test", ExtendedKeyUsagePurpose::ServerAuth);
+    let addr =
+        start_server(TlsServerConfig::from_pem(cert.as_bytes(), key.as_bytes()).unwrap()).await;
+
+    let tls = TlsClientConfig::with_ca_pem(ca.pem().as_bytes())
+        .unwrap()
+        .with_server_name("pdb.test");
+    assert!(connect(&addr, tls).await.unwrap().ping().await.unwrap());
+
+    // Wrong name, or a CA that did not sign it
+    let tls = TlsClientConfig::with_ca_pem(ca.pem().as_bytes())
+        .unwrap()
+        .with_server_name("other.test");
+    assert!(matches!(
+        connect(&addr, tls).await,
+        Err(PdbError::TlsError(_))
+    ));
+    let tls = TlsClientConfig::with_ca_pem(Ca::new().pem().as_bytes())
+        .unwrap()
+        .with_server_name("pdb.test");
+    assert!(matches!(
+        connect(&addr, tls).await,
+        Err(PdbError::TlsError(_))
+    ));
+
+    // A plain client does not get through
+    assert!(Client::connect(&addr).await.is_err());
+}
+
+#[tokio::test]
+async fn mutual_tls_requires_client_certificate() {
+    let ca = Ca::new();
+    let (cert, key) = self_signed("pdb.test");
+    let server_tls = TlsServerConfig::from_pem(cert.as_bytes(), key.as_bytes())
+        .unwrap()
+        .with_client_ca_pem(ca.pem().as_bytes())
+        .unwrap();
+    let pin = server_tls.fingerprint();
+    let addr = start_server(server_tls).await;
+
+    let (client_cert, client_key) = ca.issue("robot", ExtendedKeyUsagePurpose::ClientAuth);
+    let tls = TlsClientConfig::pinned(&pin)
+        .unwrap()
+        .with_client_cert_pem(client_cert.as_bytes(), client_key.as_bytes())
+        .unwrap();
+    let client = connect(&addr, tls).await.unwrap();
+    assert_eq!(client.list_windows().await.unwrap().len(), 1);
+
+    // No certificate, or one the CA did not sign
+    let tls = TlsClientConfig::pinned(&pin).unwrap();
+    assert!(connect(&addr, tls).await.is_err());
+    let (stranger_cert, stranger_key) = self_signed("robot");
+    let tls = TlsClientConfig::pinned(&pin)
+        .unwrap()
+        .with_client_cert_pem(stranger_cert.as_bytes(), stranger_key.as_bytes())
+        .unwrap();
+    assert!(connect(&addr, tls).await.is_err());
+}
@@ -0,0 +1,73 @@
+//! Unix domain sockets and arbitrary byte streams as transports
+
+mod common;
+
+use common::{notepad, HWND};
+use pdb::transport::Address;
+use pdb::{Client, ClientOptions, Server};
+
+#[test]
+fn addresses_are_parsed() {
+    assert_eq!(
+        Address::parse("127.0.0.1:5037").unwrap(),
+        Address::Tcp("127.0.0.1:5037".into())
+    );
+    assert_eq!(Address::parse("[::1]:5037").unwrap().host(), Some("::1"));
+    assert_eq!(
+        Address::parse("pdb.example:5037").unwrap().host(),
+        Some("pdb.example")
+    );
+    #[cfg(unix)]
+    {
+        let addr = Address::parse("unix:/run/pdb.sock").unwrap();
+        assert_eq!(addr, Address::Unix("/run/pdb.sock".into()));
+        assert_eq!(addr.host(), None);
+        assert_eq!(addr.to_string(), "unix:/run/pdb.sock");
+    }
+    assert!(Address::parse("unix:").is_err());
+}
+
+#[tokio::test]
+async fn any_byte_stream_carries_the_protocol() {
+    let server = Server::with_backend("unused", notepad());
+    let (client_end, server_end) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { server.serve_connection(server_end).await });
+
+    let client = Client::connect_stream(client_end, ClientOptions::default())
+        .await
+        .unwrap();
+    assert_eq!(client.list_windows().await.unwrap().len(), 1);
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    device.click(10, 20).await.unwrap();
+    assert_eq!(device.screenshot().await.unwrap().width, 320);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_is_private_and_replaces_stale_files() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("pdb-test-{}.sock", std::process::id()));
+    // Left behind by a server that is gone
+    drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+    assert!(path.exists());
+
+    let addr = format!("unix:{}", path.display());
+    let backend = notepad();
+    let server = Server::with_backend(&addr, backend.clone());
+    tokio::spawn(async move { server.start().await });
+
+    let client = loop {
+        match Client::connect(&addr).await {
+            Ok(client) => break client,
+            Err(_) => tokio::time::sleep(std::time::Duration::from_millis(10)).await,
+        }
+    };
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    let device = client.connect_window("Notepad").await.unwrap();
+    device.input_text("local").await.unwrap();
+    assert_eq!(backend.events().len(), 1);
+    std::fs::remove_file(&path).unwrap();
+}
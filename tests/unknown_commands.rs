@@ -0,0 +1,108 @@
+//! Commands and responses the other side cannot read
+
+mod common;
+
+use common::{listen, read_message, start_server, write_message};
+use pdb::protocol::{Capabilities, HANDSHAKE_VERSION, PROTOCOL_VERSION};
+use pdb::{Client, Command, ErrorKind, PdbError, Response};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpStream;
+
+async fn request(stream: &mut TcpStream, version: u32, body: &[u8]) -> Response {
+    write_message(stream, version, 7, body).await.unwrap();
+    let (_, body) = read_message(stream).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn unreadable_commands_are_answered() {
+    let (addr, _backend) = start_server(|server| server).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    let cases: [(&[u8], ErrorKind); 4] = [
+        (br#"{"Teleport": {"x": 1}}"#, ErrorKind::Unsupported),
+        (br#""SelfDestruct""#, ErrorKind::Unsupported),
+        (br#"{"Click": {"hwnd": "left"}}"#, ErrorKind::Protocol),
+        (b"not json", ErrorKind::Protocol),
+    ];
+    for (body, kind) in cases {
+        match request(&mut stream, PROTOCOL_VERSION, body).await {
+            Response::Error(error) => assert_eq!(error.kind, kind, "{}", error),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // Older clients get the message, and the connection still works
+    match request(&mut stream, 1, br#"{"Teleport": {}}"#).await {
+        Response::Error(error) => {
+            assert_eq!(error.kind, ErrorKind::Unknown);
+            assert_eq!(error.message, "Unsupported: Unknown command Teleport");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let ping = serde_json::to_vec(&Command::Ping).unwrap();
+    assert!(matches!(
+        request(&mut stream, PROTOCOL_VERSION, &ping).await,
+        Response::Pong
+    ));
+}
+
+#[tokio::test]
+async fn unreadable_command_before_authentication_closes() {
+    let (addr, _backend) = start_server(|server| server.with_key("secret")).await;
+    let mut stream = TcpStream::connect(&addr).await.unwrap();
+
+    let response = request(&mut stream, HANDSHAKE_VERSION, br#"{"Teleport": {}}"#).await;
+    assert!(matches!(response, Response::Error(_)), "{:?}", response);
+    let mut buf = [0u8; 1];
+    assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn unreadable_response_fails_only_its_request() {
+    // A newer server that answers the first request with a response this
+    // version does not know
+    let (listener, addr) = listen().await;
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        read_message(&mut stream).await.unwrap();
+        let hello = Response::Hello(Capabilities {
+            version: PROTOCOL_VERSION,
+            agent: "pdb/new".into(),
+            commands: vec!["Ping".into(), "ListWindows".into()],
+            encodings: vec!["json".into()],
+            encoding: "json".into(),
+            backend: "mock".into(),
+            os: "linux".into(),
+            screen_size: None,
+        });
+        let body = serde_json::to_vec(&hello).unwrap();
+        write_message(&mut stream, HANDSHAKE_VERSION, 0, &body)
+            .await
+            .unwrap();
+
+        let (header, _) = read_message(&mut stream).await.unwrap();
+        write_message(
+            &mut stream,
+            PROTOCOL_VERSION,
+            header.id,
+            br#"{"Hologram": [1, 2]}"#,
+        )
+        .await
+        .unwrap();
+        let (header, _) = read_message(&mut stream).await.unwrap();
+        let body = serde_json::to_vec(&Response::Pong).unwrap();
+        write_message(&mut stream, PROTOCOL_VERSION, header.id, &body)
+            .await
+            .unwrap();
+    });
+
+    let client = Client::connect(&addr).await.unwrap();
+    let windows = client.list_windows().await;
+    assert!(
+        matches!(windows, Err(PdbError::SerializationError(_))),
+        "{:?}",
+        windows.map(|w| w.len())
+    );
+    assert!(client.ping().await.unwrap());
+}
@@ -0,0 +1,262 @@
+//! VNC backend against an in-process RFB stand-in server
+
+#![cfg(feature = "vnc")]
+
+use flate2::{Compress, Compression, FlushCompress};
+use pdb::backend::vnc::{VncBackend, VNC_HWND};
+use pdb::rfb::{self, PixelFormat};
+use pdb::{KeyCode, WindowController};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WIDTH: u16 = 64;
+const HEIGHT: u16 = 48;
+const PASSWORD: &str = "secret";
+
+const RED: [u8; 3] = [255, 0, 0];
+const GREEN: [u8; 3] = [0, 255, 0];
+const BLUE: [u8; 3] = [0, 0, 255];
+const WHITE: [u8; 3] = [255, 255, 255];
+
+/// Client messages seen by the stand-in
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Seen {
+    Pointer(u8, u16, u16),
+    Key(u32, bool),
+}
+
+/// Start a stand-in server and return its address and message log
+fn stand_in() -> (String, Arc<Mutex<Vec<Seen>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let log = log.clone();
+            thread::spawn(move || {
+                let _ = serve(stream.unwrap(), log);
+            });
+        }
+    });
+    (addr, seen)
+}
+
+fn serve(mut s: TcpStream, seen: Arc<Mutex<Vec<Seen>>>) -> pdb::Result<()> {
+    s.write_all(rfb::RFB_VERSION)?;
+    let mut version = [0u8; 12];
+    s.read_exact(&mut version)?;
+    assert_eq!(&version, rfb::RFB_VERSION);
+
+    // VNC authentication only
+    s.write_all(&[1, rfb::SECURITY_VNC_AUTH])?;
+    assert_eq!(rfb::read_u8(&mut s)?, rfb::SECURITY_VNC_AUTH);
+    let challenge = [7u8; 16];
+    s.write_all(&challenge)?;
+    let mut response = [0u8; 16];
+    s.read_exact(&mut response)?;
+    if response != rfb::vnc_auth_response(&challenge, PASSWORD) {
+        s.write_all(&1u32.to_be_bytes())?;
+        s.write_all(&14u32.to_be_bytes())?;
+        s.write_all(b"wrong password")?;
+        return Ok(());
+    }
+    s.write_all(&0u32.to_be_bytes())?;
+
+    let _shared = rfb::read_u8(&mut s)?;
+    s.write_all(&WIDTH.to_be_bytes())?;
+    s.write_all(&HEIGHT.to_be_bytes())?;
+    s.write_all(&PixelFormat::rgbx().to_bytes())?;
+    s.write_all(&8u32.to_be_bytes())?;
+    s.write_all(b"stand-in")?;
+
+    let mut zlib = Compress::new(Compression::default(), true);
+    let mut updates = 0;
+    loop {
+        match rfb::read_u8(&mut s)? {
+            rfb::CLIENT_SET_PIXEL_FORMAT => {
+                rfb::skip(&mut s, 3)?;
+                assert_eq!(PixelFormat::read_from(&mut s)?, PixelFormat::rgbx());
+            }
+            rfb::CLIENT_SET_ENCODINGS => {
+                rfb::skip(&mut s, 1)?;
+                let count = rfb::read_u16(&mut s)?;
+                rfb::skip(&mut s, count as u64 * 4)?;
+            }
+            rfb::CLIENT_FRAMEBUFFER_UPDATE_REQUEST => {
+                rfb::skip(&mut s, 9)?;
+                let update = match updates {
+                    0 => raw_update(),
+                    1 => zrle_update(&mut zlib),
+                    _ => vec![rfb::SERVER_FRAMEBUFFER_UPDATE, 0, 0, 0],
+                };
+                updates += 1;
+                s.write_all(&update)?;
+            }
+            rfb::CLIENT_KEY_EVENT => {
+                let down = rfb::read_u8(&mut s)? != 0;
+                rfb::skip(&mut s, 2)?;
+                let key = rfb::read_u32(&mut s)?;
+                seen.lock().unwrap().push(Seen::Key(key, down));
+            }
+            rfb::CLIENT_POINTER_EVENT => {
+                let buttons = rfb::read_u8(&mut s)?;
+                let x = rfb::read_u16(&mut s)?;
+                let y = rfb::read_u16(&mut s)?;
+                seen.lock().unwrap().push(Seen::Pointer(buttons, x, y));
+            }
+            other => panic!("unexpected client message {}", other),
+        }
+    }
+}
+
+fn rect_header(msg: &mut Vec<u8>, x: u16, y: u16, w: u16, h: u16, encoding: i32) {
+    for v in [x, y, w, h] {
+        msg.extend_from_slice(&v.to_be_bytes());
+    }
+    msg.extend_from_slice(&encoding.to_be_bytes());
+}
+
+/// Full red frame in Raw encoding
+fn raw_update() -> Vec<u8> {
+    let mut msg = vec![rfb::SERVER_FRAMEBUFFER_UPDATE, 0, 0, 1];
+    rect_header(&mut msg, 0, 0, WIDTH, HEIGHT, rfb::ENCODING_RAW);
+    for _ in 0..WIDTH as usize * HEIGHT as usize {
+        msg.extend_from_slice(&RED);
+        msg.push(0);
+    }
+    msg
+}
+
+fn zrle_rect(msg: &mut Vec<u8>, zlib: &mut Compress, tile: &[u8]) {
+    let mut compressed = Vec::with_capacity(tile.len() + 64);
+    zlib.compress_vec(tile, &mut compressed, FlushCompress::Sync)
+        .unwrap();
+    msg.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    msg.extend_from_slice(&compressed);
+}
+
+/// ZRLE packed-palette and RLE tiles followed by a CopyRect
+fn zrle_update(zlib: &mut Compress) -> Vec<u8> {
+    let mut msg = vec![rfb::SERVER_FRAMEBUFFER_UPDATE, 0, 0, 3];
+
+    // 16x16 two-colour palette tile: alternating green/blue columns
+    rect_header(&mut msg, 0, 0, 16, 16, rfb::ENCODING_ZRLE);
+    let mut tile = vec![2];
+    tile.extend_from_slice(&GREEN);
+    tile.extend_from_slice(&BLUE);
+    for _ in 0..16 {
+        tile.extend_from_slice(&[0b0101_0101, 0b0101_0101]);
+    }
+    zrle_rect(&mut msg, zlib, &tile);
+
+    // 16x16 plain RLE tile: 100 white pixels, then 156 blue
+    rect_header(&mut msg, 48, 32, 16, 16, rfb::ENCODING_ZRLE);
+    let mut tile = vec![128];
+    tile.extend_from_slice(&WHITE);
+    tile.push(99);
+    tile.extend_from_slice(&BLUE);
+    tile.push(155);
+    zrle_rect(&mut msg, zlib, &tile);
+
+    // Copy the palette tile to the right
+    rect_header(&mut msg, 32, 0, 16, 16, rfb::ENCODING_COPY_RECT);
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg.extend_from_slice(&0u16.to_be_bytes());
+    msg
+}
+
+fn pixel(shot: &pdb::Screenshot, x: u32, y: u32) -> [u8; 3] {
+    let i = ((y * shot.width + x) * 4) as usize;
+    [shot.data[i], shot.data[i + 1], shot.data[i + 2]]
+}
+
+#[test]
+fn applies_raw_zrle_and_copyrect_updates() {
+    let (addr, _) = stand_in();
+    let backend = VncBackend::connect(&addr, Some(PASSWORD)).unwrap();
+    assert_eq!(backend.desktop_name(), "stand-in");
+
+    let controller = WindowController::with_backend(Arc::new(backend));
+    let info = controller.find_window("stand-in").unwrap();
+    assert_eq!(info.hwnd, VNC_HWND);
+    let device = controller.device(info);
+    assert_eq!(device.get_size().unwrap(), (64, 48));
+
+    let first = device.screenshot().unwrap();
+    assert_eq!((first.width, first.height), (64, 48));
+    assert_eq!(pixel(&first, 10, 10), RED);
+    assert_eq!(first.data[3], 255);
+
+    let second = device.screenshot().unwrap();
+    assert_eq!(pixel(&second, 0, 0), GREEN);
+    assert_eq!(pixel(&second, 1, 0), BLUE);
+    assert_eq!(pixel(&second, 33, 5), BLUE);
+    assert_eq!(pixel(&second, 20, 20), RED);
+    assert_eq!(pixel(&second, 48, 32), WHITE);
+    assert_eq!(pixel(&second, 63, 47), BLUE);
+
+    // No changes: the current framebuffer is returned
+    let third = device.screenshot().unwrap();
+    assert_eq!(third.data, second.data);
+}
+
+#[test]
+fn sends_pointer_and_key_events() {
+    let (addr, seen) = stand_in();
+    let backend = VncBackend::connect(&addr, Some(PASSWORD)).unwrap();
+    let controller = WindowController::with_backend(Arc::new(backend));
+    let device = controller.device(controller.get_window_by_hwnd(VNC_HWND).unwrap());
+
+    device.click(10, 20).unwrap();
+    device.key_event(KeyCode::Enter).unwrap();
+    device.input_text("Hi").unwrap();
+    device.swipe(0, 0, 30, 40, 0).unwrap();
+    assert_eq!(device.get_cursor_pos().unwrap(), (30, 40));
+
+    // Round-trip a screenshot so the server has processed all input
+    device.screenshot().unwrap();
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(
+        &seen[..9],
+        &[
+            Seen::Pointer(0, 10, 20),
+            Seen::Pointer(1, 10, 20),
+            Seen::Pointer(0, 10, 20),
+            Seen::Key(0xFF0D, true),
+            Seen::Key(0xFF0D, false),
+            Seen::Key('H' as u32, true),
+            Seen::Key('H' as u32, false),
+            Seen::Key('i' as u32, true),
+            Seen::Key('i' as u32, false),
+        ]
+    );
+    assert_eq!(seen[9], Seen::Pointer(0, 0, 0));
+    assert_eq!(seen[10], Seen::Pointer(1, 0, 0));
+    assert_eq!(seen.last(), Some(&Seen::Pointer(0, 30, 40)));
+}
+
+#[test]
+fn rejects_wrong_password() {
+    let (addr, _) = stand_in();
+    let err = VncBackend::connect(&addr, Some("nope")).unwrap_err();
+    assert!(err.to_string().contains("wrong password"), "{}", err);
+
+    assert!(VncBackend::connect(&addr, None).is_err());
+}
+
+#[test]
+fn silent_server_times_out() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+
+    // Accepts but never sends the version greeting
+    let started = Instant::now();
+    let err =
+        VncBackend::connect_with_timeout(&addr, None, Duration::from_millis(200)).unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(5), "{}", err);
+    drop(listener);
+}
@@ -0,0 +1,210 @@
+//! WebSocket endpoint for browser clients
+
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use common::{listen, notepad, HWND};
+use futures_util::SinkExt;
+use pdb::auth;
+use pdb::backend::mock::{MockBackend, MockEvent};
+use pdb::protocol::Encoding;
+use pdb::{Command, Response, ScreenshotFormat, Server};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+type Socket = WebSocketStream<TcpStream>;
+
+async fn start_server(configure: impl FnOnce(Server) -> Server) -> (String, Arc<MockBackend>) {
+    let backend = notepad();
+    let (listener, addr) = listen().await;
+    let server = configure(Server::with_backend("unused", backend.clone()));
+    tokio::spawn(async move { server.serve_websocket(listener).await });
+    (addr, backend)
+}
+
+async fn open(addr: &str, origin: Option<&str>) -> Result<Socket, String> {
+    let mut request = format!("ws://{}/", addr).into_client_request().unwrap();
+    if let Some(origin) = origin {
+        request
+            .headers_mut()
+            .insert("Origin", origin.parse().unwrap());
+    }
+    let stream = TcpStream::connect(addr).await.unwrap();
+    match tokio_tungstenite::client_async(request, stream).await {
+        Ok((socket, _)) => Ok(socket),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Send a command as a JSON text message and read the reply
+async fn request(socket: &mut Socket, command: &Command) -> Response {
+    let text = serde_json::to_string(command).unwrap();
+    socket.send(Message::text(text)).await.unwrap();
+    match socket.next().await.unwrap().unwrap() {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn json_text_messages() {
+    let (addr, backend) =
+        start_server(|server| server.with_websocket_origin("http://localhost:8080")).await;
+    let mut socket = open(&addr, Some("http://localhost:8080")).await.unwrap();
+
+    // Exactly what a page would send
+    socket
+        .send(Message::text(r#""ListWindows""#))
+        .await
+        .unwrap();
+    let Some(Ok(Message::Text(reply))) = socket.next().await else {
+        panic!("no reply");
+    };
+    assert!(reply.contains("Notepad"), "{}", reply);
+
+    let connect = Command::ConnectByHwnd { hwnd: HWND };
+    assert!(matches!(
+        request(&mut socket, &connect).await,
+        Response::Window(_)
+    ));
+    let click = Command::Click {
+        hwnd: HWND,
+        x: 5,
+        y: 6,
+    };
+    assert!(matches!(request(&mut socket, &click).await, Response::Ok));
+    assert!(backend
+        .events()
+        .iter()
+        .any(|e| matches!(e, MockEvent::Click { x: 5, y: 6, .. })));
+
+    let screenshot = Command::Screenshot {
+        hwnd: HWND,
+        format: ScreenshotFormat::Png,
+        scale: None,
+        base: None,
+    };
+    match request(&mut socket, &screenshot).await {
+        Response::Image(image) => assert_eq!(image.decode().unwrap().width, 320),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Unknown commands are answered and the socket stays open
+    socket
+        .send(Message::text(r#"{"Teleport": {}}"#))
+        .await
+        .unwrap();
+    let Some(Ok(Message::Text(reply))) = socket.next().await else {
+        panic!("no reply");
+    };
+    assert!(reply.contains("Unknown command Teleport"), "{}", reply);
+    assert!(matches!(
+        request(&mut socket, &Command::Ping).await,
+        Response::Pong
+    ));
+}
+
+#[tokio::test]
+async fn binary_messages_are_messagepack() {
+    let (addr, _backend) = start_server(|server| server).await;
+    let mut socket = open(&addr, None).await.unwrap();
+
+    for command in [
+        Command::ConnectByHwnd { hwnd: HWND },
+        Command::Screenshot {
+            hwnd: HWND,
+            format: ScreenshotFormat::Raw,
+            scale: Some(0.5),
+            base: None,
+        },
+    ] {
+        let body = Encoding::MessagePack.encode(&command).unwrap();
+        socket.send(Message::binary(body)).await.unwrap();
+    }
+    let mut replies = Vec::new();
+    for _ in 0..2 {
+        match socket.next().await.unwrap().unwrap() {
+            Message::Binary(data) => {
+                replies.push(Encoding::MessagePack.decode::<Response>(&data).unwrap())
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert!(matches!(replies[0], Response::Window(_)));
+    match &replies[1] {
+        Response::Screenshot(shot) => assert_eq!((shot.width, shot.height), (160, 120)),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn origins_and_keys_are_enforced() {
+    let (addr, _backend) = start_server(|server| {
+        server
+            .with_websocket_origin("https://panel.example")
+            .with_key("secret")
+    })
+    .await;
+
+    let refused = open(&addr, Some("https://evil.example")).await.unwrap_err();
+    assert!(refused.contains("403"), "{}", refused);
+
+    // Nothing but the handshake before authenticating
+    let mut socket = open(&addr, Some("https://panel.example")).await.unwrap();
+    assert!(matches!(
+        request(&mut socket, &Command::ListWindows).await,
+        Response::Error(_)
+    ));
+    assert!(!matches!(
+        socket.next().await,
+        Some(Ok(Message::Text(_) | Message::Binary(_)))
+    ));
+
+    let mut socket = open(&addr, Some("https://panel.example")).await.unwrap();
+    let challenge = match request(&mut socket, &Command::Hello(Default::default())).await {
+        Response::Challenge(challenge) => challenge,
+        other => panic!("unexpected {:?}", other),
+    };
+    let proof = auth::sign(b"secret", &challenge);
+    let response = request(&mut socket, &Command::Authenticate { proof }).await;
+    assert!(matches!(response, Response::Hello(_)), "{:?}", response);
+    assert!(matches!(
+        request(&mut socket, &Command::ListWindows).await,
+        Response::Windows(_)
+    ));
+}
+
+#[tokio::test]
+async fn pages_need_an_allowed_origin() {
+    let (addr, _backend) = start_server(|server| server).await;
+
+    let refused = open(&addr, Some("https://evil.example")).await.unwrap_err();
+    assert!(refused.contains("403"), "{}", refused);
+    let mut socket = open(&addr, None).await.unwrap();
+    assert!(matches!(
+        request(&mut socket, &Command::Ping).await,
+        Response::Pong
+    ));
+}
+
+#[tokio::test]
+async fn large_messages_are_refused() {
+    let (addr, _backend) = start_server(|server| server.with_max_frame(1024)).await;
+    let mut socket = open(&addr, None).await.unwrap();
+
+    let text = serde_json::to_string(&Command::InputText {
+        hwnd: HWND,
+        text: "x".repeat(4096),
+    })
+    .unwrap();
+    socket.send(Message::text(text)).await.unwrap();
+    assert!(!matches!(
+        socket.next().await,
+        Some(Ok(Message::Text(_) | Message::Binary(_)))
+    ));
+}
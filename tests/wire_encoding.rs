@@ -0,0 +1,98 @@
+//! Wire encoding negotiation and binary screenshot transfer
+
+mod common;
+
+use common::serve;
+use pdb::backend::mock::{solid_frame, MockBackend, MockWindow};
+use pdb::protocol::Encoding;
+use pdb::{Client, ClientOptions, Command, Rect, Response, Screenshot, Server};
+use std::sync::Arc;
+
+const HWND: usize = 0x100;
+
+async fn start_server() -> (String, Arc<MockBackend>) {
+    let backend = Arc::new(MockBackend::with_windows([MockWindow::new(
+        HWND,
+        "Viewer",
+        "ViewerClass",
+        Rect::new(0, 0, 1920, 1080),
+    )]));
+    let addr = serve(backend.clone(), |server| server).await;
+    (addr, backend)
+}
+
+/// A frame with varying pixels so nothing is accidentally uniform
+fn gradient(width: u32, height: u32) -> Screenshot {
+    let mut frame = solid_frame(width, height, [0, 0, 0, 255]);
+    for (i, px) in frame.data.chunks_exact_mut(4).enumerate() {
+        px[0] = i as u8;
+        px[1] = (i >> 8) as u8;
+    }
+    frame
+}
+
+#[tokio::test]
+async fn negotiates_messagepack_by_default() {
+    let (addr, backend) = start_server().await;
+    let frame = gradient(1920, 1080);
+    backend.set_frame(HWND, frame.clone()).unwrap();
+
+    let client = Client::connect(&addr).await.unwrap();
+    assert_eq!(client.encoding(), Encoding::MessagePack);
+    assert_eq!(client.capabilities().encoding, "msgpack");
+
+    let device = client.connect_window_by_hwnd(HWND).await.unwrap();
+    let shot = device.screenshot().await.unwrap();
+    assert_eq!((shot.width, shot.height), (1920, 1080));
+    assert_eq!(shot.data, frame.data);
+}
+
+#[tokio::test]
+async fn json_can_be_forced_for_debugging() {
+    let (addr, backend) = start_server().await;
+    backend.set_frame(HWND, gradient(64, 32)).unwrap();
+
+    let options = ClientOptions::default().with_encoding(Encoding::Json);
+    let client = Client::connect_with(&addr, options).await.unwrap();
+    assert_eq!(client.encoding(), Encoding::Json);
+
+    let device = client.connect_window("Viewer").await.unwrap();
+    assert_eq!(
+        device.screenshot().await.unwrap().data,
+        gradient(64, 32).data
+    );
+}
+
+#[tokio::test]
+async fn unknown_encodings_fall_back_to_json() {
+    let server = Server::with_backend("127.0.0.1:0", Arc::new(MockBackend::new()));
+    let hello = pdb::protocol::Hello {
+        encodings: vec!["cbor".into()],
+        ..Default::default()
+    };
+    match server.handle_command(Command::Hello(hello)).await {
+        Response::Hello(caps) => {
+            assert_eq!(caps.encoding, "json");
+            assert!(caps.encodings.contains(&"msgpack".to_string()));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn pixels_travel_as_a_binary_blob() {
+    let frame = gradient(1920, 1080);
+    let response = Response::Screenshot(frame.clone());
+
+    // MessagePack: the RGBA bytes plus a few bytes of framing
+    let packed = Encoding::MessagePack.encode(&response).unwrap();
+    assert!(packed.len() < frame.data.len() + 64, "{}", packed.len());
+    match Encoding::MessagePack.decode::<Response>(&packed).unwrap() {
+        Response::Screenshot(shot) => assert_eq!(shot.data, frame.data),
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // JSON keeps the readable number array
+    let json = Encoding::Json.encode(&response).unwrap();
+    assert!(json.len() > frame.data.len() * 2);
+}
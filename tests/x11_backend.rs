@@ -0,0 +1,228 @@
+//! X11 backend against a headless Xvfb server
+//!
+//! Each test starts its own `Xvfb`, which picks a free display itself. The
+//! tests need `Xvfb` installed, so they only run when asked for:
+//! `cargo test --test x11_backend -- --ignored`.
+
+#![cfg(all(unix, feature = "x11"))]
+
+use pdb::backend::x11::X11Backend;
+use pdb::{KeyCode, PdbError, WindowController};
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    AtomEnum, ConnectionExt as _, CreateWindowAux, EventMask, PropMode, Window, WindowClass,
+};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+const TITLE: &str = "pdb-x11-test";
+
+struct Xvfb {
+    child: Child,
+    display: String,
+}
+
+impl Xvfb {
+    /// Start Xvfb with a screen of `size`, e.g. `640x480x24`
+    fn start(size: &str) -> Self {
+        // Xvfb claims a free display and writes its number to stdout once it
+        // accepts connections, so parallel tests cannot pick the same one
+        let mut child = Command::new("Xvfb")
+            .args(["-displayfd", "1", "-screen", "0", size, "-nolisten", "tcp"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("Xvfb must be installed to run the X11 tests");
+        let mut number = String::new();
+        BufReader::new(child.stdout.take().unwrap())
+            .read_line(&mut number)
+            .unwrap();
+        let number = number.trim();
+        assert!(!number.is_empty(), "Xvfb did not start");
+
+        Self {
+            child,
+            display: format!(":{}", number),
+        }
+    }
+}
+
+impl Drop for Xvfb {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Create and map a red 200x100 test window at (10, 20)
+fn create_window(conn: &RustConnection, screen_num: usize) -> Window {
+    let screen = &conn.setup().roots[screen_num];
+    let red = screen
+        .allowed_depths
+        .iter()
+        .flat_map(|depth| &depth.visuals)
+        .find(|visual| visual.visual_id == screen.root_visual)
+        .unwrap()
+        .red_mask;
+    let window = conn.generate_id().unwrap();
+    conn.create_window(
+        screen.root_depth,
+        window,
+        screen.root,
+        10,
+        20,
+        200,
+        100,
+        0,
+        WindowClass::INPUT_OUTPUT,
+        screen.root_visual,
+        &CreateWindowAux::new()
+            .background_pixel(red)
+            .event_mask(EventMask::BUTTON_PRESS | EventMask::KEY_PRESS | EventMask::EXPOSURE),
+    )
+    .unwrap();
+    conn.change_property8(
+        PropMode::REPLACE,
+        window,
+        AtomEnum::WM_NAME,
+        AtomEnum::STRING,
+        TITLE.as_bytes(),
+    )
+    .unwrap();
+    conn.change_property8(
+        PropMode::REPLACE,
+        window,
+        AtomEnum::WM_CLASS,
+        AtomEnum::STRING,
+        b"pdbtest\0PdbTest\0",
+    )
+    .unwrap();
+    conn.map_window(window).unwrap();
+    conn.sync().unwrap();
+
+    // Wait for the first expose so the background has been painted
+    loop {
+        if let Event::Expose(_) = conn.wait_for_event().unwrap() {
+            break;
+        }
+    }
+    window
+}
+
+/// Collect events of the test window until `count` match or a timeout
+fn wait_for<T>(
+    conn: &RustConnection,
+    count: usize,
+    mut filter: impl FnMut(Event) -> Option<T>,
+) -> Vec<T> {
+    let mut found = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while found.len() < count && Instant::now() < deadline {
+        match conn.poll_for_event().unwrap() {
+            Some(event) => found.extend(filter(event)),
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    found
+}
+
+fn setup() -> (Xvfb, RustConnection, Window, WindowController) {
+    setup_screen("640x480x24")
+}
+
+fn setup_screen(size: &str) -> (Xvfb, RustConnection, Window, WindowController) {
+    let xvfb = Xvfb::start(size);
+    let (conn, screen_num) = x11rb::connect(Some(&xvfb.display)).unwrap();
+    let window = create_window(&conn, screen_num);
+    let backend = X11Backend::connect(Some(&xvfb.display)).unwrap();
+    let controller = WindowController::with_backend(Arc::new(backend));
+    (xvfb, conn, window, controller)
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn lists_windows_with_title_and_class() {
+    let (_xvfb, _conn, window, controller) = setup();
+
+    let info = controller.find_window(TITLE).unwrap();
+    assert_eq!(info.hwnd, window as usize);
+    assert_eq!(info.class_name, "PdbTest");
+    assert_eq!((info.rect.left, info.rect.top), (10, 20));
+    assert_eq!((info.rect.width(), info.rect.height()), (200, 100));
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn screenshot_reads_window_pixels() {
+    let (_xvfb, _conn, _window, controller) = setup();
+
+    let device = controller.device(controller.find_window(TITLE).unwrap());
+    let shot = device.screenshot().unwrap();
+    assert_eq!((shot.width, shot.height), (200, 100));
+    assert_eq!(&shot.data[..4], &[255, 0, 0, 255]);
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn screenshot_converts_16_bit_screens() {
+    let (_xvfb, _conn, _window, controller) = setup_screen("640x480x16");
+
+    let device = controller.device(controller.find_window(TITLE).unwrap());
+    let shot = device.screenshot().unwrap();
+    assert_eq!((shot.width, shot.height), (200, 100));
+    assert_eq!(&shot.data[..4], &[255, 0, 0, 255]);
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn click_and_swipe_arrive_in_client_coordinates() {
+    let (_xvfb, conn, _window, controller) = setup();
+
+    let device = controller.device(controller.find_window(TITLE).unwrap());
+    device.click(15, 25).unwrap();
+    let presses = wait_for(&conn, 1, |event| match event {
+        Event::ButtonPress(e) => Some((e.event_x, e.event_y)),
+        _ => None,
+    });
+    assert_eq!(presses, vec![(15, 25)]);
+
+    device.swipe(5, 5, 50, 50, 100).unwrap();
+    let presses = wait_for(&conn, 1, |event| match event {
+        Event::ButtonPress(e) => Some((e.event_x, e.event_y)),
+        _ => None,
+    });
+    assert_eq!(presses, vec![(5, 5)]);
+    assert_eq!(device.get_cursor_pos().unwrap(), (50, 50));
+
+    // Coordinates beyond the protocol's 16 bits are refused, not wrapped
+    assert!(matches!(
+        device.click(40_000, 5),
+        Err(PdbError::InputError(_))
+    ));
+}
+
+#[test]
+#[ignore = "needs Xvfb"]
+fn keys_and_text_reach_focused_window() {
+    let (_xvfb, conn, _window, controller) = setup();
+
+    let device = controller.device(controller.find_window(TITLE).unwrap());
+    device.key_event(KeyCode::Enter).unwrap();
+    assert_eq!(wait_for(&conn, 1, key_press).len(), 1);
+
+    // Includes a shifted letter and a character missing from the keymap
+    device.input_text("aB€").unwrap();
+    assert!(wait_for(&conn, 3, key_press).len() >= 3);
+}
+
+fn key_press(event: Event) -> Option<u8> {
+    match event {
+        Event::KeyPress(e) => Some(e.detail),
+        _ => None,
+    }
+}